@@ -1,7 +1,9 @@
 use std::time::Duration;
 
 use async_trait::async_trait;
-use quartermaster::{manager::TaskManager, store::memory::InMemoryTaskStore, task::Task};
+use quartermaster::{
+    context::TaskContext, manager::TaskManager, store::memory::InMemoryTaskStore, task::Task,
+};
 use tokio::time::sleep;
 
 // A simple task printing hello after a delay
@@ -26,7 +28,7 @@ impl Task for DelayedHelloTask {
     }
 
     // Task code
-    async fn run(&self) {
+    async fn run(&self, _ctx: &TaskContext) {
         sleep(Duration::from_millis(self.delay_millis)).await;
         println!("Hello {} !", self.name);
     }
@@ -55,8 +57,8 @@ async fn main() {
         .iter()
         .for_each(|s| println!("name = [{}], id = [{}], creation time = [{}], status = [{}]", s.task_name, s.task_id, s.creation_time, s.status));
     // Output
-    //name = [delayed_hello], id = [Homer], creation time = [1668816441], status = [Pending]
-    //name = [delayed_hello], id = [Bart], creation time = [1668816441], status = [Pending]
+    //name = [delayed_hello], id = [Homer], creation time = [1668816441000], status = [Pending]
+    //name = [delayed_hello], id = [Bart], creation time = [1668816441000], status = [Pending]
 
 
     // Stop the task manager
@@ -5,7 +5,7 @@ use std::time::Duration;
 use async_trait::async_trait;
 #[cfg(feature = "mongodb")]
 use quartermaster::store::mongodb::MongoDBTaskStore;
-use quartermaster::{manager::TaskManager, task::Task};
+use quartermaster::{context::TaskContext, manager::TaskManager, task::Task};
 use std::sync::Arc;
 use tokio::time::sleep;
 
@@ -34,7 +34,7 @@ impl Task for DelayedHelloTask {
     }
 
     // Task code
-    async fn run(&self) {
+    async fn run(&self, _ctx: &TaskContext) {
         sleep(Duration::from_millis(self.delay_millis)).await;
         println!("Hello {} !", self.name);
     }
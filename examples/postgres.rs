@@ -0,0 +1,88 @@
+#![cfg_attr(not(feature = "postgres"), allow(unused_imports, dead_code))]
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+#[cfg(feature = "postgres")]
+use quartermaster::store::postgres::PostgresTaskStore;
+use quartermaster::{context::TaskContext, manager::TaskManager, task::Task};
+use std::sync::Arc;
+use tokio::time::sleep;
+
+#[cfg(feature = "postgres")]
+use tokio_postgres::NoTls;
+
+// A simple task printing hello after a delay
+// name + id make a task unique
+// A task manager will refuse to run the same task while it is already running or pending
+struct DelayedHelloTask {
+    name: String,
+    delay_millis: u64,
+}
+
+#[async_trait]
+impl Task for DelayedHelloTask {
+    // Define the task name
+    fn name(&self) -> String {
+        "delayed_hello".to_string()
+    }
+
+    // Define the task id (here, the person to greet,
+    // but could be the id of the data the task is processing)
+    fn id(&self) -> String {
+        self.name.clone()
+    }
+
+    // Task code
+    async fn run(&self, _ctx: &TaskContext) {
+        sleep(Duration::from_millis(self.delay_millis)).await;
+        println!("Hello {} !", self.name);
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[tokio::main]
+async fn main() {
+    // Create database connection
+    let (client, connection) = tokio_postgres::connect("host=localhost user=postgres", NoTls)
+        .await
+        .unwrap();
+    tokio::spawn(connection);
+    let client = Arc::new(client);
+
+    // Create task manager
+    // Instance name should be unique to your server instance
+    let tm = TaskManager::new(PostgresTaskStore::new("manager", "instance", client.clone()), 2);
+
+    // Run tasks on the manager
+    tm.run(Box::new(DelayedHelloTask {
+        name: "Bart".to_string(),
+        delay_millis: 5000,
+    }))
+    .await;
+    tm.run(Box::new(DelayedHelloTask {
+        name: "Homer".to_string(),
+        delay_millis: 1000,
+    }))
+    .await;
+
+    // Stop the task manager
+    tm.stop().await;
+
+    // Start the manager and block until stopped
+    // (required in this case, otherwise program will exit before tasks are run)
+    // Use tm.start().await to start without blocking.
+    tm.start_blocking().await;
+
+    // Result output:
+    // Hello Homer !
+    // Hello Bart !
+}
+
+#[cfg(not(feature = "postgres"))]
+fn main() {
+    println!(
+        r#"Please enable feature "postgres", try:
+    cargo run --features="postgres" --example postgres"#
+    );
+}
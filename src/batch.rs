@@ -0,0 +1,172 @@
+/*!
+Task batches.
+
+A batch is a group of tasks submitted at once with
+[`TaskManager::run_batch`](crate::manager::TaskManager::run_batch)
+(or [`TaskManager::run_batch_with_id`](crate::manager::TaskManager::run_batch_with_id)),
+whose completion is tracked as a whole (fan-out / fan-in).
+Each member state records the batch id.
+
+Completion is followed on the submitting instance only: while the queue is shared,
+members which would be left to the shared queue (with a payload) are rejected.
+*/
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::sync::{watch, Mutex};
+
+use crate::{
+    task::{Task, TaskOutcome, TaskRef},
+    watch::Watcher,
+};
+
+/// Number of batch members, by status.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchCounts {
+    /// Queued, not started yet.
+    pub pending: usize,
+    pub running: usize,
+    /// Completed successfully.
+    pub done: usize,
+    /// Failed, cancelled or rejected by the task manager.
+    pub failed: usize,
+}
+
+impl BatchCounts {
+    /// Return the number of members.
+    pub fn total(&self) -> usize {
+        self.pending + self.running + self.done + self.failed
+    }
+
+    /// Return true once no member is pending or running.
+    pub fn is_settled(&self) -> bool {
+        self.pending == 0 && self.running == 0
+    }
+}
+
+/// Status of a batch member.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MemberStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+struct Member {
+    task: TaskRef,
+    status: MemberStatus,
+}
+
+/// Running batch, tracking the runs of its members.
+pub(crate) struct BatchRun {
+    id: String,
+    members: Mutex<Vec<Member>>,
+    counts: watch::Sender<BatchCounts>,
+}
+
+impl BatchRun {
+    pub fn new(id: &str, tasks: &[Box<dyn Task + Send + Sync>]) -> Arc<Self> {
+        let members = tasks
+            .iter()
+            .map(|task| Member {
+                task: TaskRef::of(task.as_ref()),
+                status: MemberStatus::Pending,
+            })
+            .collect::<Vec<_>>();
+        let counts = BatchCounts {
+            pending: members.len(),
+            ..Default::default()
+        };
+        Arc::new(Self {
+            id: id.to_string(),
+            members: Mutex::new(members),
+            counts: watch::Sender::new(counts),
+        })
+    }
+
+    /// Mark a member, by index, as rejected on submission.
+    pub async fn rejected(&self, index: usize) {
+        let mut members = self.members.lock().await;
+        if let Some(member) = members.get_mut(index) {
+            member.status = MemberStatus::Failed;
+        }
+        self.update_counts(&members);
+    }
+
+    /// Move the first member of the task in one of the given statuses to a new status.
+    async fn transition(&self, task: &TaskRef, from: &[MemberStatus], to: MemberStatus) {
+        let mut members = self.members.lock().await;
+        if let Some(member) = members.iter_mut().find(|m| {
+            m.task.name == task.name && m.task.id == task.id && from.contains(&m.status)
+        }) {
+            member.status = to;
+        }
+        self.update_counts(&members);
+    }
+
+    fn update_counts(&self, members: &[Member]) {
+        let mut counts = BatchCounts::default();
+        for member in members.iter() {
+            match member.status {
+                MemberStatus::Pending => counts.pending += 1,
+                MemberStatus::Running => counts.running += 1,
+                MemberStatus::Done => counts.done += 1,
+                MemberStatus::Failed => counts.failed += 1,
+            }
+        }
+        self.counts.send_replace(counts);
+    }
+}
+
+#[async_trait]
+impl Watcher for BatchRun {
+    async fn started(&self, task: &TaskRef) {
+        self.transition(task, &[MemberStatus::Pending], MemberStatus::Running)
+            .await;
+    }
+
+    async fn finished(&self, task: &TaskRef, outcome: &TaskOutcome) {
+        let status = if outcome.is_success() {
+            MemberStatus::Done
+        } else {
+            MemberStatus::Failed
+        };
+        self.transition(task, &[MemberStatus::Pending, MemberStatus::Running], status)
+            .await;
+    }
+}
+
+/// Handle to a running batch.
+#[derive(Clone)]
+pub struct BatchHandle {
+    run: Arc<BatchRun>,
+}
+
+impl BatchHandle {
+    pub(crate) fn new(run: Arc<BatchRun>) -> Self {
+        Self { run }
+    }
+
+    /// Return the batch id.
+    pub fn id(&self) -> &str {
+        &self.run.id
+    }
+
+    /// Return the number of members by status.
+    pub fn counts(&self) -> BatchCounts {
+        *self.run.counts.borrow()
+    }
+
+    /// Wait until no member is pending or running, and return the final counts.
+    /// The task manager must be started, or stepped, meanwhile.
+    pub async fn wait(&self) -> BatchCounts {
+        let mut counts = self.run.counts.subscribe();
+        let result = match counts.wait_for(BatchCounts::is_settled).await {
+            Ok(c) => *c,
+            Err(_) => self.counts(),
+        };
+        result
+    }
+}
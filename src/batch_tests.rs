@@ -0,0 +1,180 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+
+use crate::{
+    batch::{BatchCounts, BatchHandle},
+    context::TaskContext,
+    manager::TaskManager,
+    registry::TaskRegistry,
+    store::memory::InMemoryTaskStore,
+    task::Task,
+    testing::TestTaskManager,
+};
+
+/// Batch member, recording the batch counts while running.
+struct MemberTask {
+    pub id: String,
+    pub fail: bool,
+    pub handle: Arc<Mutex<Option<BatchHandle>>>,
+    pub counts: Arc<Mutex<Vec<BatchCounts>>>,
+}
+
+#[async_trait]
+impl Task for MemberTask {
+    fn name(&self) -> String {
+        "member_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn run(&self, ctx: &TaskContext) {
+        if let Some(handle) = self.handle.lock().unwrap().as_ref() {
+            self.counts.lock().unwrap().push(handle.counts());
+        }
+        if self.fail {
+            ctx.fail("boom");
+        }
+    }
+}
+
+fn members(
+    ids: &[(&str, bool)],
+    handle: &Arc<Mutex<Option<BatchHandle>>>,
+    counts: &Arc<Mutex<Vec<BatchCounts>>>,
+) -> Vec<Box<dyn Task + Send + Sync>> {
+    ids.iter()
+        .map(|(id, fail)| {
+            Box::new(MemberTask {
+                id: id.to_string(),
+                fail: *fail,
+                handle: handle.clone(),
+                counts: counts.clone(),
+            }) as Box<dyn Task + Send + Sync>
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn track_batch_members() {
+    let tm = TestTaskManager::new("manager");
+    let handle = Arc::new(Mutex::new(None));
+    let counts = Arc::new(Mutex::new(vec![]));
+    let batch = tm
+        .run_batch_with_id(
+            "nightly",
+            members(&[("1", false), ("2", true), ("3", false), ("3", false)], &handle, &counts),
+        )
+        .await;
+    *handle.lock().unwrap() = Some(batch.clone());
+
+    // Duplicate member is rejected
+    assert_eq!(batch.id(), "nightly");
+    assert_eq!(
+        batch.counts(),
+        BatchCounts {
+            pending: 3,
+            running: 0,
+            done: 0,
+            failed: 1,
+        }
+    );
+    let states = tm.batch_states("nightly").await;
+    assert_eq!(states.len(), 3);
+    assert!(states.iter().all(|s| s.batch_id.as_deref() == Some("nightly")));
+
+    assert_eq!(tm.run_until_idle().await, 3);
+    assert_eq!(
+        *counts.lock().unwrap(),
+        vec![
+            BatchCounts {
+                pending: 2,
+                running: 1,
+                done: 0,
+                failed: 1,
+            },
+            BatchCounts {
+                pending: 1,
+                running: 1,
+                done: 1,
+                failed: 1,
+            },
+            BatchCounts {
+                pending: 0,
+                running: 1,
+                done: 1,
+                failed: 2,
+            },
+        ]
+    );
+    let counts = batch.wait().await;
+    assert_eq!(counts.total(), 4);
+    assert_eq!(counts.done, 2);
+    assert_eq!(counts.failed, 2);
+    assert!(tm.batch_states("nightly").await.is_empty());
+}
+
+#[tokio::test]
+async fn wait_for_batch_on_started_manager() {
+    let manager = TaskManager::new(InMemoryTaskStore::new("manager"), 3);
+    manager.start().await;
+
+    let handle = Arc::new(Mutex::new(None));
+    let counts = Arc::new(Mutex::new(vec![]));
+    let ids: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+    let ids: Vec<(&str, bool)> = ids.iter().map(|id| (id.as_str(), false)).collect();
+    let batch = manager.run_batch(members(&ids, &handle, &counts)).await;
+
+    assert!(!batch.id().is_empty());
+    let counts = batch.wait().await;
+    assert!(counts.is_settled());
+    assert_eq!(counts.done, 10);
+    manager.stop().await;
+}
+
+/// Batch member with a payload, which would be left to a shared queue.
+struct SharedMemberTask {
+    pub id: String,
+}
+
+#[async_trait]
+impl Task for SharedMemberTask {
+    fn name(&self) -> String {
+        "shared_member_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn run(&self, _ctx: &TaskContext) {
+        // Nothing
+    }
+
+    fn payload(&self) -> Option<String> {
+        Some(self.id.clone())
+    }
+}
+
+#[tokio::test]
+async fn reject_shared_members() {
+    let manager = TaskManager::new(InMemoryTaskStore::new("manager"), 1)
+        .with_shared_queue(TaskRegistry::new(), Duration::from_millis(5));
+    let handle = Arc::new(Mutex::new(None));
+    let counts = Arc::new(Mutex::new(vec![]));
+    let mut tasks = members(&[("1", false)], &handle, &counts);
+    tasks.push(Box::new(SharedMemberTask { id: "2".to_string() }));
+    let batch = manager.run_batch_with_id("shared", tasks).await;
+
+    // Only the member without payload was queued, locally
+    assert_eq!(manager.batch_states("shared").await.len(), 1);
+    assert!(manager.step().await);
+    let counts = batch.wait().await;
+    assert_eq!(counts.done, 1);
+    assert_eq!(counts.failed, 1);
+}
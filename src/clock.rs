@@ -0,0 +1,18 @@
+use std::time::SystemTime;
+
+/// Source of the current time.
+/// Stores stamp task states using their clock, so that time can be faked in tests.
+pub trait Clock: Send + Sync {
+    /// Return the current time.
+    fn now(&self) -> SystemTime;
+}
+
+/// System clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
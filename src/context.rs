@@ -0,0 +1,277 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    clock::Clock,
+    lock::{LockGuard, Locks},
+    manager::RunResult,
+    store::{state::StateUpdate, TaskStore, TaskStoreError},
+    task::{Task, TaskOutcome, TaskRef},
+    util::to_millis,
+};
+
+/// Writes the state updates of a single task.
+#[async_trait]
+pub(crate) trait StateWriter: Send + Sync {
+    async fn write(&self, update: StateUpdate);
+}
+
+/// State writer of a task, backed by a task store.
+pub(crate) struct StoreStateWriter<S>
+where
+    S: TaskStore,
+{
+    pub store: Arc<S>,
+    pub task: TaskRef,
+}
+
+#[async_trait]
+impl<S: TaskStore> StateWriter for StoreStateWriter<S> {
+    async fn write(&self, update: StateUpdate) {
+        if let Some(err) = self.store.update_state(&self.task, update).await.err() {
+            log::error!(
+                "failed to update task `{}` with id `{}` state: {}",
+                self.task.name,
+                self.task.id,
+                err.to_string()
+            );
+        }
+    }
+}
+
+/// Progress reporting throttle state.
+struct Throttle {
+    /// Time of the last written report.
+    last_write: Option<SystemTime>,
+    /// Latest report not written yet.
+    pending: Option<(u8, String)>,
+}
+
+/// Task progress reporter.
+/// Reports are written to the task state, at most once per interval:
+/// reports made in between are not written, except for the latest one,
+/// which is written on next report after the interval, or when the task completes.
+#[derive(Clone)]
+pub struct ProgressReporter {
+    writer: Arc<dyn StateWriter>,
+    clock: Arc<dyn Clock>,
+    interval: Duration,
+    throttle: Arc<Mutex<Throttle>>,
+}
+
+impl ProgressReporter {
+    pub(crate) fn new(writer: Arc<dyn StateWriter>, clock: Arc<dyn Clock>, interval: Duration) -> Self {
+        Self {
+            writer,
+            clock,
+            interval,
+            throttle: Arc::new(Mutex::new(Throttle {
+                last_write: None,
+                pending: None,
+            })),
+        }
+    }
+
+    /// Report task progress, as a percentage (capped to 100) and a message.
+    pub async fn report(&self, percent: u8, message: &str) {
+        let now = self.clock.now();
+        let report = (percent.min(100), message.to_string());
+        {
+            let mut throttle = self.throttle.lock().unwrap();
+            let throttled = throttle
+                .last_write
+                .and_then(|last| now.duration_since(last).ok())
+                .map(|elapsed| elapsed < self.interval)
+                .unwrap_or(false);
+            if throttled {
+                throttle.pending = Some(report);
+                return;
+            }
+            throttle.last_write = Some(now);
+            throttle.pending = None;
+        }
+        self.writer.write(Self::update(report, to_millis(now))).await;
+    }
+
+    /// Take the latest report not written yet (if any), as an update to write with the given time.
+    pub(crate) fn take_pending(&self, time: u64) -> Option<StateUpdate> {
+        let pending = self.throttle.lock().unwrap().pending.take();
+        pending.map(|report| Self::update(report, time))
+    }
+
+    fn update((percent, message): (u8, String), time: u64) -> StateUpdate {
+        StateUpdate {
+            progress: Some(percent),
+            progress_message: Some(message),
+            time,
+            ..Default::default()
+        }
+    }
+}
+
+/// Lineage of a follow-up task: the task which submitted it, and the first task of the pipeline.
+#[derive(Debug, Clone)]
+pub(crate) struct Lineage {
+    pub parent: TaskRef,
+    pub root: TaskRef,
+}
+
+impl Lineage {
+    /// Lineage of the tasks submitted by the given task, itself part of the given pipeline (if any).
+    pub fn of(task: &dyn Task, root: Option<TaskRef>) -> Self {
+        Self {
+            parent: TaskRef::of(task),
+            root: root.unwrap_or_else(|| TaskRef::of(task)),
+        }
+    }
+}
+
+/// Options of a task submission.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Submission {
+    /// Lineage of a follow-up task.
+    pub lineage: Option<Lineage>,
+    /// Batch the task belongs to.
+    pub batch: Option<String>,
+}
+
+impl Submission {
+    /// Initial state fields of the submitted task.
+    pub fn initial_state(&self) -> StateUpdate {
+        let mut initial = StateUpdate {
+            batch_id: self.batch.clone(),
+            ..Default::default()
+        };
+        if let Some(lineage) = &self.lineage {
+            initial.parent_name = Some(lineage.parent.name.clone());
+            initial.parent_id = Some(lineage.parent.id.clone());
+            initial.root_name = Some(lineage.root.name.clone());
+            initial.root_id = Some(lineage.root.id.clone());
+        }
+        initial
+    }
+}
+
+/// Submits tasks to a task manager.
+#[async_trait]
+pub(crate) trait Submit: Send + Sync {
+    async fn submit(&self, task: Box<dyn Task + Send + Sync>, submission: Submission) -> RunResult;
+}
+
+/// Handle to a task manager, to submit tasks to it.
+/// Tasks submitted through the handle of a running task are recorded as its follow-up tasks.
+#[derive(Clone)]
+pub struct ManagerHandle {
+    submitter: Arc<dyn Submit>,
+    lineage: Option<Lineage>,
+}
+
+impl ManagerHandle {
+    pub(crate) fn new(submitter: Arc<dyn Submit>, lineage: Option<Lineage>) -> Self {
+        Self { submitter, lineage }
+    }
+
+    /// Run a task on the task manager, see [`TaskManager::run`](crate::manager::TaskManager::run).
+    pub async fn run(&self, task: Box<dyn Task + Send + Sync>) -> RunResult {
+        let submission = Submission {
+            lineage: self.lineage.clone(),
+            ..Default::default()
+        };
+        self.submitter.submit(task, submission).await
+    }
+}
+
+/// Task execution context, given to a task when run.
+pub struct TaskContext {
+    pub(crate) manager: String,
+    pub(crate) worker: usize,
+    pub(crate) attempt: u32,
+    pub(crate) cancellation: CancellationToken,
+    pub(crate) progress: ProgressReporter,
+    pub(crate) handle: ManagerHandle,
+    pub(crate) failure: Mutex<Option<String>>,
+    pub(crate) locks: Locks,
+}
+
+impl TaskContext {
+    /// Return the name of the task manager running the task.
+    pub fn manager_name(&self) -> &str {
+        &self.manager
+    }
+
+    /// Return the index of the worker running the task.
+    pub fn worker(&self) -> usize {
+        self.worker
+    }
+
+    /// Return the run attempt number, starting from 1.
+    /// A task runs again when recovered after a restart, reclaimed once its lease expired, or reassigned from a dead instance.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Return the task cancellation token, cancelled by [`TaskManager::cancel`](crate::manager::TaskManager::cancel).
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    /// Return true if the task was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    /// Return the task progress reporter.
+    pub fn progress(&self) -> &ProgressReporter {
+        &self.progress
+    }
+
+    /// Report task progress, as a percentage (capped to 100) and a message.
+    /// Progress is recorded in the task state, see [`ProgressReporter`].
+    pub async fn report_progress(&self, percent: u8, message: &str) {
+        self.progress.report(percent, message).await;
+    }
+
+    /// Return a handle to the task manager running the task.
+    pub fn handle(&self) -> &ManagerHandle {
+        &self.handle
+    }
+
+    /// Report the task run as failed, with a message.
+    /// The task is still expected to return; tasks depending on it are not run.
+    pub fn fail(&self, message: &str) {
+        *self.failure.lock().unwrap() = Some(message.to_string());
+    }
+
+    /// Outcome of the task run, once returned.
+    pub(crate) fn outcome(&self) -> TaskOutcome {
+        match self.failure.lock().unwrap().clone() {
+            Some(message) => TaskOutcome::Failed(message),
+            None if self.is_cancelled() => TaskOutcome::Cancelled,
+            None => TaskOutcome::Succeeded,
+        }
+    }
+
+    /// Take the named lock of the task manager, shared by all its instances, waiting for it to be released.
+    /// The lock is held until the guard is released or dropped, or until the time to live elapsed.
+    /// Return None if the task was cancelled while waiting.
+    /// Fails if the store does not support locks (see [`TaskStore::try_lock`]).
+    pub async fn lock(&self, name: &str, ttl: Duration) -> Result<Option<LockGuard>, TaskStoreError> {
+        self.locks.lock(name, ttl, &self.cancellation).await
+    }
+
+    /// Take the named lock of the task manager, unless held by someone else, see [`TaskContext::lock`].
+    pub async fn try_lock(&self, name: &str, ttl: Duration) -> Result<Option<LockGuard>, TaskStoreError> {
+        self.locks.try_lock(name, ttl).await
+    }
+
+    /// Run a follow-up task on the task manager running the task.
+    /// The follow-up task state records the task as its parent, and the first task of the pipeline as its root.
+    pub async fn submit(&self, task: Box<dyn Task + Send + Sync>) -> RunResult {
+        self.handle.run(task).await
+    }
+}
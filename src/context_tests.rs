@@ -0,0 +1,80 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+
+use crate::{
+    context::{ProgressReporter, StateWriter},
+    store::state::StateUpdate,
+    testing::VirtualClock,
+};
+
+/// State writer keeping the written updates.
+#[derive(Default)]
+struct CollectingWriter {
+    updates: Mutex<Vec<StateUpdate>>,
+}
+
+#[async_trait]
+impl StateWriter for CollectingWriter {
+    async fn write(&self, update: StateUpdate) {
+        self.updates.lock().unwrap().push(update);
+    }
+}
+
+fn progress(writer: &CollectingWriter) -> Vec<(Option<u8>, Option<String>)> {
+    writer
+        .updates
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|u| (u.progress, u.progress_message.clone()))
+        .collect()
+}
+
+#[tokio::test]
+async fn throttle_progress() {
+    let writer = Arc::new(CollectingWriter::default());
+    let clock = VirtualClock::new();
+    let reporter = ProgressReporter::new(
+        writer.clone(),
+        Arc::new(clock.clone()),
+        Duration::from_secs(1),
+    );
+
+    reporter.report(10, "first").await;
+    clock.advance(Duration::from_millis(100));
+    reporter.report(20, "throttled").await;
+    reporter.report(30, "pending").await;
+    assert_eq!(progress(&writer), vec![(Some(10), Some("first".to_string()))]);
+
+    clock.advance(Duration::from_secs(1));
+    reporter.report(150, "capped").await;
+    assert_eq!(writer.updates.lock().unwrap().len(), 2);
+    assert_eq!(
+        progress(&writer)[1],
+        (Some(100), Some("capped".to_string()))
+    );
+    assert!(reporter.take_pending(0).is_none());
+}
+
+#[tokio::test]
+async fn take_pending_progress() {
+    let writer = Arc::new(CollectingWriter::default());
+    let clock = VirtualClock::new();
+    let reporter = ProgressReporter::new(
+        writer.clone(),
+        Arc::new(clock.clone()),
+        Duration::from_secs(1),
+    );
+
+    reporter.report(10, "first").await;
+    reporter.report(90, "last").await;
+    let pending = reporter.take_pending(42).unwrap();
+    assert_eq!(pending.progress, Some(90));
+    assert_eq!(pending.progress_message.as_deref(), Some("last"));
+    assert_eq!(pending.time, 42);
+    assert!(reporter.take_pending(42).is_none());
+}
@@ -0,0 +1,112 @@
+/*!
+Deduplication of the tasks submitted while a task with the same name and id is pending or running.
+
+Each task chooses, with [`Task::dedup_policy`](crate::task::Task::dedup_policy), what happens to such a submission:
+rejected (default), replacing the pending task, attached to the existing run, or run once more after it.
+
+```ignore
+impl Task for RefreshCacheTask {
+    // name, id and run ...
+
+    // Latest wins, and a refresh requested while one runs is run afterwards
+    fn dedup_policy(&self) -> DedupPolicy {
+        DedupPolicy::EnqueueAfterCurrent
+    }
+}
+```
+*/
+
+use std::{fmt, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::sync::watch;
+
+use crate::{
+    task::{TaskOutcome, TaskRef},
+    watch::Watcher,
+};
+
+/// What to do with a task submitted while a task with the same name and id is pending or running.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DedupPolicy {
+    /// Reject the task ([`RunResult::Duplicate`](crate::manager::RunResult::Duplicate)).
+    #[default]
+    Reject,
+    /// Replace the pending task by the new one, which keeps its place in the queue.
+    /// Rejected if the task is already running.
+    ReplacePending,
+    /// Attach the caller to the existing run ([`RunResult::Coalesced`](crate::manager::RunResult::Coalesced)).
+    /// Rejected if the task is pending or running on another instance.
+    Coalesce,
+    /// Replace the pending task, or run the new one once the running task finished.
+    /// Tasks submitted meanwhile replace each other, so the task runs once more at most.
+    EnqueueAfterCurrent,
+}
+
+/// Run of a task, completed by the task manager.
+pub(crate) struct TaskRun {
+    outcome: watch::Sender<Option<TaskOutcome>>,
+}
+
+impl TaskRun {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            outcome: watch::Sender::new(None),
+        })
+    }
+}
+
+#[async_trait]
+impl Watcher for TaskRun {
+    async fn finished(&self, _task: &TaskRef, outcome: &TaskOutcome) {
+        self.outcome.send_replace(Some(outcome.clone()));
+    }
+}
+
+/// Handle to the run of a task.
+#[derive(Clone)]
+pub struct TaskHandle {
+    task: TaskRef,
+    run: Arc<TaskRun>,
+}
+
+impl TaskHandle {
+    pub(crate) fn new(task: TaskRef, run: Arc<TaskRun>) -> Self {
+        Self { task, run }
+    }
+
+    /// Return the task name.
+    pub fn name(&self) -> &str {
+        &self.task.name
+    }
+
+    /// Return the task id.
+    pub fn id(&self) -> &str {
+        &self.task.id
+    }
+
+    /// Return the outcome of the run, if finished.
+    pub fn outcome(&self) -> Option<TaskOutcome> {
+        self.run.outcome.borrow().clone()
+    }
+
+    /// Wait until the run finished, and return its outcome.
+    /// The task manager must be started, or stepped, meanwhile.
+    pub async fn wait(&self) -> TaskOutcome {
+        let mut outcome = self.run.outcome.subscribe();
+        let result = match outcome.wait_for(Option::is_some).await {
+            Ok(o) => o.clone().unwrap_or(TaskOutcome::Cancelled),
+            Err(_) => TaskOutcome::Cancelled,
+        };
+        result
+    }
+}
+
+impl fmt::Debug for TaskHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskHandle")
+            .field("name", &self.task.name)
+            .field("id", &self.task.id)
+            .finish()
+    }
+}
@@ -0,0 +1,176 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{sync::RwLock, time::sleep};
+
+use crate::{
+    context::TaskContext,
+    dedup::DedupPolicy,
+    manager::{RunResult, TaskManager},
+    registry::TaskRegistry,
+    store::{memory::InMemoryTaskStore, state::TaskStatus, TaskStore},
+    task::{Task, TaskOutcome},
+};
+
+/// Task recording its value, deduplicated with the given policy.
+struct DedupTask {
+    pub value: String,
+    pub policy: DedupPolicy,
+    pub sleep_millis: u64,
+    pub results: Arc<RwLock<Vec<String>>>,
+}
+
+#[async_trait]
+impl Task for DedupTask {
+    fn name(&self) -> String {
+        "dedup_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        "1".to_string()
+    }
+
+    async fn run(&self, _ctx: &TaskContext) {
+        sleep(Duration::from_millis(self.sleep_millis)).await;
+        self.results.write().await.push(self.value.clone());
+    }
+
+    fn payload(&self) -> Option<String> {
+        Some(self.value.clone())
+    }
+
+    fn dedup_policy(&self) -> DedupPolicy {
+        self.policy
+    }
+}
+
+fn dedup_task(
+    value: &str,
+    policy: DedupPolicy,
+    results: &Arc<RwLock<Vec<String>>>,
+) -> Box<DedupTask> {
+    Box::new(DedupTask {
+        value: value.to_string(),
+        policy,
+        sleep_millis: 0,
+        results: results.clone(),
+    })
+}
+
+#[tokio::test]
+async fn reject_duplicate() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let manager = TaskManager::new(InMemoryTaskStore::new("manager"), 1);
+
+    let policy = DedupPolicy::Reject;
+    assert!(matches!(manager.run(dedup_task("1", policy, &results)).await, RunResult::Accepted));
+    assert!(matches!(manager.run(dedup_task("2", policy, &results)).await, RunResult::Duplicate));
+    assert!(manager.step().await);
+    assert!(!manager.step().await);
+    assert_eq!(*results.read().await, vec!["1"]);
+}
+
+#[tokio::test]
+async fn replace_pending() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let manager = TaskManager::new(InMemoryTaskStore::new("manager"), 1);
+
+    let policy = DedupPolicy::ReplacePending;
+    assert!(matches!(manager.run(dedup_task("1", policy, &results)).await, RunResult::Accepted));
+    assert!(matches!(manager.run(dedup_task("2", policy, &results)).await, RunResult::Replaced));
+    assert!(matches!(manager.run(dedup_task("3", policy, &results)).await, RunResult::Replaced));
+    assert_eq!(manager.get_state().await[0].payload.as_deref(), Some("3"));
+    assert!(manager.step().await);
+    assert!(!manager.step().await);
+    assert_eq!(*results.read().await, vec!["3"]);
+}
+
+#[tokio::test]
+async fn replace_pending_in_shared_queue() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let registry_results = results.clone();
+    let registry = TaskRegistry::new().register("dedup_task", move |payload| {
+        Ok(dedup_task(payload, DedupPolicy::ReplacePending, &registry_results) as Box<dyn Task + Send + Sync>)
+    });
+    let manager = TaskManager::new(InMemoryTaskStore::new("manager"), 1)
+        .with_shared_queue(registry, Duration::from_millis(5));
+
+    let policy = DedupPolicy::ReplacePending;
+    assert!(matches!(manager.run(dedup_task("1", policy, &results)).await, RunResult::Accepted));
+    assert!(matches!(manager.run(dedup_task("2", policy, &results)).await, RunResult::Replaced));
+    assert!(manager.step().await);
+    assert_eq!(*results.read().await, vec!["2"]);
+}
+
+#[tokio::test]
+async fn replace_claimed_in_shared_queue() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let registry_results = results.clone();
+    let registry = TaskRegistry::new().register("dedup_task", move |payload| {
+        Ok(dedup_task(payload, DedupPolicy::ReplacePending, &registry_results) as Box<dyn Task + Send + Sync>)
+    });
+    let store = InMemoryTaskStore::new("manager");
+    let manager = TaskManager::new(store.clone(), 1).with_shared_queue(registry, Duration::from_millis(5));
+
+    // Claimed by another instance before being replaced
+    let policy = DedupPolicy::ReplacePending;
+    assert!(matches!(manager.run(dedup_task("1", policy, &results)).await, RunResult::Accepted));
+    let names = vec!["dedup_task".to_string()];
+    assert!(store.claim_pending(&names, Duration::from_secs(60)).await.unwrap().is_some());
+    assert!(matches!(manager.run(dedup_task("2", policy, &results)).await, RunResult::Duplicate));
+    let state = store.get_state(&*dedup_task("2", policy, &results)).await.unwrap().unwrap();
+    assert_eq!(state.payload.as_deref(), Some("1"));
+}
+
+#[tokio::test]
+async fn coalesce() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let manager = TaskManager::new(InMemoryTaskStore::new("manager"), 1);
+
+    let policy = DedupPolicy::Coalesce;
+    assert!(matches!(manager.run(dedup_task("1", policy, &results)).await, RunResult::Accepted));
+    let RunResult::Coalesced(handle) = manager.run(dedup_task("2", policy, &results)).await else {
+        panic!("task not coalesced");
+    };
+    assert_eq!(handle.name(), "dedup_task");
+    assert_eq!(handle.outcome(), None);
+    assert!(manager.step().await);
+    assert!(!manager.step().await);
+    assert_eq!(handle.wait().await, TaskOutcome::Succeeded);
+    assert_eq!(*results.read().await, vec!["1"]);
+}
+
+#[tokio::test]
+async fn enqueue_after_current() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let manager = TaskManager::new(InMemoryTaskStore::new("manager"), 1);
+    let policy = DedupPolicy::EnqueueAfterCurrent;
+    let task = |value: &str| {
+        Box::new(DedupTask {
+            value: value.to_string(),
+            policy,
+            sleep_millis: 50,
+            results: results.clone(),
+        })
+    };
+
+    // Pending task is replaced
+    assert!(matches!(manager.run(task("1")).await, RunResult::Accepted));
+    assert!(matches!(manager.run(task("2")).await, RunResult::Replaced));
+    assert!(manager.step().await);
+    assert_eq!(*results.read().await, vec!["2"]);
+
+    // Running task is followed by the latest submission
+    assert!(matches!(manager.run(task("3")).await, RunResult::Accepted));
+    tokio::join!(manager.step(), async {
+        while manager.get_state().await[0].status != TaskStatus::Running {
+            tokio::task::yield_now().await;
+        }
+        assert!(matches!(manager.run(task("4")).await, RunResult::Deferred));
+        assert!(matches!(manager.run(task("5")).await, RunResult::Deferred));
+    });
+    assert!(manager.step().await);
+    assert!(!manager.step().await);
+    assert_eq!(*results.read().await, vec!["2", "3", "5"]);
+    assert!(manager.get_state().await.is_empty());
+}
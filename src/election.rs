@@ -0,0 +1,91 @@
+/*!
+Leader election between the instances of a task manager.
+
+A task manager given leader-only task names (see [`TaskManager::with_leader_only`](crate::manager::TaskManager::with_leader_only))
+competes, while started, for the leadership of the task manager through its store:
+the leader holds a lease, renewed every third of its duration, and only its workers run the leader-only tasks.
+Once the lease expires (e.g. the leader crashed), another instance takes the leadership over.
+*/
+
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use crate::store::TaskStore;
+
+/// Leadership of an instance, and the tasks requiring it.
+pub(crate) struct Election {
+    /// Names of the tasks only run by the leader.
+    tasks: HashSet<String>,
+    /// Lease of the leadership.
+    lease: Duration,
+    leader: AtomicBool,
+}
+
+impl Election {
+    pub fn new(tasks: HashSet<String>, lease: Duration) -> Self {
+        Self {
+            tasks,
+            lease,
+            leader: AtomicBool::new(false),
+        }
+    }
+
+    /// Return true if some tasks require the leadership.
+    pub fn is_needed(&self) -> bool {
+        !self.tasks.is_empty()
+    }
+
+    /// Return the lease of the leadership.
+    pub fn lease(&self) -> Duration {
+        self.lease
+    }
+
+    /// Return true if the instance currently holds the leadership.
+    pub fn is_leader(&self) -> bool {
+        self.leader.load(Ordering::SeqCst)
+    }
+
+    /// Return true if the tasks with the given name can run on the instance.
+    pub fn may_run(&self, name: &str) -> bool {
+        !self.tasks.contains(name) || self.is_leader()
+    }
+
+    /// Acquire or renew the leadership.
+    /// The leadership is given up when the store cannot be reached, as it may be lost meanwhile.
+    pub async fn campaign<S: TaskStore>(&self, manager: &str, store: &S) {
+        let leader = match store.acquire_leadership(self.lease).await {
+            Ok(leader) => leader,
+            Err(err) => {
+                log::error!(
+                    "task manager `{}` failed to acquire leadership: {}",
+                    manager,
+                    err.to_string()
+                );
+                false
+            }
+        };
+        if self.leader.swap(leader, Ordering::SeqCst) != leader {
+            match leader {
+                true => log::info!("task manager `{}` instance is now the leader", manager),
+                false => log::warn!("task manager `{}` instance lost the leadership", manager),
+            }
+        }
+    }
+
+    /// Give the leadership up.
+    pub async fn resign<S: TaskStore>(&self, manager: &str, store: &S) {
+        if !self.leader.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        if let Some(err) = store.resign_leadership().await.err() {
+            log::error!(
+                "task manager `{}` failed to resign leadership: {}",
+                manager,
+                err.to_string()
+            );
+        }
+    }
+}
@@ -0,0 +1,353 @@
+/*!
+Task dependency graphs.
+
+A [`TaskGraph`] declares tasks and their prerequisites.
+Once submitted with [`TaskManager::run_graph`](crate::manager::TaskManager::run_graph),
+each task is queued only after all its prerequisites succeeded,
+and tasks depending (directly or not) on a failed task are cancelled.
+
+Completion is followed on the submitting instance only: while the queue is shared,
+graphs with a task left to the shared queue (with a payload) are rejected.
+*/
+
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    sync::{Arc, Weak},
+};
+
+use async_trait::async_trait;
+use tokio::sync::{watch, Mutex};
+
+use crate::{
+    context::{Submission, Submit},
+    task::{Task, TaskOutcome, TaskRef},
+    watch::{Watcher, Watchers},
+};
+
+/// Node of a task graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+/// Graph of tasks with dependencies.
+pub struct TaskGraph {
+    tasks: Vec<Box<dyn Task + Send + Sync>>,
+    /// Dependencies, as (node, prerequisite) pairs.
+    dependencies: Vec<(NodeId, NodeId)>,
+}
+
+impl TaskGraph {
+    /// Create an empty task graph.
+    pub fn new() -> Self {
+        Self {
+            tasks: vec![],
+            dependencies: vec![],
+        }
+    }
+
+    /// Add a task to the graph.
+    pub fn add(&mut self, task: Box<dyn Task + Send + Sync>) -> NodeId {
+        self.tasks.push(task);
+        NodeId(self.tasks.len() - 1)
+    }
+
+    /// Declare that a task can only run once another one succeeded.
+    pub fn depends_on(&mut self, node: NodeId, prerequisite: NodeId) -> &mut Self {
+        if !self.dependencies.contains(&(node, prerequisite)) {
+            self.dependencies.push((node, prerequisite));
+        }
+        self
+    }
+
+    /// Return the number of tasks in the graph.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Return true if the graph has no task.
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+}
+
+/// Invalid task graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphError {
+    /// A dependency refers to a node which is not part of the graph.
+    UnknownNode(NodeId),
+    /// A same task (name and id) was added twice.
+    DuplicateTask(String, String),
+    /// Dependencies form a cycle, between (or downstream of) the given tasks.
+    Cycle(Vec<(String, String)>),
+    /// The task would be left to the shared queue, where its completion cannot be followed.
+    SharedTask(String, String),
+}
+
+impl Display for GraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphError::UnknownNode(node) => write!(f, "unknown task graph node {}", node.0),
+            GraphError::DuplicateTask(name, id) => {
+                write!(f, "task `{}` with id `{}` added twice to the graph", name, id)
+            }
+            GraphError::Cycle(tasks) => {
+                let tasks: Vec<String> = tasks
+                    .iter()
+                    .map(|(name, id)| format!("`{}` with id `{}`", name, id))
+                    .collect();
+                write!(f, "task graph has a cycle involving tasks {}", tasks.join(", "))
+            }
+            GraphError::SharedTask(name, id) => {
+                write!(f, "task `{}` with id `{}` of the graph would be left to the shared queue", name, id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+/// Status of a task of a graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeStatus {
+    /// Waiting for its prerequisites.
+    Waiting,
+    /// Submitted to the task manager, pending or running.
+    Queued,
+    /// Task run completed.
+    Succeeded,
+    /// Task failed, or was rejected by the task manager, with a message.
+    Failed(String),
+    /// Task was cancelled while running, or not run because a prerequisite did not succeed.
+    Cancelled,
+}
+
+/// Overall status of a task graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphStatus {
+    /// Some tasks are still waiting or queued.
+    Running,
+    /// All the tasks succeeded.
+    Succeeded,
+    /// All the tasks are done, some of them did not succeed.
+    Failed,
+}
+
+/// Task of a running graph.
+struct Node {
+    task: TaskRef,
+    /// Task to submit, until queued.
+    pending: Option<Box<dyn Task + Send + Sync>>,
+    /// Number of prerequisites not succeeded yet.
+    waiting_for: usize,
+    dependents: Vec<usize>,
+    status: NodeStatus,
+}
+
+/// Running task graph, submitting tasks as their prerequisites succeed.
+pub(crate) struct GraphRun {
+    me: Weak<GraphRun>,
+    submitter: Arc<dyn Submit>,
+    watchers: Arc<Watchers>,
+    nodes: Mutex<Vec<Node>>,
+    status: watch::Sender<GraphStatus>,
+}
+
+impl GraphRun {
+    /// Validate a graph, and prepare its run.
+    /// With a shared queue, tasks with a payload are rejected.
+    pub fn new(
+        graph: TaskGraph,
+        submitter: Arc<dyn Submit>,
+        watchers: Arc<Watchers>,
+        shared: bool,
+    ) -> Result<Arc<Self>, GraphError> {
+        let mut nodes: Vec<Node> = vec![];
+        let mut keys = HashSet::new();
+        for task in graph.tasks {
+            if !keys.insert((task.name(), task.id())) {
+                return Err(GraphError::DuplicateTask(task.name(), task.id()));
+            }
+            if shared && task.payload().is_some() {
+                return Err(GraphError::SharedTask(task.name(), task.id()));
+            }
+            nodes.push(Node {
+                task: TaskRef::of(task.as_ref()),
+                pending: Some(task),
+                waiting_for: 0,
+                dependents: vec![],
+                status: NodeStatus::Waiting,
+            });
+        }
+        for (node, prerequisite) in graph.dependencies {
+            for id in [node, prerequisite] {
+                if id.0 >= nodes.len() {
+                    return Err(GraphError::UnknownNode(id));
+                }
+            }
+            nodes[node.0].waiting_for += 1;
+            nodes[prerequisite.0].dependents.push(node.0);
+        }
+        Self::check_cycles(&nodes)?;
+
+        Ok(Arc::new_cyclic(|me| Self {
+            me: me.clone(),
+            submitter,
+            watchers,
+            nodes: Mutex::new(nodes),
+            status: watch::Sender::new(GraphStatus::Running),
+        }))
+    }
+
+    /// Check that every node can be reached from the nodes without prerequisites.
+    fn check_cycles(nodes: &[Node]) -> Result<(), GraphError> {
+        let mut waiting_for: Vec<usize> = nodes.iter().map(|n| n.waiting_for).collect();
+        let mut ready: Vec<usize> = (0..nodes.len()).filter(|&i| waiting_for[i] == 0).collect();
+        let mut visited = 0;
+        while let Some(i) = ready.pop() {
+            visited += 1;
+            for &dependent in &nodes[i].dependents {
+                waiting_for[dependent] -= 1;
+                if waiting_for[dependent] == 0 {
+                    ready.push(dependent);
+                }
+            }
+        }
+        if visited == nodes.len() {
+            return Ok(());
+        }
+        Err(GraphError::Cycle(
+            (0..nodes.len())
+                .filter(|&i| waiting_for[i] > 0)
+                .map(|i| (nodes[i].task.name.clone(), nodes[i].task.id.clone()))
+                .collect(),
+        ))
+    }
+
+    /// Submit the tasks whose prerequisites all succeeded.
+    pub async fn submit_ready(&self) {
+        let mut nodes = self.nodes.lock().await;
+        let ready: Vec<usize> = (0..nodes.len())
+            .filter(|&i| nodes[i].status == NodeStatus::Waiting && nodes[i].waiting_for == 0)
+            .collect();
+        for i in ready {
+            let Some(task) = nodes[i].pending.take() else {
+                continue;
+            };
+            nodes[i].status = NodeStatus::Queued;
+
+            // Watch the task before it can run
+            let watcher: Option<Arc<dyn Watcher>> = self.me.upgrade().map(|me| me as Arc<dyn Watcher>);
+            if let Some(watcher) = &watcher {
+                self.watchers.register(task.as_ref(), watcher.clone()).await;
+            }
+            let result = self.submitter.submit(task, Submission::default()).await;
+            if !result.is_accepted() {
+                if let Some(watcher) = &watcher {
+                    self.watchers.unregister(&nodes[i].task, watcher).await;
+                }
+                nodes[i].status = NodeStatus::Failed(format!("rejected: {:?}", result));
+                Self::cancel_dependents(&mut nodes, i);
+            }
+        }
+        self.update_status(&nodes);
+    }
+
+    /// Cancel the tasks depending (directly or not) on a node.
+    fn cancel_dependents(nodes: &mut [Node], node: usize) {
+        let mut dependents = nodes[node].dependents.clone();
+        while let Some(i) = dependents.pop() {
+            if nodes[i].status == NodeStatus::Waiting {
+                nodes[i].status = NodeStatus::Cancelled;
+                nodes[i].pending = None;
+                dependents.extend(nodes[i].dependents.iter().copied());
+            }
+        }
+    }
+
+    fn update_status(&self, nodes: &[Node]) {
+        let status = if nodes
+            .iter()
+            .any(|n| matches!(n.status, NodeStatus::Waiting | NodeStatus::Queued))
+        {
+            GraphStatus::Running
+        } else if nodes.iter().all(|n| n.status == NodeStatus::Succeeded) {
+            GraphStatus::Succeeded
+        } else {
+            GraphStatus::Failed
+        };
+        self.status.send_replace(status);
+    }
+}
+
+#[async_trait]
+impl Watcher for GraphRun {
+    async fn finished(&self, task: &TaskRef, outcome: &TaskOutcome) {
+        {
+            let mut nodes = self.nodes.lock().await;
+            let Some(i) = nodes
+                .iter()
+                .position(|n| n.task.name == task.name && n.task.id == task.id)
+            else {
+                return;
+            };
+            if nodes[i].status != NodeStatus::Queued {
+                return;
+            }
+            match outcome {
+                TaskOutcome::Succeeded => {
+                    nodes[i].status = NodeStatus::Succeeded;
+                    for dependent in nodes[i].dependents.clone() {
+                        nodes[dependent].waiting_for -= 1;
+                    }
+                }
+                TaskOutcome::Failed(message) => {
+                    nodes[i].status = NodeStatus::Failed(message.clone());
+                    Self::cancel_dependents(&mut nodes, i);
+                }
+                TaskOutcome::Cancelled => {
+                    nodes[i].status = NodeStatus::Cancelled;
+                    Self::cancel_dependents(&mut nodes, i);
+                }
+            }
+        }
+        self.submit_ready().await;
+    }
+}
+
+/// Handle to a running task graph.
+#[derive(Clone)]
+pub struct GraphHandle {
+    run: Arc<GraphRun>,
+}
+
+impl GraphHandle {
+    pub(crate) fn new(run: Arc<GraphRun>) -> Self {
+        Self { run }
+    }
+
+    /// Return the overall status of the graph.
+    pub fn status(&self) -> GraphStatus {
+        *self.run.status.borrow()
+    }
+
+    /// Return the status of a task of the graph.
+    pub async fn node_status(&self, node: NodeId) -> Option<NodeStatus> {
+        self.run
+            .nodes
+            .lock()
+            .await
+            .get(node.0)
+            .map(|n| n.status.clone())
+    }
+
+    /// Wait until all the tasks of the graph are done, and return the overall status.
+    /// The task manager must be started, or stepped, meanwhile.
+    pub async fn wait(&self) -> GraphStatus {
+        let mut status = self.run.status.subscribe();
+        let result = match status.wait_for(|s| *s != GraphStatus::Running).await {
+            Ok(s) => *s,
+            Err(_) => self.status(),
+        };
+        result
+    }
+}
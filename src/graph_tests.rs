@@ -0,0 +1,236 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+use crate::{
+    context::TaskContext,
+    graph::{GraphError, GraphStatus, NodeStatus, TaskGraph},
+    manager::TaskManager,
+    registry::TaskRegistry,
+    store::memory::InMemoryTaskStore,
+    task::Task,
+    testing::TestTaskManager,
+};
+
+struct GraphTask {
+    pub id: String,
+    pub fail: bool,
+    pub payload: Option<String>,
+    pub results: Arc<RwLock<Vec<String>>>,
+}
+
+impl GraphTask {
+    fn new(id: &str, results: &Arc<RwLock<Vec<String>>>) -> Box<Self> {
+        Box::new(Self {
+            id: id.to_string(),
+            fail: false,
+            payload: None,
+            results: results.clone(),
+        })
+    }
+
+    fn failing(id: &str, results: &Arc<RwLock<Vec<String>>>) -> Box<Self> {
+        Box::new(Self {
+            id: id.to_string(),
+            fail: true,
+            payload: None,
+            results: results.clone(),
+        })
+    }
+
+    fn shared(id: &str, results: &Arc<RwLock<Vec<String>>>) -> Box<Self> {
+        Box::new(Self {
+            id: id.to_string(),
+            fail: false,
+            payload: Some(id.to_string()),
+            results: results.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Task for GraphTask {
+    fn name(&self) -> String {
+        "graph_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn run(&self, ctx: &TaskContext) {
+        self.results.write().await.push(self.id.clone());
+        if self.fail {
+            ctx.fail("boom");
+        }
+    }
+
+    fn payload(&self) -> Option<String> {
+        self.payload.clone()
+    }
+}
+
+#[tokio::test]
+async fn run_in_dependency_order() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let tm = TestTaskManager::new("manager");
+
+    // fetch -> (parse, thumbnail) -> index
+    let mut graph = TaskGraph::new();
+    let index = graph.add(GraphTask::new("index", &results));
+    let parse = graph.add(GraphTask::new("parse", &results));
+    let thumbnail = graph.add(GraphTask::new("thumbnail", &results));
+    let fetch = graph.add(GraphTask::new("fetch", &results));
+    graph
+        .depends_on(parse, fetch)
+        .depends_on(thumbnail, fetch)
+        .depends_on(index, parse)
+        .depends_on(index, thumbnail);
+    let handle = tm.run_graph(graph).await.unwrap();
+
+    // Only tasks without prerequisites are queued
+    assert_eq!(tm.get_state().await.len(), 1);
+    assert_eq!(handle.node_status(fetch).await, Some(NodeStatus::Queued));
+    assert_eq!(handle.node_status(index).await, Some(NodeStatus::Waiting));
+    assert_eq!(handle.status(), GraphStatus::Running);
+
+    assert_eq!(tm.run_until_idle().await, 4);
+    let results = results.read().await;
+    assert_eq!(results[0], "fetch");
+    assert_eq!(results[3], "index");
+    assert_eq!(handle.status(), GraphStatus::Succeeded);
+    assert_eq!(handle.wait().await, GraphStatus::Succeeded);
+    assert_eq!(handle.node_status(index).await, Some(NodeStatus::Succeeded));
+}
+
+#[tokio::test]
+async fn cancel_dependents_of_failed_task() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let tm = TestTaskManager::new("manager");
+
+    // a -> b -> c, d (independent)
+    let mut graph = TaskGraph::new();
+    let a = graph.add(GraphTask::failing("a", &results));
+    let b = graph.add(GraphTask::new("b", &results));
+    let c = graph.add(GraphTask::new("c", &results));
+    let d = graph.add(GraphTask::new("d", &results));
+    graph.depends_on(b, a).depends_on(c, b);
+    let handle = tm.run_graph(graph).await.unwrap();
+
+    assert_eq!(tm.run_until_idle().await, 2);
+    let mut results = results.read().await.clone();
+    results.sort();
+    assert_eq!(results, vec!["a", "d"]);
+    assert_eq!(
+        handle.node_status(a).await,
+        Some(NodeStatus::Failed("boom".to_string()))
+    );
+    assert_eq!(handle.node_status(b).await, Some(NodeStatus::Cancelled));
+    assert_eq!(handle.node_status(c).await, Some(NodeStatus::Cancelled));
+    assert_eq!(handle.node_status(d).await, Some(NodeStatus::Succeeded));
+    assert_eq!(handle.wait().await, GraphStatus::Failed);
+}
+
+#[tokio::test]
+async fn fail_rejected_task() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let tm = TestTaskManager::new("manager");
+    tm.run(GraphTask::new("a", &results)).await;
+
+    // Already pending outside of the graph
+    let mut graph = TaskGraph::new();
+    let a = graph.add(GraphTask::new("a", &results));
+    let b = graph.add(GraphTask::new("b", &results));
+    graph.depends_on(b, a);
+    let handle = tm.run_graph(graph).await.unwrap();
+
+    assert!(matches!(
+        handle.node_status(a).await,
+        Some(NodeStatus::Failed(_))
+    ));
+    assert_eq!(handle.node_status(b).await, Some(NodeStatus::Cancelled));
+    assert_eq!(handle.status(), GraphStatus::Failed);
+    assert_eq!(tm.run_until_idle().await, 1);
+}
+
+#[tokio::test]
+async fn reject_invalid_graph() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let tm = TestTaskManager::new("manager");
+
+    let mut graph = TaskGraph::new();
+    let a = graph.add(GraphTask::new("a", &results));
+    let b = graph.add(GraphTask::new("b", &results));
+    let c = graph.add(GraphTask::new("c", &results));
+    graph.depends_on(b, a).depends_on(c, b).depends_on(b, c);
+    assert_eq!(
+        tm.run_graph(graph).await.err(),
+        Some(GraphError::Cycle(vec![
+            ("graph_task".to_string(), "b".to_string()),
+            ("graph_task".to_string(), "c".to_string()),
+        ]))
+    );
+
+    let mut graph = TaskGraph::new();
+    graph.add(GraphTask::new("a", &results));
+    graph.add(GraphTask::new("a", &results));
+    assert_eq!(
+        tm.run_graph(graph).await.err(),
+        Some(GraphError::DuplicateTask(
+            "graph_task".to_string(),
+            "a".to_string()
+        ))
+    );
+
+    // Nothing was queued
+    assert!(tm.get_state().await.is_empty());
+}
+
+#[tokio::test]
+async fn wait_for_graph_on_started_manager() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let manager = TaskManager::new(InMemoryTaskStore::new("manager"), 2);
+    manager.start().await;
+
+    let mut graph = TaskGraph::new();
+    let a = graph.add(GraphTask::new("a", &results));
+    let b = graph.add(GraphTask::new("b", &results));
+    graph.depends_on(b, a);
+    let handle = manager.run_graph(graph).await.unwrap();
+
+    assert_eq!(handle.wait().await, GraphStatus::Succeeded);
+    assert_eq!(*results.read().await, vec!["a", "b"]);
+    manager.stop().await;
+}
+
+#[tokio::test]
+async fn reject_shared_graph() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let manager = TaskManager::new(InMemoryTaskStore::new("manager"), 1)
+        .with_shared_queue(TaskRegistry::new(), Duration::from_millis(5));
+
+    // Completion of a task claimed by another instance cannot be followed
+    let mut graph = TaskGraph::new();
+    let a = graph.add(GraphTask::new("a", &results));
+    let b = graph.add(GraphTask::shared("b", &results));
+    graph.depends_on(b, a);
+    assert_eq!(
+        manager.run_graph(graph).await.err(),
+        Some(GraphError::SharedTask(
+            "graph_task".to_string(),
+            "b".to_string()
+        ))
+    );
+    assert!(manager.get_state().await.is_empty());
+
+    // Tasks without payload run locally
+    let mut graph = TaskGraph::new();
+    let a = graph.add(GraphTask::new("a", &results));
+    let b = graph.add(GraphTask::new("b", &results));
+    graph.depends_on(b, a);
+    let handle = manager.run_graph(graph).await.unwrap();
+    assert!(manager.step().await);
+    assert!(manager.step().await);
+    assert_eq!(handle.wait().await, GraphStatus::Succeeded);
+}
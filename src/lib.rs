@@ -9,7 +9,9 @@ This example uses an in memory store to track task states.
 use std::time::Duration;
 
 use async_trait::async_trait;
-use quartermaster::{manager::TaskManager, store::memory::InMemoryTaskStore, task::Task};
+use quartermaster::{
+    context::TaskContext, manager::TaskManager, store::memory::InMemoryTaskStore, task::Task,
+};
 use tokio::time::sleep;
 
 // A simple task printing hello after a delay
@@ -34,7 +36,7 @@ impl Task for DelayedHelloTask {
     }
 
     // Task code
-    async fn run(&self) {
+    async fn run(&self, _ctx: &TaskContext) {
         sleep(Duration::from_millis(self.delay_millis)).await;
         println!("Hello {} !", self.name);
     }
@@ -63,8 +65,8 @@ async fn main() {
         .iter()
         .for_each(|s| println!("name = [{}], id = [{}], creation time = [{}], status = [{}]", s.task_name, s.task_id, s.creation_time, s.status));
     // Output
-    //name = [delayed_hello], id = [Homer], creation time = [1668816441], status = [Pending]
-    //name = [delayed_hello], id = [Bart], creation time = [1668816441], status = [Pending]
+    //name = [delayed_hello], id = [Homer], creation time = [1668816441000], status = [Pending]
+    //name = [delayed_hello], id = [Bart], creation time = [1668816441000], status = [Pending]
 
 
     // Stop the task manager
@@ -95,7 +97,7 @@ use std::time::Duration;
 
 use async_trait::async_trait;
 use quartermaster::store::mongodb::MongoDBTaskStore;
-use quartermaster::{manager::TaskManager, task::Task};
+use quartermaster::{context::TaskContext, manager::TaskManager, task::Task};
 use std::sync::Arc;
 use tokio::time::sleep;
 
@@ -123,7 +125,7 @@ impl Task for DelayedHelloTask {
     }
 
     // Task code
-    async fn run(&self) {
+    async fn run(&self, _ctx: &TaskContext) {
         sleep(Duration::from_millis(self.delay_millis)).await;
         println!("Hello {} !", self.name);
     }
@@ -178,9 +180,34 @@ async fn main() {
  
 
 pub mod task;
+pub mod context;
 pub mod manager;
+pub mod graph;
+pub mod batch;
+pub mod registry;
+pub mod lock;
+pub mod dedup;
 pub mod store;
+pub mod clock;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
+mod election;
 mod util;
+mod watch;
 
 #[cfg(test)]
-pub mod manager_tests;
\ No newline at end of file
+pub mod batch_tests;
+#[cfg(test)]
+pub mod context_tests;
+#[cfg(test)]
+pub mod dedup_tests;
+#[cfg(test)]
+pub mod graph_tests;
+#[cfg(test)]
+pub mod lock_tests;
+#[cfg(test)]
+pub mod manager_tests;
+#[cfg(test)]
+pub mod registry_tests;
+#[cfg(test)]
+pub mod testing_tests;
\ No newline at end of file
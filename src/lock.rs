@@ -0,0 +1,155 @@
+/*!
+Named locks, shared by the instances of a task manager through its store.
+
+A task takes a lock from its context (see [`TaskContext::lock`](crate::context::TaskContext::lock)),
+and holds it until the returned [`LockGuard`] is released or dropped.
+A lock is also released once its time to live elapsed, in case its holder crashed:
+the time to live should exceed the time the lock is held.
+
+```ignore
+async fn run(&self, ctx: &TaskContext) {
+    let Ok(Some(_guard)) = ctx.lock(&format!("customer:{}", self.customer), Duration::from_secs(60)).await else {
+        return;
+    };
+    // Only one task touches the customer meanwhile
+}
+```
+*/
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio_util::sync::CancellationToken;
+
+use crate::store::{TaskStore, TaskStoreError};
+
+/// Interval between two attempts to take a held lock.
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Number of lock owners created by the process.
+static OWNER_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Takes and releases named locks.
+#[async_trait]
+pub(crate) trait Locker: Send + Sync {
+    async fn try_lock(&self, name: &str, owner: &str, ttl: Duration) -> Result<bool, TaskStoreError>;
+    async fn unlock(&self, name: &str, owner: &str) -> Result<(), TaskStoreError>;
+}
+
+/// Locker backed by a task store.
+pub(crate) struct StoreLocker<S>
+where
+    S: TaskStore,
+{
+    pub store: Arc<S>,
+}
+
+#[async_trait]
+impl<S: TaskStore> Locker for StoreLocker<S> {
+    async fn try_lock(&self, name: &str, owner: &str, ttl: Duration) -> Result<bool, TaskStoreError> {
+        self.store.try_lock(name, owner, ttl).await
+    }
+
+    async fn unlock(&self, name: &str, owner: &str) -> Result<(), TaskStoreError> {
+        self.store.unlock(name, owner).await
+    }
+}
+
+/// Named locks of a task manager.
+#[derive(Clone)]
+pub(crate) struct Locks {
+    locker: Arc<dyn Locker>,
+}
+
+impl Locks {
+    pub fn new(locker: Arc<dyn Locker>) -> Self {
+        Self { locker }
+    }
+
+    /// Take a lock, unless held by another owner.
+    pub async fn try_lock(&self, name: &str, ttl: Duration) -> Result<Option<LockGuard>, TaskStoreError> {
+        let owner = format!(
+            "{}:{}:{}",
+            gethostname::gethostname().to_string_lossy(),
+            std::process::id(),
+            OWNER_COUNT.fetch_add(1, Ordering::SeqCst)
+        );
+        if !self.locker.try_lock(name, &owner, ttl).await? {
+            return Ok(None);
+        }
+        Ok(Some(LockGuard {
+            locker: self.locker.clone(),
+            name: name.to_string(),
+            owner,
+            released: false,
+        }))
+    }
+
+    /// Take a lock, waiting for it to be released.
+    /// Return None if cancelled while waiting.
+    pub async fn lock(
+        &self,
+        name: &str,
+        ttl: Duration,
+        cancellation: &CancellationToken,
+    ) -> Result<Option<LockGuard>, TaskStoreError> {
+        loop {
+            if let Some(guard) = self.try_lock(name, ttl).await? {
+                return Ok(Some(guard));
+            }
+            if tokio::time::timeout(RETRY_INTERVAL, cancellation.cancelled())
+                .await
+                .is_ok()
+            {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Held lock, released when dropped.
+pub struct LockGuard {
+    locker: Arc<dyn Locker>,
+    name: String,
+    owner: String,
+    released: bool,
+}
+
+impl LockGuard {
+    /// Return the lock name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Release the lock now, instead of in the background when dropped.
+    pub async fn release(mut self) -> Result<(), TaskStoreError> {
+        self.released = true;
+        self.locker.unlock(&self.name, &self.owner).await
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            log::warn!("lock `{}` dropped outside a runtime, released once expired", self.name);
+            return;
+        };
+        let locker = self.locker.clone();
+        let name = std::mem::take(&mut self.name);
+        let owner = std::mem::take(&mut self.owner);
+        runtime.spawn(async move {
+            if let Some(err) = locker.unlock(&name, &owner).await.err() {
+                log::error!("failed to release lock `{}`: {}", name, err.to_string());
+            }
+        });
+    }
+}
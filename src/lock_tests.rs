@@ -0,0 +1,88 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{sync::RwLock, time::sleep};
+
+use crate::{
+    context::TaskContext,
+    manager::TaskManager,
+    store::{file::FileTaskStore, memory::InMemoryTaskStore, TaskStore},
+    task::Task,
+};
+
+/// Task holding the `customer` lock while it runs, recording when it enters and leaves.
+struct LockingTask {
+    pub id: String,
+    pub events: Arc<RwLock<Vec<String>>>,
+}
+
+#[async_trait]
+impl Task for LockingTask {
+    fn name(&self) -> String {
+        "locking_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn run(&self, ctx: &TaskContext) {
+        let guard = ctx
+            .lock("customer", Duration::from_secs(10))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(ctx
+            .try_lock("customer", Duration::from_secs(10))
+            .await
+            .unwrap()
+            .is_none());
+        self.events.write().await.push(format!("enter:{}", self.id));
+        sleep(Duration::from_millis(20)).await;
+        self.events.write().await.push(format!("leave:{}", self.id));
+        if self.id == "1" {
+            guard.release().await.unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn lock_from_tasks() {
+    let events = Arc::new(RwLock::new(vec![]));
+    let store = InMemoryTaskStore::new("manager");
+    let manager = TaskManager::new(store.clone(), 2);
+    for id in ["1", "2", "3"] {
+        manager
+            .run(Box::new(LockingTask {
+                id: id.to_string(),
+                events: events.clone(),
+            }))
+            .await;
+    }
+    manager.stop().await;
+    manager.start_blocking().await;
+
+    // Tasks never held the lock at the same time
+    let events = events.read().await;
+    assert_eq!(events.len(), 6);
+    for pair in events.chunks(2) {
+        assert_eq!(pair[0].replace("enter", "leave"), pair[1]);
+    }
+
+    // Dropped guard released, once its release task ran
+    tokio::task::yield_now().await;
+    assert!(store
+        .try_lock("customer", "test", Duration::from_secs(1))
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn unsupported_locks() {
+    let dir = std::env::temp_dir().join("quartermaster_lock_tests");
+    let store = FileTaskStore::new("manager", &dir);
+    assert!(store
+        .try_lock("customer", "test", Duration::from_secs(1))
+        .await
+        .is_err());
+}
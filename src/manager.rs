@@ -1,17 +1,139 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        self,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use async_trait::async_trait;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
+use tokio_util::sync::CancellationToken;
 
 use crate::{
+    clock::{Clock, SystemClock},
+    election::Election,
+    dedup::{DedupPolicy, TaskHandle, TaskRun},
+    context::{Lineage, ManagerHandle, Submission, ProgressReporter, StoreStateWriter, Submit, TaskContext},
     store::{
-        state::{TaskState, TaskStatus},
-        TaskStore,
+        state::{StateUpdate, TaskState, TaskStatus},
+        TaskStore, TaskStoreError,
     },
-    task::Task,
+    batch::{BatchHandle, BatchRun},
+    graph::{GraphError, GraphHandle, GraphRun, TaskGraph},
+    lock::{Locks, StoreLocker},
+    registry::TaskRegistry,
+    task::{Task, TaskOutcome, TaskRef},
+    util::to_millis,
+    watch::{Watcher, Watchers},
 };
 
-type TaskQueue = deadqueue::unlimited::Queue<Box<dyn Task>>;
+/// Default minimum interval between two progress reports written to the store.
+const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Default lease of the tasks claimed from a shared queue.
+const DEFAULT_CLAIM_LEASE: Duration = Duration::from_secs(30);
+
+/// Default lease of the leadership.
+const DEFAULT_LEADER_LEASE: Duration = Duration::from_secs(30);
+
+/// What to do with the tasks of a dead instance, once its states are moved to the reaping instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrphanPolicy {
+    /// Delete the task states, the tasks can be submitted again.
+    Delete,
+    /// Re-queue the tasks re-created by the registry, delete the others.
+    Reassign,
+}
+
+/// Names and ids of the queued tasks, with the task replacing each one (if any).
+type QueuedKeys = HashMap<(String, String), Option<Box<dyn Task>>>;
+
+/// Task queue, keeping track of the queued tasks.
+struct TaskQueue {
+    queue: deadqueue::unlimited::Queue<QueuedTask>,
+    keys: sync::Mutex<QueuedKeys>,
+}
+
+impl TaskQueue {
+    fn new() -> Self {
+        Self {
+            queue: deadqueue::unlimited::Queue::new(),
+            keys: sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn push(&self, queued: QueuedTask) {
+        self.keys
+            .lock()
+            .unwrap()
+            .insert((queued.task.name(), queued.task.id()), None);
+        self.queue.push(queued);
+    }
+
+    async fn pop(&self) -> QueuedTask {
+        let mut queued = self.queue.pop().await;
+        self.remove_key(&mut queued);
+        queued
+    }
+
+    fn try_pop(&self) -> Option<QueuedTask> {
+        let mut queued = self.queue.try_pop()?;
+        self.remove_key(&mut queued);
+        Some(queued)
+    }
+
+    /// Forget a popped task, swapped with its replacement (if any).
+    fn remove_key(&self, queued: &mut QueuedTask) {
+        let replacement = self
+            .keys
+            .lock()
+            .unwrap()
+            .remove(&(queued.task.name(), queued.task.id()));
+        if let Some(Some(task)) = replacement {
+            queued.task = task;
+        }
+    }
+
+    /// Replace a queued task by a task with the same name and id, keeping its place in the queue.
+    /// Return the given task back if no such task is queued.
+    fn replace(&self, task: Box<dyn Task>) -> Result<(), Box<dyn Task>> {
+        match self.keys.lock().unwrap().get_mut(&(task.name(), task.id())) {
+            Some(replacement) => {
+                *replacement = Some(task);
+                Ok(())
+            }
+            None => Err(task),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Return true if a task with the given name and id is queued.
+    fn contains(&self, key: &(String, String)) -> bool {
+        self.keys.lock().unwrap().contains_key(key)
+    }
+}
+
+/// Queued task, with the pipeline it belongs to (if it is a follow-up task).
+struct QueuedTask {
+    task: Box<dyn Task>,
+    root: Option<TaskRef>,
+    /// Set when claimed from the store: its lease is renewed while it runs.
+    leased: bool,
+    /// Number of the previous runs of the task, as stored in its state.
+    attempt: u32,
+}
+
+/// Cancellation tokens of running tasks, by task name and id.
+type RunningTasks = HashMap<(String, String), CancellationToken>;
+
+/// Tasks to submit once the running task with the same name and id finished.
+type DeferredTasks = HashMap<(String, String), (Box<dyn Task + Send + Sync>, Submission)>;
 
 /// Stop task is a system task.
 /// It is used to shutdown the task manger.
@@ -27,7 +149,43 @@ impl Task for StopTask {
         "stop".to_string()
     }
 
-    async fn run(&self) {}
+    async fn run(&self, _ctx: &TaskContext) {}
+}
+
+/// Result of a task submission to a task manager.
+#[derive(Debug)]
+pub enum RunResult {
+    /// Task was queued.
+    Accepted,
+    /// A task with the same name and id is already pending or running.
+    Duplicate,
+    /// Task replaced the pending task with the same name and id.
+    Replaced,
+    /// Task was merged with the pending or running task with the same name and id, whose run is followed by the handle.
+    Coalesced(TaskHandle),
+    /// Task will be submitted again once the running task with the same name and id finished.
+    Deferred,
+    /// Task state could not be saved, task was not queued.
+    StoreUnavailable(TaskStoreError),
+    /// Task queue reached its capacity, task was not queued.
+    QueueFull,
+    /// Task manager is stopping or stopped, task was not queued.
+    ManagerStopped,
+    /// Task only runs on the leader instance, which this instance is not, task was not queued.
+    NotLeader,
+    /// A task with the same name and id completed within its idempotency window, with the given outcome,
+    /// task was not queued.
+    Completed(TaskOutcome),
+}
+
+impl RunResult {
+    /// Return true if the task was queued, or merged with a pending or running task.
+    pub fn is_accepted(&self) -> bool {
+        matches!(
+            self,
+            RunResult::Accepted | RunResult::Replaced | RunResult::Coalesced(_) | RunResult::Deferred
+        )
+    }
 }
 
 /// Task manager.
@@ -46,6 +204,38 @@ where
     store: Arc<S>,
     /// Task manager state
     started: Arc<RwLock<bool>>,
+    /// Set when a stop was requested, until the task manager is started again.
+    stopped: Arc<RwLock<bool>>,
+    /// Maximum number of queued tasks (unlimited if None).
+    queue_capacity: Option<usize>,
+    /// Clock used to time tasks.
+    clock: Arc<dyn Clock>,
+    /// Host name, recorded in the states of the tasks run by this task manager.
+    host: String,
+    /// Minimum interval between two progress reports written to the store.
+    progress_interval: Duration,
+    /// Cancellation tokens of the running tasks, by task name and id.
+    running: Arc<Mutex<RunningTasks>>,
+    /// Tasks submitted again once the running task with the same name and id finished.
+    deferred: Arc<Mutex<DeferredTasks>>,
+    /// Watchers of the task runs.
+    watchers: Arc<Watchers>,
+    /// Registry re-creating the tasks left in the store on start (if set).
+    registry: Option<Arc<TaskRegistry>>,
+    /// Interval between two claims of pending tasks from the store, when the queue is shared.
+    shared_poll_interval: Option<Duration>,
+    /// Lease of the tasks claimed from the store, renewed while they run.
+    claim_lease: Duration,
+    /// Interval between two heartbeats, and timeout after which other instances are dead (if set).
+    heartbeat: Option<(Duration, Duration)>,
+    /// What to do with the tasks of dead instances.
+    orphan_policy: OrphanPolicy,
+    /// Leadership of this instance, and the tasks requiring it.
+    election: Arc<Election>,
+    /// Time to live of the outcomes of completed tasks, by task name.
+    idempotency_windows: Arc<HashMap<String, Duration>>,
+    /// Cancels the heartbeat and election loops, while started.
+    background_stop: sync::Mutex<Option<CancellationToken>>,
 }
 
 impl<S: TaskStore + 'static> TaskManager<S> {
@@ -57,46 +247,187 @@ impl<S: TaskStore + 'static> TaskManager<S> {
             worker_count,
             store: Arc::new(store),
             started: Arc::new(RwLock::new(false)),
+            stopped: Arc::new(RwLock::new(false)),
+            queue_capacity: None,
+            clock: Arc::new(SystemClock),
+            host: gethostname::gethostname().to_string_lossy().to_string(),
+            progress_interval: DEFAULT_PROGRESS_INTERVAL,
+            running: Arc::new(Mutex::new(HashMap::new())),
+            deferred: Arc::new(Mutex::new(HashMap::new())),
+            watchers: Arc::new(Watchers::default()),
+            registry: None,
+            shared_poll_interval: None,
+            claim_lease: DEFAULT_CLAIM_LEASE,
+            heartbeat: None,
+            orphan_policy: OrphanPolicy::Reassign,
+            election: Arc::new(Election::new(HashSet::new(), DEFAULT_LEADER_LEASE)),
+            idempotency_windows: Arc::new(HashMap::new()),
+            background_stop: sync::Mutex::new(None),
         }
     }
 
+    /// Use the given clock to time tasks.
+    /// Stores take their own clock to stamp task states.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Set the host name recorded in task states (defaults to the system host name).
+    pub fn with_host(mut self, host: &str) -> Self {
+        self.host = host.to_string();
+        self
+    }
+
+    /// Set the minimum interval between two progress reports of a task written to the store.
+    pub fn with_progress_interval(mut self, interval: Duration) -> Self {
+        self.progress_interval = interval;
+        self
+    }
+
+    /// Recover, on start, the tasks left in the store by a previous run, instead of clearing their states.
+    /// Tasks are re-created from their payloads by the registry, tasks which cannot be re-created are cleared.
+    pub fn with_registry(mut self, registry: TaskRegistry) -> Self {
+        self.registry = Some(Arc::new(registry));
+        self
+    }
+
+    /// Share the queue with the other instances of the task manager, through the store.
+    /// Submitted tasks with a payload are not queued locally: they stay pending in the store,
+    /// until claimed by a worker of any instance, and re-created by its registry (tasks without payload run locally).
+    /// Idle workers claim pending tasks from the store at the given interval.
+    /// The registry is also used to recover tasks on start, see [`TaskManager::with_registry`].
+    pub fn with_shared_queue(mut self, registry: TaskRegistry, poll_interval: Duration) -> Self {
+        self.registry = Some(Arc::new(registry));
+        self.shared_poll_interval = Some(poll_interval);
+        self
+    }
+
+    /// Set the lease of the tasks claimed from a shared queue (30 seconds by default).
+    /// The lease is renewed while the task runs, every third of its duration.
+    /// If it expires (e.g. the instance hung or was killed), any instance can claim the task again;
+    /// a task whose lease was lost is cancelled, and its state is left to the new claimer.
+    pub fn with_claim_lease(mut self, lease: Duration) -> Self {
+        self.claim_lease = lease;
+        self
+    }
+
+    /// Send heartbeats to the store at the given interval while started,
+    /// and reap the other instances of the task manager without heartbeat for longer than the timeout:
+    /// their task states are moved to this instance, and handled according to the orphan policy.
+    /// The timeout should be several times the interval of every instance.
+    pub fn with_heartbeat(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.heartbeat = Some((interval, timeout));
+        self
+    }
+
+    /// Set what to do with the tasks of dead instances (re-assigned by default), see [`TaskManager::with_heartbeat`].
+    pub fn with_orphan_policy(mut self, policy: OrphanPolicy) -> Self {
+        self.orphan_policy = policy;
+        self
+    }
+
+    /// Only run the tasks with the given names on the leader instance of the task manager.
+    /// While started, instances compete for the leadership through the store, held for the given lease
+    /// and renewed every third of it; another instance takes over once the lease expires.
+    /// Leader-only tasks submitted to another instance are rejected, unless left to be claimed
+    /// from a shared queue, where only the leader claims them.
+    pub fn with_leader_only(mut self, names: &[&str], lease: Duration) -> Self {
+        let names = names.iter().map(|name| name.to_string()).collect();
+        self.election = Arc::new(Election::new(names, lease));
+        self
+    }
+
+    /// Return true if this instance currently holds the leadership, see [`TaskManager::with_leader_only`].
+    pub fn is_leader(&self) -> bool {
+        self.election.is_leader()
+    }
+
+    /// Remember the outcomes of the completed tasks with the given name for the given time to live:
+    /// a task with the same name and id submitted meanwhile is not run again.
+    /// Cancelled runs are not remembered. The store must support idempotency windows.
+    pub fn with_idempotency_window(mut self, name: &str, ttl: Duration) -> Self {
+        Arc::make_mut(&mut self.idempotency_windows).insert(name.to_string(), ttl);
+        self
+    }
+
+    /// Limit the number of queued tasks.
+    /// Tasks submitted while the queue is full are rejected.
+    pub fn with_queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
     /// Run an task.
-    pub async fn run(&self, task: Box<dyn Task + Send + Sync>) {
-        // Check if task is already known
-        match self.store.get_state(task.as_ref()).await {
-            Ok(r) => {
-                if r.is_some() {
-                    log::debug!(
-                        "task `{}` with id `{}` already exists",
-                        task.name(),
-                        task.id()
-                    );
-                    return;
-                }
-            }
-            Err(err) => {
-                log::error!(
-                    "failed to retrieve task `{}` with id `{}` state: {}",
+    /// Return whether the task was queued, or why it was rejected.
+    pub async fn run(&self, task: Box<dyn Task + Send + Sync>) -> RunResult {
+        self.submitter().submit(task, Submission::default()).await
+    }
+
+    /// Run a batch of tasks, identified by a generated id (see [`BatchHandle::id`]), recorded in their states.
+    /// Each task is submitted as with [`TaskManager::run`], rejected tasks are counted as failed.
+    /// With a shared queue, tasks with a payload are rejected: their completion cannot be followed.
+    /// Return a handle to follow the batch completion.
+    pub async fn run_batch(&self, tasks: Vec<Box<dyn Task + Send + Sync>>) -> BatchHandle {
+        let id = uuid::Uuid::new_v4().to_string();
+        self.run_batch_with_id(&id, tasks).await
+    }
+
+    /// Run a batch of tasks, identified by the given id, see [`TaskManager::run_batch`].
+    pub async fn run_batch_with_id(&self, id: &str, tasks: Vec<Box<dyn Task + Send + Sync>>) -> BatchHandle {
+        let run = BatchRun::new(id, &tasks);
+        let watcher: Arc<dyn Watcher> = run.clone();
+        let submitter = self.submitter();
+        for (index, task) in tasks.into_iter().enumerate() {
+            if self.shared_poll_interval.is_some() && task.payload().is_some() {
+                log::warn!(
+                    "task `{}` with id `{}` of batch `{}` rejected, it would be left to the shared queue",
                     task.name(),
                     task.id(),
-                    err.to_string()
+                    id
                 );
-                return;
+                run.rejected(index).await;
+                continue;
+            }
+            // Watch the task before it can run
+            let key = TaskRef::of(task.as_ref());
+            self.watchers.register(&key, watcher.clone()).await;
+            let submission = Submission {
+                batch: Some(id.to_string()),
+                ..Default::default()
+            };
+            let result = submitter.submit(task, submission).await;
+            if !result.is_accepted() {
+                self.watchers.unregister(&key, &watcher).await;
+                run.rejected(index).await;
             }
-        };
-
-        // Add task state to store
-        if let Some(err) = self.store.save_state(task.as_ref()).await.err() {
-            log::error!(
-                "failed to save task `{}` with id `{}` state: {}",
-                task.name(),
-                task.id(),
-                err.to_string()
-            );
         }
+        BatchHandle::new(run)
+    }
 
-        // Add task to queue
-        self.queue.push(task);
+    /// Run a graph of tasks.
+    /// Each task is queued once all its prerequisites succeeded,
+    /// tasks depending on a failed or cancelled task are not run.
+    /// With a shared queue, graphs with a task with a payload are rejected: their completion cannot be followed.
+    /// Return an error if the graph is invalid (nothing is run), or a handle to follow its execution.
+    pub async fn run_graph(&self, graph: TaskGraph) -> Result<GraphHandle, GraphError> {
+        let shared = self.shared_poll_interval.is_some();
+        let run = GraphRun::new(graph, Arc::new(self.submitter()), self.watchers.clone(), shared)?;
+        run.submit_ready().await;
+        Ok(GraphHandle::new(run))
+    }
+
+    /// Cancel a running task.
+    /// The task is notified through its context cancellation token, and is expected to stop early.
+    /// Return false if the task is not running.
+    pub async fn cancel(&self, task: &dyn Task) -> bool {
+        match self.running.lock().await.get(&(task.name(), task.id())) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
     }
 
     /// Start task manager.
@@ -135,68 +466,90 @@ impl<S: TaskStore + 'static> TaskManager<S> {
             );
         }
 
-        // Clear state
-        self.clear().await;
+        // Show this instance is alive before recovering its tasks
+        if self.heartbeat.is_some() {
+            if let Some(err) = self.store.heartbeat().await.err() {
+                log::error!(
+                    "task manager `{}` failed to send heartbeat: {}",
+                    self.name,
+                    err.to_string()
+                );
+            }
+        }
+
+        // Recover tasks left by a previous run, or clear state
+        match self.registry.clone() {
+            Some(registry) => self.recover(&registry).await,
+            None => self.clear().await,
+        }
+
+        *self.stopped.write().await = false;
+
+        let token = CancellationToken::new();
+        if let Some(previous) = self.background_stop.lock().unwrap().replace(token.clone()) {
+            previous.cancel();
+        }
+
+        // Compete for the leadership, known before workers start
+        if self.election.is_needed() {
+            self.election.campaign(&self.name, self.store.as_ref()).await;
+            let election = self.election.clone();
+            let store = self.store.clone();
+            let name = self.name.clone();
+            let token = token.clone();
+            tokio::spawn(async move {
+                while tokio::time::timeout(election.lease() / 3, token.cancelled())
+                    .await
+                    .is_err()
+                {
+                    election.campaign(&name, store.as_ref()).await;
+                }
+            });
+        }
+
+        // Start heartbeats and reaping of dead instances
+        if let Some((interval, timeout)) = self.heartbeat {
+            let reaper = self.reaper();
+            let policy = self.orphan_policy;
+            let token = token.clone();
+            tokio::spawn(async move {
+                loop {
+                    reaper.heartbeat().await;
+                    reaper.reap(timeout, policy).await;
+                    if tokio::time::timeout(interval, token.cancelled()).await.is_ok() {
+                        break;
+                    }
+                }
+            });
+        }
 
         let mut handles = vec![];
+        let remaining = Arc::new(AtomicUsize::new(self.worker_count));
 
         // Start workers
-        for worker in 0..self.worker_count {
+        for index in 0..self.worker_count {
             let queue = self.queue.clone();
-            let store = self.store.clone();
-            let name = self.name.clone();
+            let worker = self.worker(index);
             let started = self.started.clone();
+            let remaining = remaining.clone();
+            let token = token.clone();
             *started.write().await = true;
             let handle = tokio::spawn(async move {
-                while *started.read().await {
-                    let task = queue.pop().await;
+                loop {
+                    let queued = worker.next(&queue).await;
 
-                    if task.name() == "stop" {
+                    // Each worker consumes its own stop task
+                    if queued.task.name() == "stop" {
                         *started.write().await = false;
-                    } else {
-                        // Update task state to 'running'
-                        if let Some(err) = store
-                            .update_status(task.as_ref(), TaskStatus::Running)
-                            .await
-                            .err()
-                        {
-                            log::error!(
-                                "failed to update task `{}` with id `{}` state: {}",
-                                task.name(),
-                                task.id(),
-                                err.to_string()
-                            );
-                        }
-
-                        log::info!(
-                            "starting task `{}` with id `{}` on task manager `{}`, worker: {}",
-                            task.name(),
-                            task.id(),
-                            name,
-                            worker
-                        );
-
-                        // Run task
-                        task.run().await;
-
-                        log::info!(
-                            "finished task `{}` with id `{}` on task manager `{}`, worker: {}",
-                            task.name(),
-                            task.id(),
-                            name,
-                            worker
-                        );
-
-                        // Clear task state
-                        if let Some(err) = store.delete_state(task.as_ref()).await.err() {
-                            log::error!(
-                                "failed to clear task `{}` with id `{}` state: {}",
-                                task.name(),
-                                task.id(),
-                                err.to_string()
-                            );
+                        // Leadership is kept until the tasks queued before the stop are run
+                        if remaining.fetch_sub(1, Ordering::SeqCst) == 1 {
+                            token.cancel();
+                            worker.election.resign(&worker.manager, worker.store.as_ref()).await;
                         }
+                        break;
                     }
+
+                    worker.execute(queued).await;
                 }
             });
             handles.push(handle);
@@ -206,14 +559,142 @@ impl<S: TaskStore + 'static> TaskManager<S> {
         if join {
             let mut results = Vec::with_capacity(handles.len());
             for handle in handles {
+                #[allow(clippy::unit_arg, reason = "worker results are collected, even if empty for now")]
                 results.push(handle.await.unwrap());
             }
         }
     }
 
+    /// Create a task submitter.
+    fn submitter(&self) -> Submitter<S> {
+        Submitter {
+            manager: self.name.clone(),
+            queue: self.queue.clone(),
+            store: self.store.clone(),
+            stopped: self.stopped.clone(),
+            queue_capacity: self.queue_capacity,
+            shared: self.shared_poll_interval.is_some(),
+            election: self.election.clone(),
+            running: self.running.clone(),
+            deferred: self.deferred.clone(),
+            watchers: self.watchers.clone(),
+            clock: self.clock.clone(),
+            idempotency_windows: self.idempotency_windows.clone(),
+        }
+    }
+
+    /// Create a reaper.
+    fn reaper(&self) -> Reaper<S> {
+        Reaper {
+            manager: self.name.clone(),
+            queue: self.queue.clone(),
+            store: self.store.clone(),
+            clock: self.clock.clone(),
+            registry: self.registry.clone(),
+            shared: self.shared_poll_interval.is_some(),
+        }
+    }
+
+    /// Create a worker.
+    fn worker(&self, index: usize) -> Worker<S> {
+        Worker {
+            index,
+            submitter: Arc::new(self.submitter()),
+            running: self.running.clone(),
+            deferred: self.deferred.clone(),
+            manager: self.name.clone(),
+            host: self.host.clone(),
+            store: self.store.clone(),
+            clock: self.clock.clone(),
+            progress_interval: self.progress_interval,
+            watchers: self.watchers.clone(),
+            registry: self.registry.clone(),
+            shared_poll_interval: self.shared_poll_interval,
+            claim_lease: self.claim_lease,
+            election: self.election.clone(),
+            idempotency_windows: self.idempotency_windows.clone(),
+        }
+    }
+
+    /// Run the next queued task (if any) on the calling task, without started workers.
+    /// Return false if there was no queued task.
+    #[cfg(any(test, feature = "test-util"))]
+    pub(crate) async fn step(&self) -> bool {
+        let worker = self.worker(0);
+        while let Some(queued) = self.queue.try_pop() {
+            // No worker to stop
+            if queued.task.name() != "stop" {
+                worker.execute(queued).await;
+                return true;
+            }
+        }
+        match worker.claim().await {
+            Some(queued) => {
+                worker.execute(queued).await;
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Stop task manager.
+    /// Tasks queued before the stop are still run,
+    /// tasks submitted afterwards are rejected until the task manager is started again.
+    /// The leadership is released once the workers ran the queued tasks.
     pub async fn stop(&self) {
-        self.queue.push(Box::new(StopTask {}));
+        let mut stopped = self.stopped.write().await;
+        if *stopped {
+            return;
+        }
+        *stopped = true;
+        // Otherwise, the last worker to stop gives the background loops and the leadership up
+        if self.worker_count == 0 {
+            if let Some(token) = self.background_stop.lock().unwrap().take() {
+                token.cancel();
+            }
+            self.election.resign(&self.name, self.store.as_ref()).await;
+        }
+        for _ in 0..self.worker_count {
+            self.queue.push(QueuedTask {
+                task: Box::new(StopTask {}),
+                root: None,
+                leased: false,
+                attempt: 0,
+            });
+        }
+    }
+
+    /// Re-queue the tasks of the stored states which are neither queued nor running,
+    /// re-created by the registry (with a shared queue, they are only set back to pending).
+    /// States of the tasks which cannot be re-created are cleared.
+    async fn recover(&self, registry: &Arc<TaskRegistry>) {
+        let running: HashSet<_> = self.running.lock().await.keys().cloned().collect();
+        let reaper = self.reaper();
+        let mut recovered = 0;
+        for state in self.get_all_states().await {
+            let key = (state.task_name.clone(), state.task_id.clone());
+            if self.queue.contains(&key) || running.contains(&key) {
+                continue;
+            }
+            if reaper.requeue(registry, state).await {
+                recovered += 1;
+            }
+        }
+        log::info!(
+            "task manager `{}` recovered {} task(s)",
+            self.name,
+            recovered
+        );
+    }
+
+    /// Reap the other instances of the task manager without heartbeat for longer than the timeout
+    /// set with [`TaskManager::with_heartbeat`], handling their tasks according to the orphan policy.
+    /// Return the number of task states taken over.
+    pub async fn reap_orphans(&self) -> usize {
+        match self.heartbeat {
+            Some((_, timeout)) => self.reaper().reap(timeout, self.orphan_policy).await,
+            None => 0,
+        }
     }
 
     /// Clear task manager task states.
@@ -229,6 +710,33 @@ impl<S: TaskStore + 'static> TaskManager<S> {
 
     /// Get task manager state
     pub async fn get_state(&self) -> Vec<TaskState> {
+        self.get_all_states().await
+    }
+
+    /// Get the states of a pipeline: the given task and all its (direct or indirect) follow-up tasks.
+    /// The pipeline is finished once no state is left.
+    pub async fn pipeline_states(&self, root: &dyn Task) -> Vec<TaskState> {
+        let (name, id) = (root.name(), root.id());
+        self.get_all_states()
+            .await
+            .into_iter()
+            .filter(|state| {
+                (state.task_name == name && state.task_id == id)
+                    || (state.root_name.as_ref() == Some(&name) && state.root_id.as_ref() == Some(&id))
+            })
+            .collect()
+    }
+
+    /// Get the states of the pending and running tasks of a batch.
+    pub async fn batch_states(&self, id: &str) -> Vec<TaskState> {
+        self.get_all_states()
+            .await
+            .into_iter()
+            .filter(|state| state.batch_id.as_deref() == Some(id))
+            .collect()
+    }
+
+    async fn get_all_states(&self) -> Vec<TaskState> {
         match self.store.get_all_states().await {
             Ok(states) => states,
             Err(err) => {
@@ -242,3 +750,700 @@ impl<S: TaskStore + 'static> TaskManager<S> {
         }
     }
 }
+
+/// Recovery of the task states left by a previous run, or by dead instances.
+struct Reaper<S>
+where
+    S: TaskStore,
+{
+    manager: String,
+    queue: Arc<TaskQueue>,
+    store: Arc<S>,
+    clock: Arc<dyn Clock>,
+    registry: Option<Arc<TaskRegistry>>,
+    /// Set when the queue is shared: recovered tasks are only set back to pending.
+    shared: bool,
+}
+
+impl<S: TaskStore + 'static> Reaper<S> {
+    /// Record that this instance is alive.
+    async fn heartbeat(&self) {
+        if let Some(err) = self.store.heartbeat().await.err() {
+            log::error!(
+                "task manager `{}` failed to send heartbeat: {}",
+                self.manager,
+                err.to_string()
+            );
+        }
+    }
+
+    /// Take over the task states of the dead instances, and handle them according to the policy.
+    /// Return the number of task states taken over.
+    async fn reap(&self, timeout: Duration, policy: OrphanPolicy) -> usize {
+        let instances = match self.store.dead_instances(timeout).await {
+            Ok(instances) => instances,
+            Err(err) => {
+                log::error!(
+                    "task manager `{}` failed to check instance heartbeats: {}",
+                    self.manager,
+                    err.to_string()
+                );
+                return 0;
+            }
+        };
+        let mut count = 0;
+        for instance in instances {
+            let states = match self.store.adopt_instance(&instance).await {
+                Ok(states) => states,
+                Err(err) => {
+                    log::error!(
+                        "task manager `{}` failed to take over dead instance `{}` tasks: {}",
+                        self.manager,
+                        instance,
+                        err.to_string()
+                    );
+                    continue;
+                }
+            };
+            log::warn!(
+                "task manager `{}` took over {} task(s) of dead instance `{}`",
+                self.manager,
+                states.len(),
+                instance
+            );
+            count += states.len();
+            for state in states {
+                match (policy, &self.registry) {
+                    (OrphanPolicy::Reassign, Some(registry)) => {
+                        self.requeue(registry, state).await;
+                    }
+                    _ => {
+                        let task_ref = TaskRef {
+                            name: state.task_name,
+                            id: state.task_id,
+                        };
+                        self.delete(&task_ref).await;
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    /// Re-create the task of a state and set it back to pending, queued locally unless the queue is shared.
+    /// The state is deleted if the task cannot be re-created.
+    /// Return true if the task was recovered.
+    async fn requeue(&self, registry: &TaskRegistry, state: TaskState) -> bool {
+        let task_ref = TaskRef {
+            name: state.task_name.clone(),
+            id: state.task_id.clone(),
+        };
+        match registry.restore(&state) {
+            Ok(Some(task)) => {
+                let update = StateUpdate::status(TaskStatus::Pending, to_millis(self.clock.now()));
+                if let Some(err) = self.store.update_state(&task_ref, update).await.err() {
+                    log::error!(
+                        "failed to update task `{}` with id `{}` state: {}",
+                        task_ref.name,
+                        task_ref.id,
+                        err.to_string()
+                    );
+                }
+                if !self.shared {
+                    let root = state.root_name.zip(state.root_id).map(|(name, id)| TaskRef { name, id });
+                    self.queue.push(QueuedTask {
+                        task,
+                        root,
+                        leased: false,
+                        attempt: state.attempt,
+                    });
+                }
+                return true;
+            }
+            Ok(None) => log::warn!(
+                "task `{}` with id `{}` cannot be recovered, no payload or registered factory",
+                task_ref.name,
+                task_ref.id
+            ),
+            Err(err) => log::error!(
+                "failed to recover task `{}` with id `{}`: {}",
+                task_ref.name,
+                task_ref.id,
+                err
+            ),
+        }
+        self.delete(&task_ref).await;
+        false
+    }
+
+    async fn delete(&self, task: &TaskRef) {
+        if let Some(err) = self.store.delete_state(task).await.err() {
+            log::error!(
+                "failed to clear task `{}` with id `{}` state: {}",
+                task.name,
+                task.id,
+                err.to_string()
+            );
+        }
+    }
+}
+
+/// Task manager worker.
+struct Worker<S>
+where
+    S: TaskStore,
+{
+    /// Worker index.
+    index: usize,
+    /// Task manager name.
+    manager: String,
+    /// Host running the worker.
+    host: String,
+    store: Arc<S>,
+    clock: Arc<dyn Clock>,
+    progress_interval: Duration,
+    submitter: Arc<dyn Submit>,
+    running: Arc<Mutex<RunningTasks>>,
+    deferred: Arc<Mutex<DeferredTasks>>,
+    watchers: Arc<Watchers>,
+    registry: Option<Arc<TaskRegistry>>,
+    shared_poll_interval: Option<Duration>,
+    claim_lease: Duration,
+    election: Arc<Election>,
+    idempotency_windows: Arc<HashMap<String, Duration>>,
+}
+
+impl<S: TaskStore + 'static> Worker<S> {
+    /// Current time in milliseconds.
+    fn now(&self) -> u64 {
+        to_millis(self.clock.now())
+    }
+
+    /// Wait for the next task to run: queued locally, or claimed from the store when the queue is shared.
+    async fn next(&self, queue: &TaskQueue) -> QueuedTask {
+        let Some(poll_interval) = self.shared_poll_interval else {
+            return queue.pop().await;
+        };
+        loop {
+            if let Some(queued) = queue.try_pop() {
+                return queued;
+            }
+            if let Some(queued) = self.claim().await {
+                return queued;
+            }
+            if let Ok(queued) = tokio::time::timeout(poll_interval, queue.pop()).await {
+                return queued;
+            }
+        }
+    }
+
+    /// Claim a pending task from the store, and re-create it.
+    async fn claim(&self) -> Option<QueuedTask> {
+        let registry = self.registry.as_ref()?;
+        self.shared_poll_interval?;
+        let names: Vec<String> = registry
+            .names()
+            .into_iter()
+            .filter(|name| self.election.may_run(name))
+            .collect();
+        if names.is_empty() {
+            return None;
+        }
+        let state = match self.store.claim_pending(&names, self.claim_lease).await {
+            Ok(state) => state?,
+            Err(err) => {
+                log::error!(
+                    "task manager `{}` failed to claim a pending task: {}",
+                    self.manager,
+                    err.to_string()
+                );
+                return None;
+            }
+        };
+        let task_ref = TaskRef {
+            name: state.task_name.clone(),
+            id: state.task_id.clone(),
+        };
+        match registry.restore(&state) {
+            Ok(Some(task)) => {
+                let root = state.root_name.zip(state.root_id).map(|(name, id)| TaskRef { name, id });
+                return Some(QueuedTask {
+                    task,
+                    root,
+                    leased: true,
+                    attempt: state.attempt,
+                });
+            }
+            Ok(None) => log::warn!(
+                "claimed task `{}` with id `{}` cannot be re-created",
+                task_ref.name,
+                task_ref.id
+            ),
+            Err(err) => log::error!(
+                "failed to re-create claimed task `{}` with id `{}`: {}",
+                task_ref.name,
+                task_ref.id,
+                err
+            ),
+        }
+        if let Some(err) = self.store.delete_state(&task_ref).await.err() {
+            log::error!(
+                "failed to clear task `{}` with id `{}` state: {}",
+                task_ref.name,
+                task_ref.id,
+                err.to_string()
+            );
+        }
+        None
+    }
+
+    /// Renew the lease of a claimed task until aborted.
+    /// If the lease was lost, the task is cancelled and the given flag is set.
+    fn keep_lease(
+        &self,
+        task: TaskRef,
+        cancellation: CancellationToken,
+        lost: Arc<AtomicBool>,
+    ) -> tokio::task::JoinHandle<()> {
+        let store = self.store.clone();
+        let lease = self.claim_lease;
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(lease / 3).await;
+                match store.renew_lease(&task, lease).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        log::warn!(
+                            "lost the lease of task `{}` with id `{}`, cancelling it",
+                            task.name,
+                            task.id
+                        );
+                        lost.store(true, Ordering::SeqCst);
+                        cancellation.cancel();
+                        return;
+                    }
+                    Err(err) => log::error!(
+                        "failed to renew the lease of task `{}` with id `{}`: {}",
+                        task.name,
+                        task.id,
+                        err.to_string()
+                    ),
+                }
+            }
+        })
+    }
+
+    /// Run a queued task, part of the given pipeline (if any), tracking its state.
+    async fn execute(&self, queued: QueuedTask) {
+        let QueuedTask {
+            task,
+            root,
+            leased,
+            attempt,
+        } = queued;
+        let attempt = attempt + 1;
+        let task = task.as_ref();
+
+        // Leadership may have been lost since the task was queued
+        if !self.election.may_run(&task.name()) {
+            log::warn!(
+                "task `{}` with id `{}` not run, task manager `{}` instance is no longer the leader",
+                task.name(),
+                task.id(),
+                self.manager
+            );
+            if let Some(err) = self.store.delete_state(task).await.err() {
+                log::error!(
+                    "failed to clear task `{}` with id `{}` state: {}",
+                    task.name(),
+                    task.id(),
+                    err.to_string()
+                );
+            }
+            self.watchers.finished(task, &TaskOutcome::Cancelled).await;
+            return;
+        }
+
+        // Update task state to 'running'
+        let started_time = self.now();
+        let update = StateUpdate {
+            status: Some(TaskStatus::Running),
+            started_time: Some(started_time),
+            worker: Some(self.index),
+            host: Some(self.host.clone()),
+            attempt: Some(attempt),
+            time: started_time,
+            ..Default::default()
+        };
+        if let Some(err) = self.store.update_state(task, update).await.err() {
+            log::error!(
+                "failed to update task `{}` with id `{}` state: {}",
+                task.name(),
+                task.id(),
+                err.to_string()
+            );
+        }
+
+        log::info!(
+            "starting task `{}` with id `{}` on task manager `{}`, worker: {}",
+            task.name(),
+            task.id(),
+            self.manager,
+            self.index
+        );
+        self.watchers.started(task).await;
+
+        // Run task
+        let progress = ProgressReporter::new(
+            Arc::new(StoreStateWriter {
+                store: self.store.clone(),
+                task: TaskRef::of(task),
+            }),
+            self.clock.clone(),
+            self.progress_interval,
+        );
+        let cancellation = CancellationToken::new();
+        let key = (task.name(), task.id());
+        self.running
+            .lock()
+            .await
+            .insert(key.clone(), cancellation.clone());
+        let lease_lost = Arc::new(AtomicBool::new(false));
+        let renewal = leased.then(|| {
+            self.keep_lease(TaskRef::of(task), cancellation.clone(), lease_lost.clone())
+        });
+        let ctx = TaskContext {
+            manager: self.manager.clone(),
+            worker: self.index,
+            attempt,
+            cancellation,
+            progress: progress.clone(),
+            handle: ManagerHandle::new(self.submitter.clone(), Some(Lineage::of(task, root))),
+            failure: sync::Mutex::new(None),
+            locks: Locks::new(Arc::new(StoreLocker {
+                store: self.store.clone(),
+            })),
+        };
+        task.run(&ctx).await;
+        // Deferred tasks are taken along with the running key, so none is left behind
+        let deferred = {
+            let mut deferred = self.deferred.lock().await;
+            self.running.lock().await.remove(&key);
+            deferred.remove(&key)
+        };
+        if let Some(renewal) = renewal {
+            renewal.abort();
+            // The lease may have expired since its last renewal
+            if let Ok(false) = self.store.renew_lease(task, self.claim_lease).await {
+                lease_lost.store(true, Ordering::SeqCst);
+            }
+        }
+        let outcome = ctx.outcome();
+        let finished_time = self.now();
+
+        log::info!(
+            "finished task `{}` with id `{}` on task manager `{}`, worker: {}, in {}ms",
+            task.name(),
+            task.id(),
+            self.manager,
+            self.index,
+            finished_time.saturating_sub(started_time)
+        );
+        if let TaskOutcome::Failed(message) = &outcome {
+            log::warn!(
+                "task `{}` with id `{}` failed: {}",
+                task.name(),
+                task.id(),
+                message
+            );
+        }
+
+        // Task may have been claimed again: its state now belongs to another run
+        if lease_lost.load(Ordering::SeqCst) {
+            log::warn!(
+                "task `{}` with id `{}` finished after losing its lease, leaving its state",
+                task.name(),
+                task.id()
+            );
+            self.watchers.finished(task, &outcome).await;
+            self.submit_deferred(deferred).await;
+            return;
+        }
+
+        // Record completion (with the last throttled progress report), then clear task state
+        let mut update = progress.take_pending(finished_time).unwrap_or_default();
+        update.finished_time = Some(finished_time);
+        update.time = finished_time;
+        if let Some(err) = self.store.update_state(task, update).await.err() {
+            log::error!(
+                "failed to update task `{}` with id `{}` state: {}",
+                task.name(),
+                task.id(),
+                err.to_string()
+            );
+        }
+        // Outcome is remembered before the state is cleared, so a resubmission finds either of them
+        if let Some(ttl) = self.idempotency_windows.get(&task.name()) {
+            if outcome != TaskOutcome::Cancelled {
+                if let Some(err) = self.store.record_outcome(task, &outcome, *ttl).await.err() {
+                    log::error!(
+                        "failed to record task `{}` with id `{}` outcome: {}",
+                        task.name(),
+                        task.id(),
+                        err.to_string()
+                    );
+                }
+            }
+        }
+        if let Some(err) = self.store.delete_state(task).await.err() {
+            log::error!(
+                "failed to clear task `{}` with id `{}` state: {}",
+                task.name(),
+                task.id(),
+                err.to_string()
+            );
+        }
+
+        self.watchers.finished(task, &outcome).await;
+        self.submit_deferred(deferred).await;
+    }
+
+    /// Submit the task deferred until the end of a run (if any).
+    async fn submit_deferred(&self, deferred: Option<(Box<dyn Task + Send + Sync>, Submission)>) {
+        let Some((task, submission)) = deferred else {
+            return;
+        };
+        let (name, id) = (task.name(), task.id());
+        let result = self.submitter.submit(task, submission).await;
+        if !result.is_accepted() {
+            log::warn!(
+                "deferred task `{}` with id `{}` rejected: {:?}",
+                name,
+                id,
+                result
+            );
+        }
+    }
+}
+
+/// Task submission to a task manager, shared with task contexts.
+struct Submitter<S>
+where
+    S: TaskStore,
+{
+    manager: String,
+    queue: Arc<TaskQueue>,
+    store: Arc<S>,
+    stopped: Arc<RwLock<bool>>,
+    queue_capacity: Option<usize>,
+    /// Set when the queue is shared: tasks with a payload are left pending in the store.
+    shared: bool,
+    election: Arc<Election>,
+    running: Arc<Mutex<RunningTasks>>,
+    deferred: Arc<Mutex<DeferredTasks>>,
+    watchers: Arc<Watchers>,
+    clock: Arc<dyn Clock>,
+    idempotency_windows: Arc<HashMap<String, Duration>>,
+}
+
+impl<S: TaskStore> Submitter<S> {
+    /// Return the outcome of the task remembered within its idempotency window (if any).
+    async fn remembered_outcome(&self, task: &dyn Task) -> Result<Option<TaskOutcome>, TaskStoreError> {
+        if !self.idempotency_windows.contains_key(&task.name()) {
+            return Ok(None);
+        }
+        self.store.get_outcome(task).await
+    }
+
+    /// Handle a task submitted while a task with the same name and id is known, according to its policy.
+    async fn deduplicate(&self, task: Box<dyn Task + Send + Sync>, submission: Submission) -> RunResult {
+        let (name, id) = (task.name(), task.id());
+        let result = match task.dedup_policy() {
+            DedupPolicy::Reject => RunResult::Duplicate,
+            DedupPolicy::ReplacePending => self.replace_pending(task).await,
+            DedupPolicy::Coalesce => self.coalesce(task.as_ref()).await,
+            DedupPolicy::EnqueueAfterCurrent => {
+                // Checked under the deferred tasks lock, taken by workers before the end of a run
+                let mut deferred = self.deferred.lock().await;
+                let key = (name.clone(), id.clone());
+                if self.running.lock().await.contains_key(&key) {
+                    deferred.insert(key, (task, submission));
+                    RunResult::Deferred
+                } else {
+                    drop(deferred);
+                    self.replace_pending(task).await
+                }
+            }
+        };
+        log::debug!(
+            "task `{}` with id `{}` already exists: {:?}",
+            name,
+            id,
+            result
+        );
+        result
+    }
+
+    /// Replace the pending task with the same name and id, queued locally or left in a shared queue.
+    async fn replace_pending(&self, task: Box<dyn Task + Send + Sync>) -> RunResult {
+        let task_ref = TaskRef::of(task.as_ref());
+        let payload = task.payload();
+        if self.queue.replace(task).is_ok() {
+            return self.update_payload(&task_ref, payload).await;
+        }
+        if !(self.shared && payload.is_some()) {
+            return RunResult::Duplicate;
+        }
+        // Left in the shared queue: replaced only if not claimed meanwhile by an instance
+        let update = StateUpdate {
+            payload,
+            time: to_millis(self.clock.now()),
+            ..Default::default()
+        };
+        match self.store.update_pending_state(&task_ref, update).await {
+            Ok(true) => RunResult::Replaced,
+            Ok(false) => RunResult::Duplicate,
+            Err(err) => RunResult::StoreUnavailable(err),
+        }
+    }
+
+    /// Keep the stored payload of a task replaced in the local queue in line with the task to run.
+    async fn update_payload(&self, task_ref: &TaskRef, payload: Option<String>) -> RunResult {
+        if payload.is_none() {
+            return RunResult::Replaced;
+        }
+        let update = StateUpdate {
+            payload,
+            time: to_millis(self.clock.now()),
+            ..Default::default()
+        };
+        if let Some(err) = self.store.update_state(task_ref, update).await.err() {
+            log::error!(
+                "failed to update task `{}` with id `{}` state: {}",
+                task_ref.name,
+                task_ref.id,
+                err.to_string()
+            );
+        }
+        RunResult::Replaced
+    }
+
+    /// Attach to the run of the pending or running task with the same name and id, run by this instance.
+    async fn coalesce(&self, task: &dyn Task) -> RunResult {
+        let run = TaskRun::new();
+        let watcher: Arc<dyn Watcher> = run.clone();
+        // Watch the run before looking for it, so its end cannot be missed
+        self.watchers.register(task, watcher.clone()).await;
+        let key = (task.name(), task.id());
+        if self.queue.contains(&key) || self.running.lock().await.contains_key(&key) {
+            return RunResult::Coalesced(TaskHandle::new(TaskRef::of(task), run));
+        }
+        self.watchers.unregister(task, &watcher).await;
+        RunResult::Duplicate
+    }
+}
+
+#[async_trait]
+impl<S: TaskStore> Submit for Submitter<S> {
+    async fn submit(&self, task: Box<dyn Task + Send + Sync>, submission: Submission) -> RunResult {
+        // Check manager and queue
+        if *self.stopped.read().await {
+            log::debug!(
+                "task `{}` with id `{}` rejected, task manager `{}` is stopped",
+                task.name(),
+                task.id(),
+                self.manager
+            );
+            return RunResult::ManagerStopped;
+        }
+        if let Some(capacity) = self.queue_capacity {
+            if self.queue.len() >= capacity {
+                log::warn!(
+                    "task `{}` with id `{}` rejected, task manager `{}` queue is full",
+                    task.name(),
+                    task.id(),
+                    self.manager
+                );
+                return RunResult::QueueFull;
+            }
+        }
+
+        let payload = task.payload();
+        let claimable = payload.is_some();
+        // Leader-only tasks are left to be claimed by the leader from a shared queue
+        if !(self.election.may_run(&task.name()) || (self.shared && claimable)) {
+            log::debug!(
+                "task `{}` with id `{}` rejected, task manager `{}` instance is not the leader",
+                task.name(),
+                task.id(),
+                self.manager
+            );
+            return RunResult::NotLeader;
+        }
+
+        // Tasks completed within their idempotency window are not run again
+        match self.remembered_outcome(task.as_ref()).await {
+            Ok(None) => {}
+            Ok(Some(outcome)) => {
+                log::debug!(
+                    "task `{}` with id `{}` rejected, already completed: {:?}",
+                    task.name(),
+                    task.id(),
+                    outcome
+                );
+                return RunResult::Completed(outcome);
+            }
+            Err(err) => {
+                log::error!(
+                    "failed to read task `{}` with id `{}` outcome: {}",
+                    task.name(),
+                    task.id(),
+                    err.to_string()
+                );
+                return RunResult::StoreUnavailable(err);
+            }
+        }
+
+        // Add task state to store, unless task is already known
+        let mut initial = submission.initial_state();
+        initial.payload = payload;
+        match self.store.try_insert_state_with(task.as_ref(), initial).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return self.deduplicate(task, submission).await,
+            Err(err) => {
+                log::error!(
+                    "failed to save task `{}` with id `{}` state: {}",
+                    task.name(),
+                    task.id(),
+                    err.to_string()
+                );
+                return RunResult::StoreUnavailable(err);
+            }
+        };
+
+        // Task may have completed between the outcome check and the insert
+        if let Ok(Some(outcome)) = self.remembered_outcome(task.as_ref()).await {
+            if let Some(err) = self.store.delete_state(task.as_ref()).await.err() {
+                log::error!(
+                    "failed to clear task `{}` with id `{}` state: {}",
+                    task.name(),
+                    task.id(),
+                    err.to_string()
+                );
+            }
+            return RunResult::Completed(outcome);
+        }
+
+        // Add task to queue, unless it is left to be claimed from the store
+        if self.shared && claimable {
+            return RunResult::Accepted;
+        }
+        self.queue.push(QueuedTask {
+            task,
+            root: submission.lineage.map(|lineage| lineage.root),
+            leased: false,
+            attempt: 0,
+        });
+        RunResult::Accepted
+    }
+}
@@ -3,7 +3,18 @@ use std::{sync::Arc, time::Duration};
 use async_trait::async_trait;
 use tokio::{sync::RwLock, time::sleep};
 
-use crate::{manager::TaskManager, store::memory::InMemoryTaskStore, task::Task};
+use crate::{
+    context::TaskContext,
+    manager::{OrphanPolicy, RunResult, TaskManager},
+    registry::TaskRegistry,
+    store::{
+        memory::InMemoryTaskStore,
+        state::{StateUpdate, TaskState, TaskStatus},
+        TaskStore, TaskStoreError,
+    },
+    task::{Task, TaskOutcome, TaskRef},
+    testing::{RecordingTaskStore, TransitionKind, VirtualClock},
+};
 
 struct TestTask {
     pub id: String,
@@ -21,7 +32,7 @@ impl Task for TestTask {
         self.id.clone()
     }
 
-    async fn run(&self) {
+    async fn run(&self, _ctx: &TaskContext) {
         sleep(Duration::from_millis(self.sleep_millis)).await;
         self.results.write().await.push(self.id.clone());
     }
@@ -137,4 +148,526 @@ async fn get_state() {
 
     assert_eq!(state.len(), 3);
     
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn run_duplicate() {
+    let results = Arc::new(RwLock::new(vec![]));
+
+    let manager = TaskManager::new(InMemoryTaskStore::new("manager"), 2);
+
+    let mut accepted = 0;
+    for _ in 0..3 {
+        let result = manager
+            .run(Box::new(TestTask {
+                id: "1".to_string(),
+                sleep_millis: 5,
+                results: results.clone(),
+            }))
+            .await;
+        if result.is_accepted() {
+            accepted += 1;
+        } else {
+            assert!(matches!(result, RunResult::Duplicate));
+        }
+    }
+    assert_eq!(accepted, 1);
+
+    manager.stop().await;
+
+    manager.start_blocking().await;
+
+    assert_eq!(results.read().await.len(), 1);
+}
+
+#[tokio::test]
+async fn run_queue_full() {
+    let results = Arc::new(RwLock::new(vec![]));
+
+    let manager = TaskManager::new(InMemoryTaskStore::new("manager"), 1).with_queue_capacity(1);
+
+    let result = manager
+        .run(Box::new(TestTask {
+            id: "1".to_string(),
+            sleep_millis: 5,
+            results: results.clone(),
+        }))
+        .await;
+    assert!(matches!(result, RunResult::Accepted));
+    let result = manager
+        .run(Box::new(TestTask {
+            id: "2".to_string(),
+            sleep_millis: 5,
+            results: results.clone(),
+        }))
+        .await;
+    assert!(matches!(result, RunResult::QueueFull));
+    assert_eq!(manager.get_state().await.len(), 1);
+}
+
+#[tokio::test]
+async fn run_stopped() {
+    let results = Arc::new(RwLock::new(vec![]));
+
+    let manager = TaskManager::new(InMemoryTaskStore::new("manager"), 2);
+
+    manager
+        .run(Box::new(TestTask {
+            id: "1".to_string(),
+            sleep_millis: 5,
+            results: results.clone(),
+        }))
+        .await;
+
+    manager.stop().await;
+
+    let result = manager
+        .run(Box::new(TestTask {
+            id: "2".to_string(),
+            sleep_millis: 5,
+            results: results.clone(),
+        }))
+        .await;
+    assert!(matches!(result, RunResult::ManagerStopped));
+
+    manager.start_blocking().await;
+
+    assert_eq!(results.read().await.len(), 1);
+    assert_eq!(results.read().await[0], "1");
+}
+
+/// Task waiting to be cancelled, then recording its context.
+struct CancellableTask {
+    pub results: Arc<RwLock<Vec<String>>>,
+}
+
+#[async_trait]
+impl Task for CancellableTask {
+    fn name(&self) -> String {
+        "cancellable_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        "1".to_string()
+    }
+
+    async fn run(&self, ctx: &TaskContext) {
+        ctx.cancellation_token().cancelled().await;
+        self.results.write().await.push(format!(
+            "{}:{}:{}:{}",
+            ctx.manager_name(),
+            ctx.worker(),
+            ctx.attempt(),
+            ctx.is_cancelled()
+        ));
+    }
+}
+
+#[tokio::test]
+async fn cancel() {
+    let results = Arc::new(RwLock::new(vec![]));
+
+    let manager = TaskManager::new(InMemoryTaskStore::new("manager"), 1);
+    let task = CancellableTask {
+        results: results.clone(),
+    };
+    assert!(!manager.cancel(&task).await);
+    manager
+        .run(Box::new(CancellableTask {
+            results: results.clone(),
+        }))
+        .await;
+    manager.stop().await;
+
+    // Cancel once the task is running
+    tokio::join!(manager.start_blocking(), async {
+        while !manager.cancel(&task).await {
+            tokio::task::yield_now().await;
+        }
+    });
+
+    assert_eq!(*results.read().await, vec!["manager:0:1:true"]);
+    assert!(!manager.cancel(&task).await);
+}
+
+/// Task with a payload, recording the instance running it.
+struct SharedTask {
+    pub id: String,
+    pub instance: String,
+    pub results: Arc<RwLock<Vec<String>>>,
+}
+
+#[async_trait]
+impl Task for SharedTask {
+    fn name(&self) -> String {
+        "shared_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn run(&self, ctx: &TaskContext) {
+        sleep(Duration::from_millis(10)).await;
+        self.results
+            .write()
+            .await
+            .push(format!("{}:{}:{}", self.instance, self.id, ctx.attempt()));
+    }
+
+    fn payload(&self) -> Option<String> {
+        Some(self.id.clone())
+    }
+}
+
+/// Task manager instance sharing its queue through the given store.
+fn shared_instance(
+    store: &InMemoryTaskStore,
+    instance: &str,
+    results: &Arc<RwLock<Vec<String>>>,
+) -> TaskManager<InMemoryTaskStore> {
+    let instance = instance.to_string();
+    let results = results.clone();
+    let registry = TaskRegistry::new().register("shared_task", move |payload| {
+        Ok(Box::new(SharedTask {
+            id: payload.to_string(),
+            instance: instance.clone(),
+            results: results.clone(),
+        }) as Box<dyn Task + Send + Sync>)
+    });
+    TaskManager::new(store.clone(), 1).with_shared_queue(registry, Duration::from_millis(5))
+}
+
+#[tokio::test]
+async fn claim_from_shared_queue() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let store = InMemoryTaskStore::new("manager");
+    let a = shared_instance(&store, "a", &results);
+    let b = shared_instance(&store, "b", &results);
+
+    // Submitted on a, claimed by b
+    let submitted = SharedTask {
+        id: "1".to_string(),
+        instance: "submitter".to_string(),
+        results: results.clone(),
+    };
+    assert!(a.run(Box::new(submitted)).await.is_accepted());
+    assert!(matches!(
+        a.run(Box::new(SharedTask {
+            id: "1".to_string(),
+            instance: "submitter".to_string(),
+            results: results.clone(),
+        }))
+        .await,
+        RunResult::Duplicate
+    ));
+    assert!(b.step().await);
+    assert!(!a.step().await);
+    assert_eq!(*results.read().await, vec!["b:1:1"]);
+    assert_eq!(store.count_tasks().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn spread_shared_queue() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let store = InMemoryTaskStore::new("manager");
+    let a = shared_instance(&store, "a", &results);
+    let b = shared_instance(&store, "b", &results);
+    a.start().await;
+    b.start().await;
+
+    for i in 0..10 {
+        a.run(Box::new(SharedTask {
+            id: i.to_string(),
+            instance: "submitter".to_string(),
+            results: results.clone(),
+        }))
+        .await;
+    }
+    for _ in 0..100 {
+        if results.read().await.len() == 10 {
+            break;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    a.stop().await;
+    b.stop().await;
+
+    // Each task ran once, on both instances
+    let results = results.read().await;
+    let mut ids: Vec<_> = results.iter().map(|r| r.split(':').nth(1).unwrap()).collect();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), 10);
+    assert_eq!(results.len(), 10);
+    assert!(results.iter().any(|r| r.starts_with("a:")));
+    assert!(results.iter().any(|r| r.starts_with("b:")));
+}
+
+#[tokio::test]
+async fn reclaim_expired_lease() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let clock = VirtualClock::new();
+    let store = InMemoryTaskStore::new("manager").with_clock(Arc::new(clock.clone()));
+    let a = shared_instance(&store, "a", &results);
+    let b = shared_instance(&store, "b", &results);
+    a.run(Box::new(SharedTask {
+        id: "1".to_string(),
+        instance: "submitter".to_string(),
+        results: results.clone(),
+    }))
+    .await;
+
+    // Claimed and started by an instance which never finishes it
+    let names = vec!["shared_task".to_string()];
+    let claimed = store
+        .claim_pending(&names, Duration::from_millis(50))
+        .await
+        .unwrap()
+        .unwrap();
+    let update = StateUpdate {
+        attempt: Some(1),
+        time: claimed.last_update_time,
+        ..Default::default()
+    };
+    let task = TaskRef {
+        name: claimed.task_name,
+        id: claimed.task_id,
+    };
+    store.update_state(&task, update).await.unwrap();
+    assert!(!b.step().await);
+
+    clock.advance(Duration::from_millis(80));
+    assert!(b.step().await);
+    assert_eq!(*results.read().await, vec!["b:1:2"]);
+    assert_eq!(store.count_tasks().await.unwrap(), 0);
+}
+
+/// In memory store holding the states left by a dead instance, adopted on the first reaping.
+#[derive(Clone)]
+struct OrphanStore {
+    inner: InMemoryTaskStore,
+    dead: Arc<std::sync::Mutex<Option<String>>>,
+}
+
+impl OrphanStore {
+    /// Leave a started task with a payload, and one without, on a dead instance.
+    async fn new(results: &Arc<RwLock<Vec<String>>>) -> Self {
+        let inner = InMemoryTaskStore::new("manager");
+        let task = SharedTask {
+            id: "1".to_string(),
+            instance: "dead".to_string(),
+            results: results.clone(),
+        };
+        let initial = StateUpdate {
+            payload: task.payload(),
+            ..Default::default()
+        };
+        inner.try_insert_state_with(&task, initial).await.unwrap();
+        let update = StateUpdate {
+            attempt: Some(1),
+            ..StateUpdate::status(TaskStatus::Running, 0)
+        };
+        inner.update_state(&task, update).await.unwrap();
+        inner
+            .save_state(&TestTask {
+                id: "2".to_string(),
+                sleep_millis: 0,
+                results: results.clone(),
+            })
+            .await
+            .unwrap();
+        Self {
+            inner,
+            dead: Arc::new(std::sync::Mutex::new(Some("dead".to_string()))),
+        }
+    }
+}
+
+#[async_trait]
+impl TaskStore for OrphanStore {
+    fn manager_name(&self) -> String {
+        self.inner.manager_name()
+    }
+
+    async fn init(&self) -> Result<(), TaskStoreError> {
+        self.inner.init().await
+    }
+
+    async fn save_state(&self, task: &dyn Task) -> Result<TaskState, TaskStoreError> {
+        self.inner.save_state(task).await
+    }
+
+    async fn try_insert_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        self.inner.try_insert_state(task).await
+    }
+
+    async fn delete_state(&self, task: &dyn Task) -> Result<(), TaskStoreError> {
+        self.inner.delete_state(task).await
+    }
+
+    async fn get_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        self.inner.get_state(task).await
+    }
+
+    async fn count_tasks(&self) -> Result<usize, TaskStoreError> {
+        self.inner.count_tasks().await
+    }
+
+    async fn update_status(&self, task: &dyn Task, status: TaskStatus) -> Result<(), TaskStoreError> {
+        self.inner.update_status(task, status).await
+    }
+
+    async fn update_state(&self, task: &dyn Task, update: StateUpdate) -> Result<(), TaskStoreError> {
+        self.inner.update_state(task, update).await
+    }
+
+    async fn clear(&self) -> Result<(), TaskStoreError> {
+        self.inner.clear().await
+    }
+
+    async fn get_all_states(&self) -> Result<Vec<TaskState>, TaskStoreError> {
+        self.inner.get_all_states().await
+    }
+
+    async fn dead_instances(&self, _timeout: Duration) -> Result<Vec<String>, TaskStoreError> {
+        Ok(self.dead.lock().unwrap().iter().cloned().collect())
+    }
+
+    async fn adopt_instance(&self, instance: &str) -> Result<Vec<TaskState>, TaskStoreError> {
+        if self.dead.lock().unwrap().take_if(|dead| dead == instance).is_none() {
+            return Ok(vec![]);
+        }
+        let mut states = self.inner.get_all_states().await?;
+        for state in states.iter_mut() {
+            let task = TaskRef {
+                name: state.task_name.clone(),
+                id: state.task_id.clone(),
+            };
+            self.inner.update_status(&task, TaskStatus::Pending).await?;
+            state.status = TaskStatus::Pending;
+        }
+        Ok(states)
+    }
+}
+
+/// Task manager reaping the orphans of the given store with the given policy.
+fn reaping_manager(
+    store: &RecordingTaskStore<OrphanStore>,
+    policy: OrphanPolicy,
+    results: &Arc<RwLock<Vec<String>>>,
+) -> TaskManager<RecordingTaskStore<OrphanStore>> {
+    let results = results.clone();
+    let registry = TaskRegistry::new().register("shared_task", move |payload| {
+        Ok(Box::new(SharedTask {
+            id: payload.to_string(),
+            instance: "live".to_string(),
+            results: results.clone(),
+        }) as Box<dyn Task + Send + Sync>)
+    });
+    TaskManager::new(store.clone(), 1)
+        .with_registry(registry)
+        .with_heartbeat(Duration::from_secs(10), Duration::from_secs(30))
+        .with_orphan_policy(policy)
+}
+
+#[tokio::test]
+async fn reassign_orphans() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let store = RecordingTaskStore::new(OrphanStore::new(&results).await, Arc::new(VirtualClock::new()));
+    let manager = reaping_manager(&store, OrphanPolicy::Reassign, &results);
+    assert_eq!(manager.reap_orphans().await, 2);
+    assert_eq!(manager.reap_orphans().await, 0);
+
+    // Task with a payload runs again, the other one was cleared
+    assert!(manager.step().await);
+    assert!(!manager.step().await);
+    assert_eq!(*results.read().await, vec!["live:1:2"]);
+    assert_eq!(store.count_tasks().await.unwrap(), 0);
+    let cleared: Vec<_> = store
+        .transitions()
+        .into_iter()
+        .filter(|t| t.task_id == "2")
+        .map(|t| t.kind)
+        .collect();
+    assert_eq!(
+        cleared,
+        vec![TransitionKind::Status(TaskStatus::Pending), TransitionKind::Deleted]
+    );
+}
+
+#[tokio::test]
+async fn delete_orphans() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let store = RecordingTaskStore::new(OrphanStore::new(&results).await, Arc::new(VirtualClock::new()));
+    let manager = reaping_manager(&store, OrphanPolicy::Delete, &results);
+    assert_eq!(manager.reap_orphans().await, 2);
+
+    // Nothing runs, both states were cleared
+    assert!(!manager.step().await);
+    assert!(results.read().await.is_empty());
+    assert_eq!(store.count_tasks().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn leader_only_tasks() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let manager = TaskManager::new(InMemoryTaskStore::new("manager"), 1)
+        .with_leader_only(&["test_task"], Duration::from_secs(30));
+    let task = |id: &str| {
+        Box::new(TestTask {
+            id: id.to_string(),
+            sleep_millis: 0,
+            results: results.clone(),
+        })
+    };
+
+    // Leadership is only acquired once started
+    assert!(!manager.is_leader());
+    assert!(matches!(manager.run(task("1")).await, RunResult::NotLeader));
+
+    // Tasks accepted before the stop are run before the leadership is released
+    tokio::join!(manager.start_blocking(), async {
+        while !manager.is_leader() {
+            tokio::task::yield_now().await;
+        }
+        assert!(manager.run(task("2")).await.is_accepted());
+        assert!(manager.run(task("3")).await.is_accepted());
+        manager.stop().await;
+    });
+    assert!(!manager.is_leader());
+    assert_eq!(*results.read().await, vec!["2", "3"]);
+}
+
+#[tokio::test]
+async fn idempotency_window() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let clock = VirtualClock::new();
+    let store = InMemoryTaskStore::new("manager").with_clock(Arc::new(clock.clone()));
+    let manager = TaskManager::new(store, 1)
+        .with_clock(Arc::new(clock.clone()))
+        .with_idempotency_window("test_task", Duration::from_millis(100));
+    let task = |id: &str| {
+        Box::new(TestTask {
+            id: id.to_string(),
+            sleep_millis: 0,
+            results: results.clone(),
+        })
+    };
+
+    assert!(manager.run(task("1")).await.is_accepted());
+    assert!(manager.step().await);
+    assert!(matches!(
+        manager.run(task("1")).await,
+        RunResult::Completed(TaskOutcome::Succeeded)
+    ));
+    assert!(manager.run(task("2")).await.is_accepted());
+    assert!(manager.step().await);
+    assert!(!manager.step().await);
+
+    // Run again once the window elapsed
+    clock.advance(Duration::from_millis(150));
+    assert!(manager.run(task("1")).await.is_accepted());
+    assert!(manager.step().await);
+    assert_eq!(*results.read().await, vec!["1", "2", "1"]);
+}
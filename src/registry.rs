@@ -0,0 +1,82 @@
+/*!
+Task registry, to re-create tasks from their stored payloads.
+
+Tasks returning a [`payload`](crate::task::Task::payload) have it stored with their state.
+A task manager given a registry (see [`TaskManager::with_registry`](crate::manager::TaskManager::with_registry))
+re-creates and re-queues, when started, the tasks whose states were left in the store by a previous run.
+*/
+
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{store::state::TaskState, task::Task};
+
+/// Task factory, re-creating a task from its payload.
+pub type TaskFactory =
+    Arc<dyn Fn(&str) -> Result<Box<dyn Task + Send + Sync>, String> + Send + Sync>;
+
+/// Task factories, by task name.
+#[derive(Clone, Default)]
+pub struct TaskRegistry {
+    factories: HashMap<String, TaskFactory>,
+}
+
+impl TaskRegistry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the factory of the tasks with the given name.
+    pub fn register<F>(mut self, name: &str, factory: F) -> Self
+    where
+        F: Fn(&str) -> Result<Box<dyn Task + Send + Sync>, String> + Send + Sync + 'static,
+    {
+        self.factories.insert(name.to_string(), Arc::new(factory));
+        self
+    }
+
+    /// Register the tasks with the given name, deserialized from a JSON payload
+    /// (see [`json_payload`]).
+    #[cfg(feature = "serde")]
+    pub fn register_json<T>(self, name: &str) -> Self
+    where
+        T: serde::de::DeserializeOwned + Task + 'static,
+    {
+        self.register(name, |payload| {
+            serde_json::from_str::<T>(payload)
+                .map(|task| Box::new(task) as Box<dyn Task + Send + Sync>)
+                .map_err(|err| err.to_string())
+        })
+    }
+
+    /// Return the names of the tasks which can be re-created.
+    pub fn names(&self) -> Vec<String> {
+        self.factories.keys().cloned().collect()
+    }
+
+    /// Return true if tasks with the given name can be re-created.
+    pub fn contains(&self, name: &str) -> bool {
+        self.factories.contains_key(name)
+    }
+
+    /// Re-create the task of a state.
+    /// Return None if the state has no payload, or if the task name is not registered.
+    pub fn restore(&self, state: &TaskState) -> Result<Option<Box<dyn Task + Send + Sync>>, String> {
+        match (&state.payload, self.factories.get(&state.task_name)) {
+            (Some(payload), Some(factory)) => factory(payload).map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Serialize a task as a JSON payload, to implement [`Task::payload`].
+#[cfg(feature = "serde")]
+pub fn json_payload<T: serde::Serialize>(task: &T) -> Option<String> {
+    match serde_json::to_string(task) {
+        Ok(payload) => Some(payload),
+        Err(err) => {
+            log::error!("failed to serialize task payload: {}", err);
+            None
+        }
+    }
+}
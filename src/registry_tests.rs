@@ -0,0 +1,172 @@
+use std::{fs, path::PathBuf, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{sync::RwLock, time::sleep};
+
+use crate::{
+    context::TaskContext,
+    manager::TaskManager,
+    registry::TaskRegistry,
+    store::{file::FileTaskStore, TaskStore},
+    task::Task,
+};
+
+/// Task with an optional payload (its id).
+struct PayloadTask {
+    pub id: String,
+    pub persistent: bool,
+    pub results: Arc<RwLock<Vec<String>>>,
+}
+
+#[async_trait]
+impl Task for PayloadTask {
+    fn name(&self) -> String {
+        "payload_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn run(&self, _ctx: &TaskContext) {
+        self.results.write().await.push(self.id.clone());
+    }
+
+    fn payload(&self) -> Option<String> {
+        self.persistent.then(|| self.id.clone())
+    }
+}
+
+fn task(id: &str, persistent: bool, results: &Arc<RwLock<Vec<String>>>) -> Box<PayloadTask> {
+    Box::new(PayloadTask {
+        id: id.to_string(),
+        persistent,
+        results: results.clone(),
+    })
+}
+
+fn registry(results: &Arc<RwLock<Vec<String>>>) -> TaskRegistry {
+    let results = results.clone();
+    TaskRegistry::new().register("payload_task", move |payload| {
+        Ok(task(payload, true, &results) as Box<dyn Task + Send + Sync>)
+    })
+}
+
+/// Return an empty directory for a test.
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join("quartermaster_registry_tests")
+        .join(name);
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Wait until the given number of tasks ran.
+async fn wait_for(results: &Arc<RwLock<Vec<String>>>, count: usize) {
+    for _ in 0..100 {
+        if results.read().await.len() >= count {
+            return;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn recover_after_restart() {
+    let dir = test_dir("recover_after_restart");
+    let results = Arc::new(RwLock::new(vec![]));
+
+    // Tasks submitted, never run
+    let store = FileTaskStore::new("manager", &dir);
+    store.init().await.unwrap();
+    let manager = TaskManager::new(store, 1);
+    assert!(manager.run(task("1", true, &results)).await.is_accepted());
+    assert!(manager.run(task("2", false, &results)).await.is_accepted());
+    drop(manager);
+
+    // Restarted: payload task is re-created and run, other one is cleared
+    let store = FileTaskStore::new("manager", &dir);
+    let manager = TaskManager::new(store.clone(), 1).with_registry(registry(&results));
+    tokio::join!(manager.start_blocking(), async {
+        wait_for(&results, 1).await;
+        manager.stop().await;
+    });
+
+    assert_eq!(*results.read().await, vec!["1"]);
+    assert_eq!(store.count_tasks().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn keep_queued_tasks_on_start() {
+    let dir = test_dir("keep_queued_tasks_on_start");
+    let results = Arc::new(RwLock::new(vec![]));
+    let store = FileTaskStore::new("manager", &dir);
+    store.init().await.unwrap();
+    let manager = TaskManager::new(store.clone(), 1).with_registry(registry(&results));
+
+    // Already queued task is not queued twice
+    assert!(manager.run(task("1", true, &results)).await.is_accepted());
+    let state = store.get_state(&*task("1", true, &results)).await.unwrap().unwrap();
+    assert_eq!(state.payload.as_deref(), Some("1"));
+    // Tasks queued before the stop are run before the workers stop
+    tokio::join!(manager.start_blocking(), async {
+        wait_for(&results, 1).await;
+        manager.stop().await;
+    });
+
+    assert_eq!(*results.read().await, vec!["1"]);
+}
+
+#[cfg(feature = "serde")]
+mod json {
+    use async_trait::async_trait;
+    use serde::{Deserialize, Serialize};
+
+    use crate::{
+        context::TaskContext,
+        registry::{json_payload, TaskRegistry},
+        store::state::TaskState,
+        task::Task,
+    };
+
+    #[derive(Serialize, Deserialize)]
+    struct JsonTask {
+        pub id: String,
+        pub pages: u32,
+    }
+
+    #[async_trait]
+    impl Task for JsonTask {
+        fn name(&self) -> String {
+            "json_task".to_string()
+        }
+
+        fn id(&self) -> String {
+            self.id.clone()
+        }
+
+        async fn run(&self, _ctx: &TaskContext) {}
+
+        fn payload(&self) -> Option<String> {
+            json_payload(self)
+        }
+    }
+
+    #[test]
+    fn restore_json_task() {
+        let registry = TaskRegistry::new().register_json::<JsonTask>("json_task");
+        let task = JsonTask {
+            id: "1".to_string(),
+            pages: 3,
+        };
+        let mut state = TaskState::new(&task, "manager", None, 0);
+        state.payload = task.payload();
+
+        let restored = registry.restore(&state).unwrap().unwrap();
+        assert_eq!(restored.id(), "1");
+        assert_eq!(restored.payload(), task.payload());
+
+        state.payload = Some("{}".to_string());
+        assert!(registry.restore(&state).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{sync::RwLock, time::Instant};
+
+use crate::task::{Task, TaskOutcome, TaskRef};
+
+use super::{StateUpdate, TaskState, TaskStatus, TaskStore, TaskStoreError};
+
+/// Cached task state.
+struct CacheEntry {
+    state: Option<TaskState>,
+    cached_at: Instant,
+    /// Incremented on each invalidation, so that a state read meanwhile is not cached.
+    version: u64,
+}
+
+/// Caching task store.
+/// Decorates a task store to keep the states created by this instance in memory,
+/// so that reads and duplicate submissions of these tasks do not hit the backing store.
+/// Cached states are invalidated on this instance writes (the task stays known as owned),
+/// states of other instances are always read from the backing store.
+/// States with a payload can be claimed by other instances from a shared queue: they are never cached.
+#[derive(Clone)]
+pub struct CachingTaskStore<S>
+where
+    S: TaskStore,
+{
+    inner: S,
+    ttl: Option<Duration>,
+    /// Cache of the states owned by this instance, by task name and id.
+    /// An entry without state means that the state must be read again from the backing store.
+    cache: Arc<RwLock<HashMap<(String, String), CacheEntry>>>,
+}
+
+impl<S: TaskStore> CachingTaskStore<S> {
+    /// Create a new caching task store, decorating the given store.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            ttl: None,
+            cache: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Expire cached states after the given duration.
+    /// Useful when states can be changed by other parties (e.g. an administrator cleaning the store).
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Return the inner store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn key(task: &dyn Task) -> (String, String) {
+        (task.name(), task.id())
+    }
+
+    fn is_fresh(&self, entry: &CacheEntry) -> bool {
+        match self.ttl {
+            Some(ttl) => entry.cached_at.elapsed() < ttl,
+            None => true,
+        }
+    }
+
+    /// Return the cached state of an owned task.
+    /// Return None if the task is not owned, or if its state must be read again.
+    async fn cached(&self, task: &dyn Task) -> Option<TaskState> {
+        let cache = self.cache.read().await;
+        cache
+            .get(&Self::key(task))
+            .filter(|e| self.is_fresh(e))
+            .and_then(|e| e.state.clone())
+    }
+
+    /// Return true if the task state was created by this instance, and not deleted since.
+    async fn owned(&self, task: &dyn Task) -> bool {
+        let cache = self.cache.read().await;
+        cache
+            .get(&Self::key(task))
+            .map(|e| self.is_fresh(e))
+            .unwrap_or(false)
+    }
+
+    /// Cache the state of an owned task.
+    async fn cache(&self, task: &dyn Task, state: Option<TaskState>) {
+        self.cache.write().await.insert(
+            Self::key(task),
+            CacheEntry {
+                state,
+                cached_at: Instant::now(),
+                version: 0,
+            },
+        );
+    }
+
+    /// Invalidate the cached state of a task, the state is read again on next access.
+    async fn invalidate(&self, task: &dyn Task) {
+        if let Some(entry) = self.cache.write().await.get_mut(&Self::key(task)) {
+            entry.state = None;
+            entry.version += 1;
+        }
+    }
+}
+
+#[async_trait]
+impl<S: TaskStore> TaskStore for CachingTaskStore<S> {
+    fn manager_name(&self) -> String {
+        self.inner.manager_name()
+    }
+
+    async fn init(&self) -> Result<(), TaskStoreError> {
+        self.inner.init().await
+    }
+
+    async fn save_state(&self, task: &dyn Task) -> Result<TaskState, TaskStoreError> {
+        if self.owned(task).await {
+            return Err(TaskStoreError::duplicate(task));
+        }
+        let state = self.inner.save_state(task).await?;
+        self.cache(task, Some(state.clone())).await;
+        Ok(state)
+    }
+
+    async fn try_insert_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        self.try_insert_state_with(task, StateUpdate::default()).await
+    }
+
+    async fn try_insert_state_with(
+        &self,
+        task: &dyn Task,
+        initial: StateUpdate,
+    ) -> Result<Option<TaskState>, TaskStoreError> {
+        // Task already owned by this instance: no need to ask the backing store
+        if self.owned(task).await {
+            return Ok(None);
+        }
+        let claimable = initial.payload.is_some();
+        let state = self.inner.try_insert_state_with(task, initial).await?;
+        if let Some(state) = state.as_ref().filter(|_| !claimable) {
+            self.cache(task, Some(state.clone())).await;
+        }
+        Ok(state)
+    }
+
+    async fn delete_state(&self, task: &dyn Task) -> Result<(), TaskStoreError> {
+        self.cache.write().await.remove(&Self::key(task));
+        self.inner.delete_state(task).await
+    }
+
+    async fn get_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        if let Some(state) = self.cached(task).await {
+            return Ok(Some(state));
+        }
+        let key = Self::key(task);
+        let version = self.cache.read().await.get(&key).map(|e| e.version);
+        let state = self.inner.get_state(task).await?;
+
+        // Cache again states owned by this instance, unless invalidated by a write meanwhile
+        if let Some(entry) = self.cache.write().await.get_mut(&key) {
+            if Some(entry.version) == version && state.is_some() {
+                entry.state = state.clone();
+                entry.cached_at = Instant::now();
+            }
+        }
+        Ok(state)
+    }
+
+    async fn count_tasks(&self) -> Result<usize, TaskStoreError> {
+        self.inner.count_tasks().await
+    }
+
+    async fn update_status(
+        &self,
+        task: &dyn Task,
+        status: TaskStatus,
+    ) -> Result<(), TaskStoreError> {
+        // Invalidate before and after writing: a state read during the write is not kept
+        self.invalidate(task).await;
+        let result = self.inner.update_status(task, status).await;
+        self.invalidate(task).await;
+        result
+    }
+
+    async fn update_state(&self, task: &dyn Task, update: StateUpdate) -> Result<(), TaskStoreError> {
+        self.invalidate(task).await;
+        let result = self.inner.update_state(task, update).await;
+        self.invalidate(task).await;
+        result
+    }
+
+    async fn update_pending_state(
+        &self,
+        task: &dyn Task,
+        update: StateUpdate,
+    ) -> Result<bool, TaskStoreError> {
+        self.invalidate(task).await;
+        let result = self.inner.update_pending_state(task, update).await;
+        self.invalidate(task).await;
+        result
+    }
+
+    async fn clear(&self) -> Result<(), TaskStoreError> {
+        self.cache.write().await.clear();
+        let result = self.inner.clear().await;
+        self.cache.write().await.clear();
+        result
+    }
+
+    async fn get_all_states(&self) -> Result<Vec<TaskState>, TaskStoreError> {
+        self.inner.get_all_states().await
+    }
+
+    /// Claimed tasks are not cached, they can be claimed again by another instance once their lease expired.
+    async fn claim_pending(
+        &self,
+        names: &[String],
+        lease: Duration,
+    ) -> Result<Option<TaskState>, TaskStoreError> {
+        self.inner.claim_pending(names, lease).await
+    }
+
+    async fn renew_lease(&self, task: &dyn Task, lease: Duration) -> Result<bool, TaskStoreError> {
+        self.inner.renew_lease(task, lease).await
+    }
+
+    async fn heartbeat(&self) -> Result<(), TaskStoreError> {
+        self.inner.heartbeat().await
+    }
+
+    async fn dead_instances(&self, timeout: Duration) -> Result<Vec<String>, TaskStoreError> {
+        self.inner.dead_instances(timeout).await
+    }
+
+    async fn adopt_instance(&self, instance: &str) -> Result<Vec<TaskState>, TaskStoreError> {
+        let states = self.inner.adopt_instance(instance).await?;
+        for state in states.iter().filter(|state| state.payload.is_none()) {
+            let task = TaskRef {
+                name: state.task_name.clone(),
+                id: state.task_id.clone(),
+            };
+            self.cache(&task, None).await;
+        }
+        Ok(states)
+    }
+
+    async fn acquire_leadership(&self, lease: Duration) -> Result<bool, TaskStoreError> {
+        self.inner.acquire_leadership(lease).await
+    }
+
+    async fn resign_leadership(&self) -> Result<(), TaskStoreError> {
+        self.inner.resign_leadership().await
+    }
+
+    async fn try_lock(&self, name: &str, owner: &str, ttl: Duration) -> Result<bool, TaskStoreError> {
+        self.inner.try_lock(name, owner, ttl).await
+    }
+
+    async fn unlock(&self, name: &str, owner: &str) -> Result<(), TaskStoreError> {
+        self.inner.unlock(name, owner).await
+    }
+
+    async fn record_outcome(
+        &self,
+        task: &dyn Task,
+        outcome: &TaskOutcome,
+        ttl: Duration,
+    ) -> Result<(), TaskStoreError> {
+        self.inner.record_outcome(task, outcome, ttl).await
+    }
+
+    async fn get_outcome(&self, task: &dyn Task) -> Result<Option<TaskOutcome>, TaskStoreError> {
+        self.inner.get_outcome(task).await
+    }
+}
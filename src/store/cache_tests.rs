@@ -0,0 +1,233 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::sync::Notify;
+
+use crate::{context::TaskContext, store::TaskStatus, task::Task};
+
+use super::{
+    cache::CachingTaskStore, memory::InMemoryTaskStore, StateUpdate, TaskState, TaskStore,
+    TaskStoreError,
+};
+
+struct TestTask {
+    pub id: String,
+}
+
+#[async_trait]
+impl Task for TestTask {
+    fn name(&self) -> String {
+        "test_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn run(&self, _ctx: &TaskContext) {
+        // Nothing
+    }
+}
+
+/// In memory store counting state reads and inserts.
+#[derive(Clone)]
+struct CountingStore {
+    inner: InMemoryTaskStore,
+    reads: Arc<AtomicUsize>,
+    /// If set, status updates wait for a notification before being written.
+    update_gate: Option<Arc<Notify>>,
+}
+
+impl CountingStore {
+    fn new() -> Self {
+        Self {
+            inner: InMemoryTaskStore::new("test_manager"),
+            reads: Arc::new(AtomicUsize::new(0)),
+            update_gate: None,
+        }
+    }
+
+    fn reads(&self) -> usize {
+        self.reads.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl TaskStore for CountingStore {
+    fn manager_name(&self) -> String {
+        self.inner.manager_name()
+    }
+
+    async fn init(&self) -> Result<(), TaskStoreError> {
+        self.inner.init().await
+    }
+
+    async fn save_state(&self, task: &dyn Task) -> Result<TaskState, TaskStoreError> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        self.inner.save_state(task).await
+    }
+
+    async fn try_insert_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        self.inner.try_insert_state(task).await
+    }
+
+    async fn delete_state(&self, task: &dyn Task) -> Result<(), TaskStoreError> {
+        self.inner.delete_state(task).await
+    }
+
+    async fn get_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        self.inner.get_state(task).await
+    }
+
+    async fn count_tasks(&self) -> Result<usize, TaskStoreError> {
+        self.inner.count_tasks().await
+    }
+
+    async fn update_status(
+        &self,
+        task: &dyn Task,
+        status: TaskStatus,
+    ) -> Result<(), TaskStoreError> {
+        if let Some(gate) = &self.update_gate {
+            gate.notified().await;
+        }
+        self.inner.update_status(task, status).await
+    }
+
+    async fn clear(&self) -> Result<(), TaskStoreError> {
+        self.inner.clear().await
+    }
+
+    async fn get_all_states(&self) -> Result<Vec<TaskState>, TaskStoreError> {
+        self.inner.get_all_states().await
+    }
+}
+
+#[tokio::test]
+async fn cached_duplicate() {
+    let counting = CountingStore::new();
+    let store = CachingTaskStore::new(counting.clone());
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    assert!(store.try_insert_state(&task).await.unwrap().is_some());
+    assert!(store.try_insert_state(&task).await.unwrap().is_none());
+    assert!(store.get_state(&task).await.unwrap().is_some());
+    assert_eq!(counting.reads(), 1);
+}
+
+#[tokio::test]
+async fn invalidate_on_update() {
+    let counting = CountingStore::new();
+    let store = CachingTaskStore::new(counting.clone());
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    store.try_insert_state(&task).await.unwrap();
+    store
+        .update_status(&task, TaskStatus::Running)
+        .await
+        .unwrap();
+
+    // Task is still known as owned
+    assert!(store.try_insert_state(&task).await.unwrap().is_none());
+    assert_eq!(counting.reads(), 1);
+
+    // State is read again once
+    let state = store.get_state(&task).await.unwrap().unwrap();
+    assert_eq!(state.status, TaskStatus::Running);
+    store.get_state(&task).await.unwrap();
+    assert_eq!(counting.reads(), 2);
+}
+
+#[tokio::test]
+async fn read_during_update() {
+    let gate = Arc::new(Notify::new());
+    let counting = CountingStore {
+        update_gate: Some(gate.clone()),
+        ..CountingStore::new()
+    };
+    let store = CachingTaskStore::new(counting.clone());
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    store.try_insert_state(&task).await.unwrap();
+
+    // State read while the update is being written
+    let (updated, read) = tokio::join!(store.update_status(&task, TaskStatus::Running), async {
+        let state = store.get_state(&task).await.unwrap().unwrap();
+        gate.notify_one();
+        state
+    });
+    updated.unwrap();
+    assert_eq!(read.status, TaskStatus::Pending);
+
+    // Old state was not kept
+    let state = store.get_state(&task).await.unwrap().unwrap();
+    assert_eq!(state.status, TaskStatus::Running);
+}
+
+#[tokio::test]
+async fn invalidate_on_delete() {
+    let counting = CountingStore::new();
+    let store = CachingTaskStore::new(counting.clone());
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    store.try_insert_state(&task).await.unwrap();
+    store.delete_state(&task).await.unwrap();
+    assert!(store.get_state(&task).await.unwrap().is_none());
+    assert!(store.try_insert_state(&task).await.unwrap().is_some());
+    assert_eq!(counting.reads(), 3);
+}
+
+#[tokio::test]
+async fn other_instance_state() {
+    let counting = CountingStore::new();
+    let store = CachingTaskStore::new(counting.clone());
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+
+    // State created through the backing store (e.g. by another instance)
+    counting.inner.try_insert_state(&task).await.unwrap();
+    assert!(store.get_state(&task).await.unwrap().is_some());
+    assert!(store.get_state(&task).await.unwrap().is_some());
+    assert_eq!(counting.reads(), 2);
+}
+
+#[tokio::test]
+async fn conformance() {
+    let factory = |manager: String, _| async move { CachingTaskStore::new(InMemoryTaskStore::new(&manager)) };
+    super::conformance::run_all(factory).await;
+    super::conformance::check_claim_pending(&factory).await;
+    super::conformance::check_lease_expiry(&factory).await;
+}
+
+#[tokio::test]
+async fn claimable_state_not_owned() {
+    let backing = InMemoryTaskStore::new("manager");
+    let store = CachingTaskStore::new(backing.clone());
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    let initial = StateUpdate {
+        payload: Some("1".to_string()),
+        ..Default::default()
+    };
+    assert!(store.try_insert_state_with(&task, initial.clone()).await.unwrap().is_some());
+
+    // Claimed and completed by another instance
+    let names = vec!["test_task".to_string()];
+    assert!(backing.claim_pending(&names, Duration::from_secs(60)).await.unwrap().is_some());
+    backing.delete_state(&task).await.unwrap();
+    assert!(store.try_insert_state_with(&task, initial).await.unwrap().is_some());
+}
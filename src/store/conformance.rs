@@ -0,0 +1,702 @@
+/*!
+Task store conformance suite.
+
+Generic behavioural checks that any [`TaskStore`] implementation is expected to pass.
+Enabled with the `test-util` feature.
+
+The suite is driven by a factory, called with a manager name and an instance name,
+returning an initialized and empty store.
+Stores sharing their backend between instances (MongoDB, PostgreSQL, Redis, ...)
+must return stores connected to the same backend for the same manager,
+and should also be checked with [`check_cross_instance_dedup`], [`check_cross_instance_claim`],
+[`check_instance_adoption`] and [`check_leadership`].
+Stores supporting shared queues should be checked with [`check_claim_pending`]
+and [`check_lease_expiry`],
+stores supporting locks with [`check_locks`],
+stores supporting idempotency windows with [`check_outcomes`].
+
+```rust
+#[tokio::test]
+async fn conformance() {
+    quartermaster::store::conformance::run_all(|manager, instance| async move {
+        let store = MyTaskStore::new(&manager, &instance);
+        store.init().await.unwrap();
+        store.clear().await.unwrap();
+        store
+    })
+    .await;
+}
+```
+*/
+
+use std::{future::Future, time::Duration};
+
+use async_trait::async_trait;
+
+use crate::{
+    context::TaskContext,
+    task::{Task, TaskOutcome},
+};
+
+use super::{StateUpdate, TaskStatus, TaskStore, TaskStoreError};
+
+/// Lease of the claims of the checks not expecting them to expire.
+const LEASE: Duration = Duration::from_secs(60);
+
+/// Task used by the conformance checks.
+pub struct ConformanceTask {
+    pub id: String,
+}
+
+impl ConformanceTask {
+    pub fn new(id: &str) -> Self {
+        Self { id: id.to_string() }
+    }
+}
+
+#[async_trait]
+impl Task for ConformanceTask {
+    fn name(&self) -> String {
+        "conformance_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn run(&self, _ctx: &TaskContext) {
+        // Nothing
+    }
+}
+
+/// Run all the conformance checks.
+pub async fn run_all<S, F, Fut>(factory: F)
+where
+    S: TaskStore + 'static,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    check_save_and_get(&factory).await;
+    check_duplicate(&factory).await;
+    check_delete(&factory).await;
+    check_update_status(&factory).await;
+    check_update_state(&factory).await;
+    check_insert_with_initial_state(&factory).await;
+    check_clear(&factory).await;
+    check_count_and_get_all(&factory).await;
+    check_concurrent_dedup(&factory).await;
+    check_instance_scoping(&factory).await;
+    check_manager_scoping(&factory).await;
+}
+
+/// Saved states can be retrieved, unknown states cannot.
+pub async fn check_save_and_get<S, F, Fut>(factory: &F)
+where
+    S: TaskStore,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let store = factory("conformance_save".to_string(), "instance".to_string()).await;
+    let state = store
+        .save_state(&ConformanceTask::new("1"))
+        .await
+        .unwrap();
+    assert_eq!(state.task_id, "1");
+    assert_eq!(state.task_name, "conformance_task");
+    assert_eq!(state.task_manager, "conformance_save");
+    assert_eq!(state.status, TaskStatus::Pending);
+
+    let state = store
+        .get_state(&ConformanceTask::new("1"))
+        .await
+        .unwrap()
+        .expect("saved state should be found");
+    assert_eq!(state.task_id, "1");
+    assert_eq!(state.status, TaskStatus::Pending);
+    assert!(store
+        .get_state(&ConformanceTask::new("2"))
+        .await
+        .unwrap()
+        .is_none());
+}
+
+/// A task cannot be saved twice.
+pub async fn check_duplicate<S, F, Fut>(factory: &F)
+where
+    S: TaskStore,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let store = factory("conformance_duplicate".to_string(), "instance".to_string()).await;
+    let task = ConformanceTask::new("1");
+    assert!(store.try_insert_state(&task).await.unwrap().is_some());
+    assert!(store.try_insert_state(&task).await.unwrap().is_none());
+    assert!(matches!(
+        store.save_state(&task).await,
+        Err(TaskStoreError::Duplicate(_))
+    ));
+    assert_eq!(store.count_tasks().await.unwrap(), 1);
+}
+
+/// Deleted states are gone, and can be saved again.
+pub async fn check_delete<S, F, Fut>(factory: &F)
+where
+    S: TaskStore,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let store = factory("conformance_delete".to_string(), "instance".to_string()).await;
+    let task = ConformanceTask::new("1");
+    store.save_state(&task).await.unwrap();
+    store.delete_state(&task).await.unwrap();
+    assert!(store.get_state(&task).await.unwrap().is_none());
+    assert_eq!(store.count_tasks().await.unwrap(), 0);
+    assert!(store.try_insert_state(&task).await.unwrap().is_some());
+
+    // Deleting an unknown state is either a no-op or a not found error
+    assert!(matches!(
+        store.delete_state(&ConformanceTask::new("2")).await,
+        Ok(()) | Err(TaskStoreError::NotFound(_))
+    ));
+}
+
+/// Status updates are persisted, and do not create states.
+pub async fn check_update_status<S, F, Fut>(factory: &F)
+where
+    S: TaskStore,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let store = factory("conformance_update".to_string(), "instance".to_string()).await;
+    let task = ConformanceTask::new("1");
+    store.save_state(&task).await.unwrap();
+    store
+        .update_status(&task, TaskStatus::Running)
+        .await
+        .unwrap();
+    let state = store.get_state(&task).await.unwrap().unwrap();
+    assert_eq!(state.status, TaskStatus::Running);
+
+    let unknown = ConformanceTask::new("2");
+    assert!(matches!(
+        store.update_status(&unknown, TaskStatus::Running).await,
+        Ok(()) | Err(TaskStoreError::NotFound(_))
+    ));
+    assert!(store.get_state(&unknown).await.unwrap().is_none());
+}
+
+/// Partial updates only change the given fields.
+pub async fn check_update_state<S, F, Fut>(factory: &F)
+where
+    S: TaskStore,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let store = factory("conformance_update_state".to_string(), "instance".to_string()).await;
+    let task = ConformanceTask::new("1");
+    let created = store.save_state(&task).await.unwrap();
+    assert_eq!(created.last_update_time, created.creation_time);
+    assert!(created.started_time.is_none());
+
+    let started = created.creation_time + 1000;
+    store
+        .update_state(
+            &task,
+            StateUpdate {
+                status: Some(TaskStatus::Running),
+                started_time: Some(started),
+                worker: Some(3),
+                host: Some("host\twith\nseparators".to_string()),
+                time: started,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    store
+        .update_state(
+            &task,
+            StateUpdate {
+                finished_time: Some(started + 500),
+                progress: Some(50),
+                progress_message: Some("half way".to_string()),
+                time: started + 500,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    let state = store.get_state(&task).await.unwrap().unwrap();
+    assert_eq!(state.status, TaskStatus::Running);
+    assert_eq!(state.creation_time, created.creation_time);
+    assert_eq!(state.started_time, Some(started));
+    assert_eq!(state.finished_time, Some(started + 500));
+    assert_eq!(state.last_update_time, started + 500);
+    assert_eq!(state.worker, Some(3));
+    assert_eq!(state.host.as_deref(), Some("host\twith\nseparators"));
+    assert_eq!(state.progress, Some(50));
+    assert_eq!(state.progress_message.as_deref(), Some("half way"));
+}
+
+/// States can be inserted with initial fields, which are stamped with the creation time.
+pub async fn check_insert_with_initial_state<S, F, Fut>(factory: &F)
+where
+    S: TaskStore,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let store = factory("conformance_insert_with".to_string(), "instance".to_string()).await;
+    let task = ConformanceTask::new("1");
+    let initial = StateUpdate {
+        parent_name: Some("parent\ttask".to_string()),
+        parent_id: Some("p1".to_string()),
+        root_name: Some("root_task".to_string()),
+        root_id: Some("r1".to_string()),
+        batch_id: Some("b1".to_string()),
+        payload: Some("{\"key\":\"line\\nbreak\"}\n".to_string()),
+        ..Default::default()
+    };
+    let created = store
+        .try_insert_state_with(&task, initial.clone())
+        .await
+        .unwrap()
+        .expect("state should be inserted");
+    assert_eq!(created.parent_name.as_deref(), Some("parent\ttask"));
+    assert!(store
+        .try_insert_state_with(&task, initial)
+        .await
+        .unwrap()
+        .is_none());
+
+    let state = store.get_state(&task).await.unwrap().unwrap();
+    assert_eq!(state.status, TaskStatus::Pending);
+    assert_eq!(state.last_update_time, state.creation_time);
+    assert_eq!(state.parent_name.as_deref(), Some("parent\ttask"));
+    assert_eq!(state.parent_id.as_deref(), Some("p1"));
+    assert_eq!(state.root_name.as_deref(), Some("root_task"));
+    assert_eq!(state.root_id.as_deref(), Some("r1"));
+    assert_eq!(state.batch_id.as_deref(), Some("b1"));
+    assert_eq!(
+        state.payload.as_deref(),
+        Some("{\"key\":\"line\\nbreak\"}\n")
+    );
+}
+
+/// Pending states with a payload can be claimed once, oldest first, and updated until claimed.
+pub async fn check_claim_pending<S, F, Fut>(factory: &F)
+where
+    S: TaskStore,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let store = factory("conformance_claim".to_string(), "instance".to_string()).await;
+    let payload = |value: &str| StateUpdate {
+        payload: Some(value.to_string()),
+        ..Default::default()
+    };
+    let names = vec!["conformance_task".to_string()];
+    store.save_state(&ConformanceTask::new("no_payload")).await.unwrap();
+    store
+        .try_insert_state_with(&ConformanceTask::new("1"), payload("1"))
+        .await
+        .unwrap();
+    store
+        .try_insert_state_with(&ConformanceTask::new("2"), payload("2"))
+        .await
+        .unwrap();
+    store
+        .update_status(&ConformanceTask::new("2"), TaskStatus::Running)
+        .await
+        .unwrap();
+    assert!(store
+        .update_pending_state(&ConformanceTask::new("1"), payload("1b"))
+        .await
+        .unwrap());
+    assert!(!store
+        .update_pending_state(&ConformanceTask::new("2"), payload("2b"))
+        .await
+        .unwrap());
+    assert!(!store
+        .update_pending_state(&ConformanceTask::new("unknown"), payload("3"))
+        .await
+        .unwrap());
+
+    assert!(store
+        .claim_pending(&["other_task".to_string()], LEASE)
+        .await
+        .unwrap()
+        .is_none());
+    let claimed = store
+        .claim_pending(&names, LEASE)
+        .await
+        .unwrap()
+        .expect("pending state should be claimed");
+    assert_eq!(claimed.task_id, "1");
+    assert_eq!(claimed.status, TaskStatus::Running);
+    assert_eq!(claimed.payload.as_deref(), Some("1b"));
+    assert!(claimed.lease_expiry_time.is_some());
+    assert!(!store
+        .update_pending_state(&ConformanceTask::new("1"), payload("1c"))
+        .await
+        .unwrap());
+    let state = store
+        .get_state(&ConformanceTask::new("1"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(state.status, TaskStatus::Running);
+    assert_eq!(state.payload.as_deref(), Some("1b"));
+
+    // Running or without payload: not claimable
+    assert!(store.claim_pending(&names, LEASE).await.unwrap().is_none());
+    store.clear().await.unwrap();
+}
+
+/// Claimed states can be claimed again once their lease expired, unless renewed.
+pub async fn check_lease_expiry<S, F, Fut>(factory: &F)
+where
+    S: TaskStore,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let store = factory("conformance_lease".to_string(), "instance".to_string()).await;
+    let task = ConformanceTask::new("1");
+    let names = vec!["conformance_task".to_string()];
+    let initial = StateUpdate {
+        payload: Some("1".to_string()),
+        ..Default::default()
+    };
+    store.try_insert_state_with(&task, initial).await.unwrap();
+
+    // Checked at least 40% of the lease away from the expiry, to tolerate slow backends
+    let lease = Duration::from_secs(1);
+    assert!(store.claim_pending(&names, lease).await.unwrap().is_some());
+    assert!(store.claim_pending(&names, lease).await.unwrap().is_none());
+
+    // Renewed lease is not expired yet
+    tokio::time::sleep(lease * 6 / 10).await;
+    assert!(store.renew_lease(&task, lease).await.unwrap());
+    tokio::time::sleep(lease * 6 / 10).await;
+    assert!(store.claim_pending(&names, lease).await.unwrap().is_none());
+
+    // Expired lease
+    tokio::time::sleep(lease).await;
+    let claimed = store
+        .claim_pending(&names, LEASE)
+        .await
+        .unwrap()
+        .expect("state with an expired lease should be claimed");
+    assert_eq!(claimed.task_id, "1");
+    assert_eq!(claimed.status, TaskStatus::Running);
+
+    // Deleted state lease cannot be renewed
+    store.delete_state(&task).await.unwrap();
+    assert!(!store.renew_lease(&task, LEASE).await.unwrap());
+    store.clear().await.unwrap();
+}
+
+/// Clear removes all the states.
+pub async fn check_clear<S, F, Fut>(factory: &F)
+where
+    S: TaskStore,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let store = factory("conformance_clear".to_string(), "instance".to_string()).await;
+    store.save_state(&ConformanceTask::new("1")).await.unwrap();
+    store.save_state(&ConformanceTask::new("2")).await.unwrap();
+    store.clear().await.unwrap();
+    assert_eq!(store.count_tasks().await.unwrap(), 0);
+    assert!(store.get_all_states().await.unwrap().is_empty());
+    assert!(store
+        .get_state(&ConformanceTask::new("1"))
+        .await
+        .unwrap()
+        .is_none());
+}
+
+/// Count and listing reflect the saved states.
+pub async fn check_count_and_get_all<S, F, Fut>(factory: &F)
+where
+    S: TaskStore,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let store = factory("conformance_count".to_string(), "instance".to_string()).await;
+    assert_eq!(store.count_tasks().await.unwrap(), 0);
+    for id in ["1", "2", "3"] {
+        store.save_state(&ConformanceTask::new(id)).await.unwrap();
+    }
+    assert_eq!(store.count_tasks().await.unwrap(), 3);
+    let states = store.get_all_states().await.unwrap();
+    assert_eq!(states.len(), 3);
+    for id in ["1", "2", "3"] {
+        assert!(states.iter().any(|s| s.task_id == id));
+    }
+}
+
+/// Concurrent insertions of the same task: exactly one succeeds.
+pub async fn check_concurrent_dedup<S, F, Fut>(factory: &F)
+where
+    S: TaskStore + 'static,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let store = factory("conformance_concurrent".to_string(), "instance".to_string()).await;
+    let mut handles = vec![];
+    for _ in 0..10 {
+        let store = store.clone();
+        handles.push(tokio::spawn(async move {
+            store
+                .try_insert_state(&ConformanceTask::new("1"))
+                .await
+                .unwrap()
+                .is_some()
+        }));
+    }
+    let mut inserted = 0;
+    for handle in handles {
+        if handle.await.unwrap() {
+            inserted += 1;
+        }
+    }
+    assert_eq!(inserted, 1);
+    assert_eq!(store.count_tasks().await.unwrap(), 1);
+}
+
+/// Count, listing and clear only apply to the states of the store instance.
+pub async fn check_instance_scoping<S, F, Fut>(factory: &F)
+where
+    S: TaskStore,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let manager = "conformance_instance_scoping";
+    let store = factory(manager.to_string(), "instance".to_string()).await;
+    let other = factory(manager.to_string(), "other_instance".to_string()).await;
+    store.save_state(&ConformanceTask::new("1")).await.unwrap();
+    store.save_state(&ConformanceTask::new("2")).await.unwrap();
+    other.save_state(&ConformanceTask::new("3")).await.unwrap();
+
+    assert_eq!(store.count_tasks().await.unwrap(), 2);
+    assert_eq!(other.count_tasks().await.unwrap(), 1);
+    let states = store.get_all_states().await.unwrap();
+    assert_eq!(states.len(), 2);
+    assert!(!states.iter().any(|s| s.task_id == "3"));
+
+    store.clear().await.unwrap();
+    assert_eq!(store.count_tasks().await.unwrap(), 0);
+    assert_eq!(other.count_tasks().await.unwrap(), 1);
+    other.clear().await.unwrap();
+}
+
+/// States of different managers do not collide.
+pub async fn check_manager_scoping<S, F, Fut>(factory: &F)
+where
+    S: TaskStore,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let store = factory("conformance_manager".to_string(), "instance".to_string()).await;
+    let other = factory(
+        "conformance_other_manager".to_string(),
+        "other_instance".to_string(),
+    )
+    .await;
+    let task = ConformanceTask::new("1");
+    assert!(store.try_insert_state(&task).await.unwrap().is_some());
+    assert!(other.get_state(&task).await.unwrap().is_none());
+    assert!(other.try_insert_state(&task).await.unwrap().is_some());
+    assert_eq!(
+        store.get_state(&task).await.unwrap().unwrap().task_manager,
+        "conformance_manager"
+    );
+    other.clear().await.unwrap();
+}
+
+/// For stores shared between instances:
+/// a task accepted by an instance is seen, and rejected, by the other instances of the same manager.
+pub async fn check_cross_instance_dedup<S, F, Fut>(factory: &F)
+where
+    S: TaskStore,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let manager = "conformance_cross_instance";
+    let store = factory(manager.to_string(), "instance".to_string()).await;
+    let other = factory(manager.to_string(), "other_instance".to_string()).await;
+    let task = ConformanceTask::new("1");
+    assert!(store.try_insert_state(&task).await.unwrap().is_some());
+    assert!(other.get_state(&task).await.unwrap().is_some());
+    assert!(other.try_insert_state(&task).await.unwrap().is_none());
+    store.delete_state(&task).await.unwrap();
+    assert!(other.try_insert_state(&task).await.unwrap().is_some());
+    other.clear().await.unwrap();
+}
+
+/// For stores shared between instances:
+/// a pending task created by an instance can be claimed by another one, and moves to its instance.
+pub async fn check_cross_instance_claim<S, F, Fut>(factory: &F)
+where
+    S: TaskStore,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let manager = "conformance_cross_instance_claim";
+    let store = factory(manager.to_string(), "instance".to_string()).await;
+    let other = factory(manager.to_string(), "other_instance".to_string()).await;
+    let task = ConformanceTask::new("1");
+    let initial = StateUpdate {
+        payload: Some("1".to_string()),
+        ..Default::default()
+    };
+    assert!(store
+        .try_insert_state_with(&task, initial)
+        .await
+        .unwrap()
+        .is_some());
+
+    let claimed = other
+        .claim_pending(&["conformance_task".to_string()], LEASE)
+        .await
+        .unwrap()
+        .expect("pending state should be claimed");
+    assert_eq!(claimed.task_id, "1");
+    assert_eq!(store.count_tasks().await.unwrap(), 0);
+    let states = other.get_all_states().await.unwrap();
+    assert_eq!(states.len(), 1);
+    assert_eq!(states[0].instance, claimed.instance);
+    assert!(other.renew_lease(&task, LEASE).await.unwrap());
+    assert!(!store.renew_lease(&task, LEASE).await.unwrap());
+    assert!(store
+        .claim_pending(&["conformance_task".to_string()], LEASE)
+        .await
+        .unwrap()
+        .is_none());
+    other.clear().await.unwrap();
+}
+
+/// Check that the states of an instance without recent heartbeat can be taken over by another instance.
+pub async fn check_instance_adoption<S, F, Fut>(factory: &F)
+where
+    S: TaskStore,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let manager = "conformance_instance_adoption";
+    let store = factory(manager.to_string(), "instance".to_string()).await;
+    let other = factory(manager.to_string(), "other_instance".to_string()).await;
+    let task = ConformanceTask::new("1");
+    let state = store.save_state(&task).await.unwrap();
+    store.update_status(&task, TaskStatus::Running).await.unwrap();
+    let instance = state.instance.expect("state should have an instance");
+
+    store.heartbeat().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    other.heartbeat().await.unwrap();
+    let dead = other.dead_instances(Duration::from_millis(25)).await.unwrap();
+    assert!(dead.contains(&instance));
+    assert!(store.dead_instances(Duration::from_secs(60)).await.unwrap().is_empty());
+
+    let adopted = other.adopt_instance(&instance).await.unwrap();
+    assert_eq!(adopted.len(), 1);
+    assert_eq!(adopted[0].task_id, "1");
+    assert_eq!(adopted[0].status, TaskStatus::Pending);
+    assert_ne!(adopted[0].instance, Some(instance.clone()));
+    assert_eq!(store.count_tasks().await.unwrap(), 0);
+    assert_eq!(other.get_all_states().await.unwrap().len(), 1);
+
+    // Adopted instance is forgotten
+    let dead = other.dead_instances(Duration::from_millis(25)).await.unwrap();
+    assert!(!dead.contains(&instance));
+    assert!(other.adopt_instance(&instance).await.unwrap().is_empty());
+    other.clear().await.unwrap();
+}
+
+/// Check that a single instance holds the leadership, until it resigns or its lease expires.
+pub async fn check_leadership<S, F, Fut>(factory: &F)
+where
+    S: TaskStore,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let manager = "conformance_leadership";
+    let store = factory(manager.to_string(), "instance".to_string()).await;
+    let other = factory(manager.to_string(), "other_instance".to_string()).await;
+    let lease = Duration::from_millis(100);
+
+    assert!(store.acquire_leadership(lease).await.unwrap());
+    assert!(!other.acquire_leadership(lease).await.unwrap());
+    assert!(store.acquire_leadership(lease).await.unwrap());
+
+    // Resigned leadership
+    other.resign_leadership().await.unwrap();
+    assert!(!other.acquire_leadership(lease).await.unwrap());
+    store.resign_leadership().await.unwrap();
+    assert!(other.acquire_leadership(lease).await.unwrap());
+    assert!(!store.acquire_leadership(lease).await.unwrap());
+
+    // Expired lease
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(store.acquire_leadership(lease).await.unwrap());
+    assert!(!other.acquire_leadership(lease).await.unwrap());
+    store.resign_leadership().await.unwrap();
+}
+
+/// Check that a named lock is held by a single owner, until released or expired.
+pub async fn check_locks<S, F, Fut>(factory: &F)
+where
+    S: TaskStore,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let store = factory("conformance_locks".to_string(), "instance".to_string()).await;
+    let ttl = Duration::from_millis(100);
+
+    assert!(store.try_lock("lock", "owner", ttl).await.unwrap());
+    assert!(!store.try_lock("lock", "other_owner", ttl).await.unwrap());
+    assert!(store.try_lock("lock", "owner", ttl).await.unwrap());
+    assert!(store.try_lock("other_lock", "other_owner", ttl).await.unwrap());
+
+    // Only released by its owner
+    store.unlock("lock", "other_owner").await.unwrap();
+    assert!(!store.try_lock("lock", "other_owner", ttl).await.unwrap());
+    store.unlock("lock", "owner").await.unwrap();
+    assert!(store.try_lock("lock", "other_owner", ttl).await.unwrap());
+
+    // Expired lock
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(store.try_lock("lock", "owner", ttl).await.unwrap());
+    store.unlock("lock", "owner").await.unwrap();
+    store.unlock("other_lock", "other_owner").await.unwrap();
+}
+
+/// Check that the outcome of a completed task is remembered, until its time to live elapses.
+pub async fn check_outcomes<S, F, Fut>(factory: &F)
+where
+    S: TaskStore,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = S>,
+{
+    let store = factory("conformance_outcomes".to_string(), "instance".to_string()).await;
+    let other_manager = factory("conformance_outcomes_other".to_string(), "instance".to_string()).await;
+    let task = ConformanceTask::new("1");
+    let ttl = Duration::from_millis(100);
+
+    assert_eq!(store.get_outcome(&task).await.unwrap(), None);
+    store.record_outcome(&task, &TaskOutcome::Succeeded, ttl).await.unwrap();
+    assert_eq!(store.get_outcome(&task).await.unwrap(), Some(TaskOutcome::Succeeded));
+    assert_eq!(store.get_outcome(&ConformanceTask::new("2")).await.unwrap(), None);
+    assert_eq!(other_manager.get_outcome(&task).await.unwrap(), None);
+
+    // Replaced by a later run, kept when the store is cleared
+    let failed = TaskOutcome::Failed("failure: message".to_string());
+    store.record_outcome(&task, &failed, ttl).await.unwrap();
+    store.clear().await.unwrap();
+    assert_eq!(store.get_outcome(&task).await.unwrap(), Some(failed));
+
+    // Expired outcome
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(store.get_outcome(&task).await.unwrap(), None);
+}
@@ -0,0 +1,600 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use crate::{
+    task::{Task, TaskOutcome},
+    util::now_millis,
+};
+
+use super::{StateUpdate, TaskState, TaskStatus, TaskStore, TaskStoreError};
+
+/// Default number of obsolete journal records tolerated before compacting.
+const DEFAULT_COMPACTION_THRESHOLD: usize = 1000;
+
+impl From<std::io::Error> for TaskStoreError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io {
+            message: err.to_string(),
+            source: Some(Box::new(err)),
+        }
+    }
+}
+
+/// Journal record.
+/// Each record is written as a single line of tab separated fields,
+/// optional state fields being written as `name=value`.
+enum Record {
+    Save(Box<TaskState>),
+    Update(String, String, Vec<(String, String)>),
+    Delete(String, String),
+    Clear,
+    /// Outcome of a completed task, remembered until its expiry time (ms).
+    Outcome(String, String, TaskOutcome, u64),
+}
+
+impl Record {
+    fn encode(&self) -> String {
+        match self {
+            Record::Save(s) => format!(
+                "save\t{}\t{}\t{}\t{}{}\n",
+                escape(&s.task_name),
+                escape(&s.task_id),
+                s.status,
+                s.creation_time,
+                encode_fields(s.extra_fields())
+            ),
+            Record::Update(name, id, fields) => format!(
+                "update\t{}\t{}{}\n",
+                escape(name),
+                escape(id),
+                encode_fields(fields.iter().map(|(k, v)| (k.as_str(), v.clone())))
+            ),
+            Record::Delete(name, id) => format!("delete\t{}\t{}\n", escape(name), escape(id)),
+            Record::Clear => "clear\n".to_string(),
+            Record::Outcome(name, id, outcome, expiry_time) => format!(
+                "outcome\t{}\t{}\t{}\t{}\n",
+                escape(name),
+                escape(id),
+                expiry_time,
+                escape(&outcome.to_string())
+            ),
+        }
+    }
+
+    fn decode(line: &str, manager: &str) -> Result<Self, TaskStoreError> {
+        let fields: Vec<String> = line.split('\t').map(unescape).collect();
+        let invalid = || TaskStoreError::serialization(format!("invalid journal record: {}", line));
+        let pairs = |fields: &[String]| {
+            fields
+                .iter()
+                .map(|f| {
+                    f.split_once('=')
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .ok_or_else(invalid)
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        match fields.as_slice() {
+            [kind, name, id, st, time, extra @ ..] if kind == "save" => {
+                let creation_time = time.parse().map_err(|_| invalid())?;
+                let mut state = TaskState {
+                    id: None,
+                    task_id: id.clone(),
+                    task_name: name.clone(),
+                    task_manager: manager.to_string(),
+                    instance: None,
+                    status: st.parse().map_err(TaskStoreError::serialization)?,
+                    creation_time,
+                    started_time: None,
+                    finished_time: None,
+                    last_update_time: creation_time,
+                    worker: None,
+                    host: None,
+                    progress: None,
+                    progress_message: None,
+                    parent_name: None,
+                    parent_id: None,
+                    root_name: None,
+                    root_id: None,
+                    batch_id: None,
+                    payload: None,
+                    lease_expiry_time: None,
+                    attempt: 0,
+                };
+                for (k, v) in pairs(extra)? {
+                    state.set_field(&k, &v).map_err(TaskStoreError::serialization)?;
+                }
+                Ok(Record::Save(Box::new(state)))
+            }
+            [kind, name, id, extra @ ..] if kind == "update" => {
+                Ok(Record::Update(name.clone(), id.clone(), pairs(extra)?))
+            }
+            [kind, name, id] if kind == "delete" => Ok(Record::Delete(name.clone(), id.clone())),
+            [kind] if kind == "clear" => Ok(Record::Clear),
+            [kind, name, id, time, outcome] if kind == "outcome" => Ok(Record::Outcome(
+                name.clone(),
+                id.clone(),
+                outcome.parse().map_err(TaskStoreError::serialization)?,
+                time.parse().map_err(|_| invalid())?,
+            )),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Encode optional state fields, each prefixed by a field separator.
+fn encode_fields<'a>(fields: impl IntoIterator<Item = (&'a str, String)>) -> String {
+    fields
+        .into_iter()
+        .map(|(k, v)| format!("\t{}={}", k, escape(&v)))
+        .collect()
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('t') => result.push('\t'),
+                Some('n') => result.push('\n'),
+                Some(other) => result.push(other),
+                None => result.push('\\'),
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Run blocking file I/O on a thread where blocking is acceptable, off the runtime threads.
+async fn blocking<T, F>(f: F) -> Result<T, TaskStoreError>
+where
+    F: FnOnce() -> Result<T, TaskStoreError> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|err| TaskStoreError::Backend {
+            message: err.to_string(),
+            source: Some(Box::new(err)),
+        })?
+}
+
+/// Live task states, by task name and id.
+type States = HashMap<(String, String), TaskState>;
+
+/// Outcomes of completed tasks and expiry times (ms), by task name and id.
+type Outcomes = HashMap<(String, String), (TaskOutcome, u64)>;
+
+/// Store content, guarded by the store lock.
+struct Journal {
+    states: States,
+    outcomes: Outcomes,
+    /// Journal file, opened in append mode.
+    file: Option<File>,
+    /// Number of records in the journal file.
+    records: usize,
+}
+
+/// File based (durable) task store implementation.
+/// Every change is appended to a journal file, which is replayed on `init`
+/// and periodically compacted into a snapshot of the live states.
+#[derive(Clone)]
+pub struct FileTaskStore {
+    manager: String,
+    path: PathBuf,
+    compaction_threshold: usize,
+    journal: Arc<Mutex<Journal>>,
+}
+
+impl FileTaskStore {
+    /// Create a new task store, persisting its journal into the given directory.
+    pub fn new(manager_name: &str, dir: impl AsRef<Path>) -> Self {
+        Self {
+            manager: manager_name.to_string(),
+            path: dir.as_ref().join(format!("{}.journal", manager_name)),
+            compaction_threshold: DEFAULT_COMPACTION_THRESHOLD,
+            journal: Arc::new(Mutex::new(Journal {
+                states: HashMap::new(),
+                outcomes: HashMap::new(),
+                file: None,
+                records: 0,
+            })),
+        }
+    }
+
+    /// Set the number of obsolete journal records tolerated before compacting.
+    pub fn with_compaction_threshold(mut self, threshold: usize) -> Self {
+        self.compaction_threshold = threshold;
+        self
+    }
+
+    /// Path of the journal file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Apply a record to the live states and outcomes.
+    fn apply(states: &mut States, outcomes: &mut Outcomes, record: Record) -> Result<(), TaskStoreError> {
+        match record {
+            Record::Save(state) => {
+                let state = *state;
+                states.insert((state.task_name.clone(), state.task_id.clone()), state);
+            }
+            Record::Update(name, id, fields) => {
+                if let Some(state) = states.get_mut(&(name, id)) {
+                    for (k, v) in fields {
+                        state.set_field(&k, &v).map_err(TaskStoreError::serialization)?;
+                    }
+                }
+            }
+            Record::Delete(name, id) => {
+                states.remove(&(name, id));
+            }
+            // Outcomes are kept when the store is cleared
+            Record::Clear => states.clear(),
+            Record::Outcome(name, id, outcome, expiry_time) => {
+                outcomes.insert((name, id), (outcome, expiry_time));
+            }
+        }
+        Ok(())
+    }
+
+    /// Open the journal file in append mode, creating it (and its directory) if needed.
+    /// A record torn by a failed write is truncated, so that next records start on their own line.
+    pub(crate) fn open(path: &Path) -> Result<File, TaskStoreError> {
+        Self::create_dir(path)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        let len = file.metadata()?.len();
+        let end = Self::last_line_end(&mut file, len)?;
+        if end < len {
+            log::warn!(
+                "truncating incomplete record at the end of journal {}",
+                path.display()
+            );
+            file.set_len(end)?;
+            file.sync_data()?;
+        }
+        Ok(file)
+    }
+
+    /// Return the position following the last end of line of the file (0 if none).
+    fn last_line_end(file: &mut File, len: u64) -> Result<u64, TaskStoreError> {
+        let mut buf = [0u8; 4096];
+        let mut end = len;
+        while end > 0 {
+            let start = end.saturating_sub(buf.len() as u64);
+            let chunk = &mut buf[..(end - start) as usize];
+            file.seek(SeekFrom::Start(start))?;
+            file.read_exact(chunk)?;
+            if let Some(pos) = chunk.iter().rposition(|b| *b == b'\n') {
+                return Ok(start + pos as u64 + 1);
+            }
+            end = start;
+        }
+        Ok(0)
+    }
+
+    /// Create the journal directory if needed.
+    fn create_dir(path: &Path) -> Result<(), TaskStoreError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(())
+    }
+
+    /// Replay the journal (if any) into live states and outcomes.
+    fn replay(path: &Path, manager: &str) -> Result<(States, Outcomes), TaskStoreError> {
+        let mut states = HashMap::new();
+        let mut outcomes = HashMap::new();
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok((states, outcomes)),
+            Err(err) => return Err(err.into()),
+        };
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 0 {
+            // A line without end of line is a record torn by a crash: drop it
+            if !line.ends_with('\n') {
+                log::warn!(
+                    "dropping incomplete record at the end of journal {}",
+                    path.display()
+                );
+                break;
+            }
+            let record = Record::decode(line.trim_end_matches('\n'), manager)?;
+            Self::apply(&mut states, &mut outcomes, record)?;
+            line.clear();
+        }
+        Ok((states, outcomes))
+    }
+
+    /// Durably append a record to the journal and apply it to the live states.
+    async fn append(&self, journal: &mut Journal, record: Record) -> Result<(), TaskStoreError> {
+        // The file is moved to the blocking thread, and reopened on next append if the write failed
+        let file = journal.file.take();
+        let path = self.path.clone();
+        let line = record.encode();
+        let file = blocking(move || {
+            let mut file = match file {
+                Some(file) => file,
+                None => Self::open(&path)?,
+            };
+            file.write_all(line.as_bytes())?;
+            file.sync_data()?;
+            Ok(file)
+        })
+        .await?;
+        journal.file = Some(file);
+        journal.records += 1;
+        Self::apply(&mut journal.states, &mut journal.outcomes, record)?;
+
+        let live = journal.states.len() + journal.outcomes.len();
+        if journal.records.saturating_sub(live) >= self.compaction_threshold {
+            self.compact(journal).await?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the journal with only the live states and the outcomes not expired yet.
+    /// The snapshot is written aside then renamed, so a crash never leaves a partial journal.
+    async fn compact(&self, journal: &mut Journal) -> Result<(), TaskStoreError> {
+        let now = now_millis();
+        journal.outcomes.retain(|_, (_, expiry_time)| *expiry_time >= now);
+        let states = journal
+            .states
+            .values()
+            .map(|state| Record::Save(Box::new(state.clone())).encode());
+        let outcomes = journal.outcomes.iter().map(|((name, id), (outcome, expiry_time))| {
+            Record::Outcome(name.clone(), id.clone(), outcome.clone(), *expiry_time).encode()
+        });
+        let snapshot: String = states.chain(outcomes).collect();
+        let path = self.path.clone();
+        journal.file = None;
+        let file = blocking(move || {
+            Self::create_dir(&path)?;
+            let tmp_path = path.with_extension("journal.tmp");
+            {
+                let mut tmp = File::create(&tmp_path)?;
+                tmp.write_all(snapshot.as_bytes())?;
+                tmp.sync_all()?;
+            }
+            fs::rename(&tmp_path, &path)?;
+            Self::open(&path)
+        })
+        .await?;
+        journal.file = Some(file);
+        journal.records = journal.states.len() + journal.outcomes.len();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TaskStore for FileTaskStore {
+    fn manager_name(&self) -> String {
+        self.manager.clone()
+    }
+
+    async fn init(&self) -> Result<(), TaskStoreError> {
+        let mut journal = self.journal.lock().await;
+        journal.states.clear();
+        journal.outcomes.clear();
+        journal.file = None;
+        journal.records = 0;
+
+        // Replay journal (if any)
+        let path = self.path.clone();
+        let manager = self.manager.clone();
+        (journal.states, journal.outcomes) = blocking(move || Self::replay(&path, &manager)).await?;
+
+        // Start from a clean journal
+        self.compact(&mut journal).await
+    }
+
+    async fn save_state(&self, task: &dyn Task) -> Result<TaskState, TaskStoreError> {
+        self.try_insert_state(task)
+            .await?
+            .ok_or_else(|| TaskStoreError::duplicate(task))
+    }
+
+    async fn try_insert_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        self.try_insert_state_with(task, StateUpdate::default()).await
+    }
+
+    async fn try_insert_state_with(
+        &self,
+        task: &dyn Task,
+        initial: StateUpdate,
+    ) -> Result<Option<TaskState>, TaskStoreError> {
+        let mut journal = self.journal.lock().await;
+        if journal.states.contains_key(&(task.name(), task.id())) {
+            return Ok(None);
+        }
+
+        let state = TaskState::new(task, &self.manager, None, now_millis()).with_initial(initial);
+        self.append(&mut journal, Record::Save(Box::new(state.clone()))).await?;
+
+        Ok(Some(state))
+    }
+
+    async fn delete_state(&self, task: &dyn Task) -> Result<(), TaskStoreError> {
+        let mut journal = self.journal.lock().await;
+        if !journal.states.contains_key(&(task.name(), task.id())) {
+            return Err(TaskStoreError::not_found(task));
+        }
+        self.append(&mut journal, Record::Delete(task.name(), task.id()))
+            .await
+    }
+
+    async fn get_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        Ok(self
+            .journal
+            .lock()
+            .await
+            .states
+            .get(&(task.name(), task.id()))
+            .cloned())
+    }
+
+    async fn count_tasks(&self) -> Result<usize, TaskStoreError> {
+        Ok(self.journal.lock().await.states.len())
+    }
+
+    async fn update_status(
+        &self,
+        task: &dyn Task,
+        status: TaskStatus,
+    ) -> Result<(), TaskStoreError> {
+        self.update_state(task, StateUpdate::status(status, now_millis()))
+            .await
+    }
+
+    async fn update_state(&self, task: &dyn Task, update: StateUpdate) -> Result<(), TaskStoreError> {
+        let mut journal = self.journal.lock().await;
+        if !journal.states.contains_key(&(task.name(), task.id())) {
+            return Err(TaskStoreError::not_found(task));
+        }
+        let fields = update
+            .fields()
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect();
+        self.append(&mut journal, Record::Update(task.name(), task.id(), fields))
+            .await
+    }
+
+    async fn update_pending_state(
+        &self,
+        task: &dyn Task,
+        update: StateUpdate,
+    ) -> Result<bool, TaskStoreError> {
+        let mut journal = self.journal.lock().await;
+        match journal.states.get(&(task.name(), task.id())) {
+            Some(state) if state.status == TaskStatus::Pending => {}
+            _ => return Ok(false),
+        }
+        let fields = update
+            .fields()
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect();
+        self.append(&mut journal, Record::Update(task.name(), task.id(), fields))
+            .await?;
+        Ok(true)
+    }
+
+    async fn clear(&self) -> Result<(), TaskStoreError> {
+        let mut journal = self.journal.lock().await;
+        self.append(&mut journal, Record::Clear).await
+    }
+
+    async fn get_all_states(&self) -> Result<Vec<TaskState>, TaskStoreError> {
+        Ok(self.journal.lock().await.states.values().cloned().collect())
+    }
+
+    async fn claim_pending(
+        &self,
+        names: &[String],
+        lease: Duration,
+    ) -> Result<Option<TaskState>, TaskStoreError> {
+        let now = now_millis();
+        let mut journal = self.journal.lock().await;
+        let Some(state) = journal
+            .states
+            .values()
+            .filter(|s| s.is_claimable(now) && names.contains(&s.task_name))
+            .min_by_key(|s| s.creation_time)
+            .cloned()
+        else {
+            return Ok(None);
+        };
+        let update = StateUpdate {
+            lease_expiry_time: Some(now + lease.as_millis() as u64),
+            ..StateUpdate::status(TaskStatus::Running, now)
+        };
+        let fields = update
+            .fields()
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect();
+        let key = (state.task_name.clone(), state.task_id.clone());
+        self.append(&mut journal, Record::Update(key.0.clone(), key.1.clone(), fields))
+            .await?;
+        Ok(journal.states.get(&key).cloned())
+    }
+
+    async fn renew_lease(&self, task: &dyn Task, lease: Duration) -> Result<bool, TaskStoreError> {
+        let now = now_millis();
+        let mut journal = self.journal.lock().await;
+        let running = journal
+            .states
+            .get(&(task.name(), task.id()))
+            .is_some_and(|s| s.status == TaskStatus::Running);
+        if !running {
+            return Ok(false);
+        }
+        let update = StateUpdate {
+            lease_expiry_time: Some(now + lease.as_millis() as u64),
+            time: now,
+            ..Default::default()
+        };
+        let fields = update
+            .fields()
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect();
+        self.append(&mut journal, Record::Update(task.name(), task.id(), fields))
+            .await?;
+        Ok(true)
+    }
+
+    async fn record_outcome(
+        &self,
+        task: &dyn Task,
+        outcome: &TaskOutcome,
+        ttl: Duration,
+    ) -> Result<(), TaskStoreError> {
+        let expiry_time = now_millis() + ttl.as_millis() as u64;
+        let mut journal = self.journal.lock().await;
+        self.append(
+            &mut journal,
+            Record::Outcome(task.name(), task.id(), outcome.clone(), expiry_time),
+        )
+        .await
+    }
+
+    async fn get_outcome(&self, task: &dyn Task) -> Result<Option<TaskOutcome>, TaskStoreError> {
+        let now = now_millis();
+        Ok(self
+            .journal
+            .lock()
+            .await
+            .outcomes
+            .get(&(task.name(), task.id()))
+            .filter(|(_, expiry_time)| *expiry_time >= now)
+            .map(|(outcome, _)| outcome.clone()))
+    }
+}
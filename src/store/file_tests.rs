@@ -0,0 +1,247 @@
+use std::{fs, io::Write, path::PathBuf, time::Duration};
+
+use async_trait::async_trait;
+use tokio::time::sleep;
+
+use crate::{
+    context::TaskContext,
+    store::TaskStatus,
+    task::{Task, TaskOutcome},
+};
+
+use super::{file::FileTaskStore, TaskStore};
+
+struct TestTask {
+    pub id: String,
+}
+
+#[async_trait]
+impl Task for TestTask {
+    fn name(&self) -> String {
+        "test_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn run(&self, _ctx: &TaskContext) {
+        // Nothing
+    }
+}
+
+/// Return an empty directory for a test.
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join("quartermaster_file_tests").join(name);
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn journal_lines(store: &FileTaskStore) -> usize {
+    fs::read_to_string(store.path()).unwrap().lines().count()
+}
+
+#[tokio::test]
+async fn create_state() {
+    let store = FileTaskStore::new("test_manager", test_dir("create_state"));
+    store.init().await.unwrap();
+    let state1 = store
+        .save_state(&TestTask {
+            id: "1".to_string(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(state1.task_id, "1");
+    let state2 = store
+        .save_state(&TestTask {
+            id: "2".to_string(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(state2.task_id, "2");
+    assert_eq!(store.count_tasks().await.unwrap(), 2);
+    assert!(store
+        .save_state(&TestTask {
+            id: "2".to_string(),
+        })
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn delete_and_update_state() {
+    let store = FileTaskStore::new("test_manager", test_dir("delete_and_update_state"));
+    store.init().await.unwrap();
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    store.save_state(&task).await.unwrap();
+    store
+        .update_status(&task, TaskStatus::Running)
+        .await
+        .unwrap();
+    let state = store.get_state(&task).await.unwrap().unwrap();
+    assert_eq!(state.status, TaskStatus::Running);
+    store.delete_state(&task).await.unwrap();
+    assert!(store.get_state(&task).await.unwrap().is_none());
+    assert!(store.delete_state(&task).await.is_err());
+}
+
+#[tokio::test]
+async fn recover() {
+    let dir = test_dir("recover");
+    let store = FileTaskStore::new("test_manager", &dir);
+    store.init().await.unwrap();
+    for id in ["1", "2", "3"] {
+        store
+            .save_state(&TestTask { id: id.to_string() })
+            .await
+            .unwrap();
+    }
+    store
+        .update_status(&TestTask { id: "1".to_string() }, TaskStatus::Running)
+        .await
+        .unwrap();
+    store
+        .delete_state(&TestTask { id: "2".to_string() })
+        .await
+        .unwrap();
+
+    // Simulate a crash while writing a record
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(store.path())
+        .unwrap();
+    file.write_all(b"delete\ttest_task\t").unwrap();
+
+    let recovered = FileTaskStore::new("test_manager", &dir);
+    recovered.init().await.unwrap();
+    let states = recovered.get_all_states().await.unwrap();
+    assert_eq!(states.len(), 2);
+    assert!(states
+        .iter()
+        .any(|s| s.task_id == "1" && s.status == TaskStatus::Running));
+    assert!(states
+        .iter()
+        .any(|s| s.task_id == "3" && s.status == TaskStatus::Pending));
+    assert_eq!(journal_lines(&recovered), 2);
+}
+
+#[tokio::test]
+async fn reopen_torn_journal() {
+    let dir = test_dir("reopen_torn_journal");
+    let store = FileTaskStore::new("test_manager", &dir);
+    store.init().await.unwrap();
+    store
+        .save_state(&TestTask { id: "1".to_string() })
+        .await
+        .unwrap();
+
+    // Simulate a failed write, the journal being reopened before the next record
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(store.path())
+        .unwrap();
+    file.write_all(b"delete\ttest_task\t").unwrap();
+    let mut file = FileTaskStore::open(store.path()).unwrap();
+    file.write_all(b"update\ttest_task\t1\tstatus=Running\n").unwrap();
+
+    let recovered = FileTaskStore::new("test_manager", &dir);
+    recovered.init().await.unwrap();
+    let state = recovered
+        .get_state(&TestTask { id: "1".to_string() })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(state.status, TaskStatus::Running);
+}
+
+#[tokio::test]
+async fn compact() {
+    let store = FileTaskStore::new("test_manager", test_dir("compact")).with_compaction_threshold(5);
+    store.init().await.unwrap();
+    store
+        .save_state(&TestTask {
+            id: "kept".to_string(),
+        })
+        .await
+        .unwrap();
+    for _ in 0..3 {
+        let task = TestTask {
+            id: "deleted".to_string(),
+        };
+        store.save_state(&task).await.unwrap();
+        store.delete_state(&task).await.unwrap();
+    }
+    assert_eq!(journal_lines(&store), 1);
+    assert_eq!(store.count_tasks().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn recover_outcomes() {
+    let dir = test_dir("recover_outcomes");
+    let store = FileTaskStore::new("test_manager", &dir).with_compaction_threshold(3);
+    store.init().await.unwrap();
+    let ttl = Duration::from_secs(60);
+    store
+        .record_outcome(&TestTask { id: "1".to_string() }, &TaskOutcome::Succeeded, ttl)
+        .await
+        .unwrap();
+    store
+        .record_outcome(&TestTask { id: "2".to_string() }, &TaskOutcome::Failed("error".to_string()), ttl)
+        .await
+        .unwrap();
+    store
+        .record_outcome(&TestTask { id: "3".to_string() }, &TaskOutcome::Succeeded, Duration::ZERO)
+        .await
+        .unwrap();
+    store.clear().await.unwrap();
+
+    let recovered = FileTaskStore::new("test_manager", &dir);
+    recovered.init().await.unwrap();
+    assert_eq!(
+        recovered.get_outcome(&TestTask { id: "1".to_string() }).await.unwrap(),
+        Some(TaskOutcome::Succeeded)
+    );
+    assert_eq!(
+        recovered.get_outcome(&TestTask { id: "2".to_string() }).await.unwrap(),
+        Some(TaskOutcome::Failed("error".to_string()))
+    );
+    sleep(Duration::from_millis(5)).await;
+    assert_eq!(recovered.get_outcome(&TestTask { id: "3".to_string() }).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn clear() {
+    let dir = test_dir("clear");
+    let store = FileTaskStore::new("test_manager", &dir);
+    store.init().await.unwrap();
+    store
+        .save_state(&TestTask {
+            id: "1".to_string(),
+        })
+        .await
+        .unwrap();
+    store.clear().await.unwrap();
+    assert_eq!(store.count_tasks().await.unwrap(), 0);
+
+    let recovered = FileTaskStore::new("test_manager", &dir);
+    recovered.init().await.unwrap();
+    assert_eq!(recovered.count_tasks().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn conformance() {
+    let factory = |manager: String, instance: String| async move {
+        let store = FileTaskStore::new(
+            &manager,
+            test_dir(&format!("conformance_{}_{}", manager, instance)),
+        );
+        store.init().await.unwrap();
+        store
+    };
+    super::conformance::run_all(factory).await;
+    super::conformance::check_claim_pending(&factory).await;
+    super::conformance::check_lease_expiry(&factory).await;
+    super::conformance::check_outcomes(&factory).await;
+}
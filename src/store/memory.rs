@@ -1,17 +1,34 @@
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{self, Arc},
+    time::Duration,
+};
 
 use async_trait::async_trait;
 use tokio::sync::RwLock;
 
-use crate::{task::Task, util::now_secs};
+use crate::{
+    clock::{Clock, SystemClock},
+    task::{Task, TaskOutcome},
+    util::to_millis,
+};
 
-use super::{TaskState, TaskStatus, TaskStore, TaskStoreError};
+use super::{StateUpdate, TaskState, TaskStatus, TaskStore, TaskStoreError};
+
+/// Lock owners and expiry times (ms), by lock name.
+type Locks = HashMap<String, (String, u64)>;
+
+/// Outcomes of completed tasks and expiry times (ms), by task name and id.
+type Outcomes = HashMap<(String, String), (TaskOutcome, u64)>;
 
 /// In Memory (thread safe) task store implementation.
 #[derive(Clone)]
 pub struct InMemoryTaskStore {
     manager: String,
     states: Arc<RwLock<HashSet<TaskState>>>,
+    locks: Arc<sync::Mutex<Locks>>,
+    outcomes: Arc<sync::Mutex<Outcomes>>,
+    clock: Arc<dyn Clock>,
 }
 
 impl InMemoryTaskStore {
@@ -20,8 +37,17 @@ impl InMemoryTaskStore {
         Self {
             manager: manager_name.to_string(),
             states: Arc::new(RwLock::new(HashSet::new())),
+            locks: Arc::new(sync::Mutex::new(HashMap::new())),
+            outcomes: Arc::new(sync::Mutex::new(HashMap::new())),
+            clock: Arc::new(SystemClock),
         }
     }
+
+    /// Use the given clock to stamp task states.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
 }
 
 #[async_trait]
@@ -35,19 +61,34 @@ impl TaskStore for InMemoryTaskStore {
         Ok(())
     }
     async fn save_state(&self, task: &dyn Task) -> Result<TaskState, TaskStoreError> {
-        // Insert new task state
-        let state = TaskState {
-            id: None,
-            task_id: task.id(),
-            task_name: task.name(),
-            task_manager: self.manager.to_string(),
-            instance: None,
-            status: super::TaskStatus::Pending,
-            creation_time: now_secs(),
-        };
-        self.states.write().await.insert(state.clone());
+        self.try_insert_state(task)
+            .await?
+            .ok_or_else(|| TaskStoreError::duplicate(task))
+    }
+
+    async fn try_insert_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        self.try_insert_state_with(task, StateUpdate::default()).await
+    }
+
+    async fn try_insert_state_with(
+        &self,
+        task: &dyn Task,
+        initial: StateUpdate,
+    ) -> Result<Option<TaskState>, TaskStoreError> {
+        // Hold the lock between the check and the insert
+        let mut states = self.states.write().await;
+        if states
+            .iter()
+            .any(|s| s.task_id == task.id() && s.task_name == task.name())
+        {
+            return Ok(None);
+        }
+
+        let state = TaskState::new(task, &self.manager, None, to_millis(self.clock.now()))
+            .with_initial(initial);
+        states.insert(state.clone());
 
-        Ok(state)
+        Ok(Some(state))
     }
 
     async fn delete_state(&self, task: &dyn Task) -> Result<(), TaskStoreError> {
@@ -56,11 +97,7 @@ impl TaskStore for InMemoryTaskStore {
                 self.states.write().await.remove(&s);
                 Ok(())
             }
-            None => Err(TaskStoreError::NotFound(format!(
-                "task {} with id {} was not found",
-                task.name(),
-                task.id()
-            ))),
+            None => Err(TaskStoreError::not_found(task)),
         }
     }
 
@@ -83,19 +120,47 @@ impl TaskStore for InMemoryTaskStore {
         task: &dyn Task,
         status: TaskStatus,
     ) -> Result<(), TaskStoreError> {
-        match self.get_state(task).await? {
+        self.update_state(task, StateUpdate::status(status, to_millis(self.clock.now())))
+            .await
+    }
+
+    async fn update_state(&self, task: &dyn Task, update: StateUpdate) -> Result<(), TaskStoreError> {
+        let mut states = self.states.write().await;
+        match states
+            .iter()
+            .find(|s| s.task_id == task.id() && s.task_name == task.name())
+            .cloned()
+        {
             Some(s) => {
                 let mut new_state = s.clone();
-                new_state.status = status;
-                self.states.write().await.remove(&s);
-                self.states.write().await.insert(new_state);
+                update.apply(&mut new_state);
+                states.remove(&s);
+                states.insert(new_state);
                 Ok(())
             }
-            None => Err(TaskStoreError::NotFound(format!(
-                "task {} with id {} was not found",
-                task.name(),
-                task.id()
-            ))),
+            None => Err(TaskStoreError::not_found(task)),
+        }
+    }
+
+    async fn update_pending_state(
+        &self,
+        task: &dyn Task,
+        update: StateUpdate,
+    ) -> Result<bool, TaskStoreError> {
+        let mut states = self.states.write().await;
+        match states
+            .iter()
+            .find(|s| s.task_id == task.id() && s.task_name == task.name())
+            .cloned()
+        {
+            Some(s) if s.status == TaskStatus::Pending => {
+                let mut new_state = s.clone();
+                update.apply(&mut new_state);
+                states.remove(&s);
+                states.insert(new_state);
+                Ok(true)
+            }
+            _ => Ok(false),
         }
     }
 
@@ -107,4 +172,99 @@ impl TaskStore for InMemoryTaskStore {
     async fn get_all_states(&self) -> Result<Vec<TaskState>, TaskStoreError> {
         Ok(self.states.read().await.clone().into_iter().collect())
     }
+
+    async fn claim_pending(
+        &self,
+        names: &[String],
+        lease: Duration,
+    ) -> Result<Option<TaskState>, TaskStoreError> {
+        let now = to_millis(self.clock.now());
+        let mut states = self.states.write().await;
+        let Some(state) = states
+            .iter()
+            .filter(|s| s.is_claimable(now) && names.contains(&s.task_name))
+            .min_by_key(|s| s.creation_time)
+            .cloned()
+        else {
+            return Ok(None);
+        };
+        let mut claimed = state.clone();
+        StateUpdate {
+            lease_expiry_time: Some(now + lease.as_millis() as u64),
+            ..StateUpdate::status(TaskStatus::Running, now)
+        }
+        .apply(&mut claimed);
+        states.remove(&state);
+        states.insert(claimed.clone());
+        Ok(Some(claimed))
+    }
+
+    async fn renew_lease(&self, task: &dyn Task, lease: Duration) -> Result<bool, TaskStoreError> {
+        let now = to_millis(self.clock.now());
+        let mut states = self.states.write().await;
+        let Some(state) = states
+            .iter()
+            .find(|s| s.task_id == task.id() && s.task_name == task.name() && s.status == TaskStatus::Running)
+            .cloned()
+        else {
+            return Ok(false);
+        };
+        let mut renewed = state.clone();
+        StateUpdate {
+            lease_expiry_time: Some(now + lease.as_millis() as u64),
+            time: now,
+            ..Default::default()
+        }
+        .apply(&mut renewed);
+        states.remove(&state);
+        states.insert(renewed);
+        Ok(true)
+    }
+
+    async fn try_lock(&self, name: &str, owner: &str, ttl: Duration) -> Result<bool, TaskStoreError> {
+        let now = to_millis(self.clock.now());
+        let mut locks = self.locks.lock().unwrap();
+        if let Some((holder, expiry_time)) = locks.get(name) {
+            if holder != owner && *expiry_time >= now {
+                return Ok(false);
+            }
+        }
+        locks.insert(name.to_string(), (owner.to_string(), now + ttl.as_millis() as u64));
+        Ok(true)
+    }
+
+    async fn unlock(&self, name: &str, owner: &str) -> Result<(), TaskStoreError> {
+        let mut locks = self.locks.lock().unwrap();
+        if locks.get(name).is_some_and(|(holder, _)| holder == owner) {
+            locks.remove(name);
+        }
+        Ok(())
+    }
+
+    async fn record_outcome(
+        &self,
+        task: &dyn Task,
+        outcome: &TaskOutcome,
+        ttl: Duration,
+    ) -> Result<(), TaskStoreError> {
+        let now = to_millis(self.clock.now());
+        let mut outcomes = self.outcomes.lock().unwrap();
+        outcomes.retain(|_, (_, expiry_time)| *expiry_time >= now);
+        outcomes.insert(
+            (task.name(), task.id()),
+            (outcome.clone(), now + ttl.as_millis() as u64),
+        );
+        Ok(())
+    }
+
+    async fn get_outcome(&self, task: &dyn Task) -> Result<Option<TaskOutcome>, TaskStoreError> {
+        let now = to_millis(self.clock.now());
+        Ok(self
+            .outcomes
+            .lock()
+            .unwrap()
+            .get(&(task.name(), task.id()))
+            .filter(|(_, expiry_time)| *expiry_time >= now)
+            .map(|(outcome, _)| outcome.clone()))
+    }
 }
@@ -1,29 +1,143 @@
-use std::fmt::Display;
+use std::{error::Error, fmt::Display, time::Duration};
 
 use async_trait::async_trait;
 
-use crate::task::Task;
+use crate::task::{Task, TaskOutcome};
 
-use self::state::{TaskState, TaskStatus};
+use self::state::{StateUpdate, TaskState, TaskStatus};
 
 pub mod state;
 pub mod memory;
 #[cfg(test)]
 pub mod memory_tests;
+pub mod file;
+#[cfg(test)]
+pub mod file_tests;
+#[cfg(any(test, feature = "test-util"))]
+pub mod conformance;
+pub mod cache;
+#[cfg(test)]
+pub mod cache_tests;
+pub mod resilient;
+#[cfg(test)]
+pub mod resilient_tests;
 #[cfg(feature = "mongodb")]
 pub mod mongodb;
+#[cfg(all(test, feature = "mongodb"))]
+pub mod mongodb_tests;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(all(test, feature = "postgres"))]
+pub mod postgres_tests;
+#[cfg(feature = "redis")]
+pub mod redis;
+#[cfg(all(test, feature = "redis"))]
+pub mod redis_tests;
 
 
+/// Boxed source error, as returned by a store backend.
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Task store error.
 #[derive(Debug)]
 pub enum TaskStoreError {
-    Data(String),
-    Io(String),
+    /// A state already exists for the task.
+    Duplicate(String),
+    /// No state exists for the task.
     NotFound(String),
+    /// The store backend could not be reached.
+    Connection {
+        message: String,
+        source: Option<BoxError>,
+    },
+    /// The store backend did not answer in time.
+    Timeout {
+        message: String,
+        source: Option<BoxError>,
+    },
+    /// A task state could not be written to or read from the store backend format.
+    Serialization {
+        message: String,
+        source: Option<BoxError>,
+    },
+    /// Local storage failure.
+    Io {
+        message: String,
+        source: Option<BoxError>,
+    },
+    /// Any other store backend failure.
+    Backend {
+        message: String,
+        source: Option<BoxError>,
+    },
+}
+
+impl TaskStoreError {
+    /// Return true if the failure is transient, and the store call may succeed if retried.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            TaskStoreError::Connection { .. } | TaskStoreError::Timeout { .. }
+        )
+    }
+
+    /// Create a serialization error without source error.
+    pub fn serialization(message: impl Into<String>) -> Self {
+        TaskStoreError::Serialization {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    /// Create a duplicate error for a task.
+    pub fn duplicate(task: &dyn Task) -> Self {
+        TaskStoreError::Duplicate(format!(
+            "task {} with id {} already exists",
+            task.name(),
+            task.id()
+        ))
+    }
+
+    /// Create a not found error for a task.
+    pub fn not_found(task: &dyn Task) -> Self {
+        TaskStoreError::NotFound(format!(
+            "task {} with id {} was not found",
+            task.name(),
+            task.id()
+        ))
+    }
 }
 
 impl Display for TaskStoreError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            TaskStoreError::Duplicate(message) => write!(f, "duplicate task state: {}", message),
+            TaskStoreError::NotFound(message) => write!(f, "task state not found: {}", message),
+            TaskStoreError::Connection { message, .. } => {
+                write!(f, "store connection error: {}", message)
+            }
+            TaskStoreError::Timeout { message, .. } => write!(f, "store timeout: {}", message),
+            TaskStoreError::Serialization { message, .. } => {
+                write!(f, "task state serialization error: {}", message)
+            }
+            TaskStoreError::Io { message, .. } => write!(f, "store I/O error: {}", message),
+            TaskStoreError::Backend { message, .. } => write!(f, "store error: {}", message),
+        }
+    }
+}
+
+impl Error for TaskStoreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TaskStoreError::Duplicate(_) | TaskStoreError::NotFound(_) => None,
+            TaskStoreError::Connection { source, .. }
+            | TaskStoreError::Timeout { source, .. }
+            | TaskStoreError::Serialization { source, .. }
+            | TaskStoreError::Io { source, .. }
+            | TaskStoreError::Backend { source, .. } => source
+                .as_ref()
+                .map(|s| s.as_ref() as &(dyn Error + 'static)),
+        }
     }
 }
 
@@ -37,6 +151,27 @@ pub trait TaskStore: Sized + Send + Sync + Clone {
     async fn init(&self) -> Result<(), TaskStoreError>;
     /// If successful, return a task state with a unique identifier.
     async fn save_state(&self, task: &dyn Task) -> Result<TaskState, TaskStoreError>;
+    /// Atomically check that no state exists for the task and save a new one.
+    /// Return the new task state, or None if a state already exists.
+    async fn try_insert_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError>;
+    /// Same as `try_insert_state`, setting the given fields on the new state.
+    /// Default implementation writes the fields right after the insertion, built-in stores write them atomically.
+    async fn try_insert_state_with(
+        &self,
+        task: &dyn Task,
+        initial: StateUpdate,
+    ) -> Result<Option<TaskState>, TaskStoreError> {
+        let Some(state) = self.try_insert_state(task).await? else {
+            return Ok(None);
+        };
+        let state = state.with_initial(initial.clone());
+        self.update_state(task, StateUpdate {
+            time: state.creation_time,
+            ..initial
+        })
+        .await?;
+        Ok(Some(state))
+    }
     /// Delete task state.
     async fn delete_state(&self, task: &dyn Task) -> Result<(), TaskStoreError>;
     /// Retrieve a task state.
@@ -49,8 +184,122 @@ pub trait TaskStore: Sized + Send + Sync + Clone {
         task: &dyn Task,
         status: TaskStatus,
     ) -> Result<(), TaskStoreError>;
+    /// Apply a partial update to a task state.
+    /// Stores not tracking timing details only update the status.
+    async fn update_state(&self, task: &dyn Task, update: StateUpdate) -> Result<(), TaskStoreError> {
+        match update.status {
+            Some(status) => self.update_status(task, status).await,
+            None => Ok(()),
+        }
+    }
     /// Clear store.
     async fn clear(&self) -> Result<(), TaskStoreError>;
     /// Return all the task states of the store.
     async fn get_all_states(&self) -> Result<Vec<TaskState>, TaskStoreError>;
+    /// Atomically claim the oldest claimable task of the manager, created by any instance,
+    /// among the tasks with a payload and one of the given names:
+    /// a pending task, or a running task whose lease expired.
+    /// The claimed state is marked as running, moved to the store instance, and leased for the given duration.
+    /// Return None if there is no such task.
+    /// Default implementation fails: the store does not support shared queues.
+    async fn claim_pending(
+        &self,
+        _names: &[String],
+        _lease: Duration,
+    ) -> Result<Option<TaskState>, TaskStoreError> {
+        Err(TaskStoreError::Backend {
+            message: "shared queue is not supported by the store".to_string(),
+            source: None,
+        })
+    }
+    /// Extend the lease of a running task claimed by the store instance, to the given duration from now.
+    /// Return false if the task is no longer running on the store instance (the lease was lost).
+    /// Default implementation fails: the store does not support shared queues.
+    async fn renew_lease(&self, _task: &dyn Task, _lease: Duration) -> Result<bool, TaskStoreError> {
+        Err(TaskStoreError::Backend {
+            message: "shared queue is not supported by the store".to_string(),
+            source: None,
+        })
+    }
+    /// Atomically apply a partial update to a task state, only if the task is still pending.
+    /// Return false if the task is not pending (e.g. claimed meanwhile by another instance).
+    /// Default implementation fails: the store does not support shared queues.
+    async fn update_pending_state(
+        &self,
+        _task: &dyn Task,
+        _update: StateUpdate,
+    ) -> Result<bool, TaskStoreError> {
+        Err(TaskStoreError::Backend {
+            message: "shared queue is not supported by the store".to_string(),
+            source: None,
+        })
+    }
+    /// Record that the store instance is alive.
+    /// Default implementation does nothing: the store is not shared between instances.
+    async fn heartbeat(&self) -> Result<(), TaskStoreError> {
+        Ok(())
+    }
+    /// Return the other instances of the manager whose last heartbeat is older than the given timeout.
+    /// Instances which never sent a heartbeat are not reported.
+    async fn dead_instances(&self, _timeout: Duration) -> Result<Vec<String>, TaskStoreError> {
+        Ok(vec![])
+    }
+    /// Atomically move the task states of the manager owned by another instance to the store instance,
+    /// set them back to pending, and forget the instance heartbeat.
+    /// Return the moved states (concurrent calls move each state once).
+    async fn adopt_instance(&self, _instance: &str) -> Result<Vec<TaskState>, TaskStoreError> {
+        Ok(vec![])
+    }
+    /// Atomically acquire the leadership of the manager for the store instance, for the given lease from now:
+    /// if there is no leader, if the leader lease expired, or to renew the lease of the store instance.
+    /// Return false if another instance holds the leadership.
+    /// Default implementation always succeeds: the store is not shared between instances.
+    async fn acquire_leadership(&self, _lease: Duration) -> Result<bool, TaskStoreError> {
+        Ok(true)
+    }
+    /// Release the leadership of the manager, if held by the store instance.
+    async fn resign_leadership(&self) -> Result<(), TaskStoreError> {
+        Ok(())
+    }
+    /// Atomically acquire the named lock of the manager for the given owner, until the given time to live elapses:
+    /// if the lock is free, if its time to live elapsed, or to extend it for the same owner.
+    /// Return false if the lock is held by another owner.
+    /// Default implementation fails: the store does not support locks.
+    async fn try_lock(&self, _name: &str, _owner: &str, _ttl: Duration) -> Result<bool, TaskStoreError> {
+        Err(TaskStoreError::Backend {
+            message: "locks are not supported by the store".to_string(),
+            source: None,
+        })
+    }
+    /// Release the named lock of the manager, if held by the given owner.
+    /// Default implementation fails: the store does not support locks.
+    async fn unlock(&self, _name: &str, _owner: &str) -> Result<(), TaskStoreError> {
+        Err(TaskStoreError::Backend {
+            message: "locks are not supported by the store".to_string(),
+            source: None,
+        })
+    }
+    /// Remember the outcome of a completed task of the manager, until the given time to live elapses.
+    /// A remembered outcome is replaced by the outcome of a later run.
+    /// Outcomes are kept when the store is cleared.
+    /// Default implementation fails: the store does not support idempotency windows.
+    async fn record_outcome(
+        &self,
+        _task: &dyn Task,
+        _outcome: &TaskOutcome,
+        _ttl: Duration,
+    ) -> Result<(), TaskStoreError> {
+        Err(TaskStoreError::Backend {
+            message: "idempotency windows are not supported by the store".to_string(),
+            source: None,
+        })
+    }
+    /// Return the remembered outcome of a completed task of the manager, unless its time to live elapsed.
+    /// Default implementation fails: the store does not support idempotency windows.
+    async fn get_outcome(&self, _task: &dyn Task) -> Result<Option<TaskOutcome>, TaskStoreError> {
+        Err(TaskStoreError::Backend {
+            message: "idempotency windows are not supported by the store".to_string(),
+            source: None,
+        })
+    }
 }
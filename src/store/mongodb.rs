@@ -1,24 +1,106 @@
 use async_trait::async_trait;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 use futures::TryStreamExt;
 
-use crate::util::now_secs;
+use crate::{
+    clock::{Clock, SystemClock},
+    task::TaskOutcome,
+    util::to_millis,
+};
 
 use super::{
-    state::{TaskState, TaskStatus},
-    TaskStore, TaskStoreError,
+    state::{StateUpdate, TaskState, TaskStatus},
+    BoxError, TaskStore, TaskStoreError,
 };
 
 use mongodb::{
-    bson::{doc, Bson},
-    options::IndexOptions,
+    bson::{doc, Bson, Document},
+    error::{ErrorKind, WriteFailure},
+    options::{IndexOptions, ReturnDocument},
     Collection, Database, IndexModel,
 };
 
+/// MongoDB duplicate key error code.
+const DUPLICATE_KEY_CODE: i32 = 11000;
+
+/// Name of the unique index created by previous versions.
+const LEGACY_INDEX_NAME: &str = "task_id_1_task_name_1";
+
 impl From<mongodb::error::Error> for TaskStoreError {
     fn from(err: mongodb::error::Error) -> Self {
-        Self::Data(err.to_string())
+        let message = err.to_string();
+        if is_duplicate_key(&err) {
+            return Self::Duplicate(message);
+        }
+        let source: Option<BoxError> = Some(Box::new(err.clone()));
+        match *err.kind {
+            ErrorKind::Io(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                Self::Timeout { message, source }
+            }
+            ErrorKind::Io(_)
+            | ErrorKind::ServerSelection { .. }
+            | ErrorKind::ConnectionPoolCleared { .. }
+            | ErrorKind::DnsResolve { .. } => Self::Connection { message, source },
+            ErrorKind::BsonDeserialization(_) | ErrorKind::BsonSerialization(_) => {
+                Self::Serialization { message, source }
+            }
+            _ => Self::Backend { message, source },
+        }
+    }
+}
+
+/// Check if an error is a unique index violation.
+fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    matches!(
+        *err.kind,
+        ErrorKind::Write(WriteFailure::WriteError(ref e)) if e.code == DUPLICATE_KEY_CODE
+    )
+}
+
+/// Build the `$set` document of a partial state update.
+fn set_document(update: StateUpdate) -> Document {
+    let mut set = Document::new();
+    set.insert("last_update_time", update.time as i64);
+    if let Some(status) = update.status {
+        set.insert("status", status);
+    }
+    if let Some(time) = update.started_time {
+        set.insert("started_time", time as i64);
+    }
+    if let Some(time) = update.finished_time {
+        set.insert("finished_time", time as i64);
+    }
+    if let Some(worker) = update.worker {
+        set.insert("worker", worker as i64);
+    }
+    if let Some(host) = update.host {
+        set.insert("host", host);
+    }
+    if let Some(progress) = update.progress {
+        set.insert("progress", progress as i32);
+    }
+    if let Some(message) = update.progress_message {
+        set.insert("progress_message", message);
+    }
+    if let Some(time) = update.lease_expiry_time {
+        set.insert("lease_expiry_time", time as i64);
     }
+    if let Some(attempt) = update.attempt {
+        set.insert("attempt", attempt as i64);
+    }
+    for (field, value) in [
+        ("parent_name", update.parent_name),
+        ("parent_id", update.parent_id),
+        ("root_name", update.root_name),
+        ("root_id", update.root_id),
+        ("batch_id", update.batch_id),
+        ("payload", update.payload),
+    ] {
+        if let Some(value) = value {
+            set.insert(field, value);
+        }
+    }
+    set
 }
 
 impl From<TaskStatus> for Bson {
@@ -33,6 +115,7 @@ pub struct MongoDBTaskStore {
     manager: String,
     instance: String,
     db: Arc<Database>,
+    clock: Arc<dyn Clock>,
 }
 
 impl MongoDBTaskStore {
@@ -41,12 +124,39 @@ impl MongoDBTaskStore {
             manager: manager_name.to_string(),
             instance: instance_name.to_string(),
             db,
+            clock: Arc::new(SystemClock),
         }
     }
 
+    /// Use the given clock to stamp task states.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
     fn collection(&self) -> Collection<TaskState> {
         self.db.collection("TaskState")
     }
+
+    /// Collection of the instance heartbeats.
+    fn instances(&self) -> Collection<Document> {
+        self.db.collection("TaskInstance")
+    }
+
+    /// Collection of the manager leaders.
+    fn leaders(&self) -> Collection<Document> {
+        self.db.collection("TaskLeader")
+    }
+
+    /// Collection of the named locks.
+    fn locks(&self) -> Collection<Document> {
+        self.db.collection("TaskLock")
+    }
+
+    /// Collection of the remembered outcomes of completed tasks.
+    fn outcomes(&self) -> Collection<Document> {
+        self.db.collection("TaskOutcome")
+    }
 }
 
 #[async_trait]
@@ -57,12 +167,40 @@ impl TaskStore for MongoDBTaskStore {
 
     async fn init(&self) -> Result<(), TaskStoreError> {
         let col = self.collection();
-        // Index: task_id + task_name
+        // Legacy index (task_id + task_name) prevented running a same task on different managers
+        if col.drop_index(LEGACY_INDEX_NAME).await.is_ok() {
+            log::info!("dropped legacy task state index `{}`", LEGACY_INDEX_NAME);
+        }
+        // Index: task_manager + task_name + task_id
         let model = IndexModel::builder()
-            .keys(doc! {"task_id": 1u32, "task_name": 1u32})
+            .keys(doc! {"task_manager": 1u32, "task_name": 1u32, "task_id": 1u32})
             .options(IndexOptions::builder().unique(true).build())
             .build();
         col.create_index(model).await?;
+        // Index: task_manager + instance
+        let model = IndexModel::builder()
+            .keys(doc! {"task_manager": 1u32, "instance": 1u32})
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.instances().create_index(model).await?;
+        // Index: task_manager (one leader per manager)
+        let model = IndexModel::builder()
+            .keys(doc! {"task_manager": 1u32})
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.leaders().create_index(model).await?;
+        // Index: task_manager + name
+        let model = IndexModel::builder()
+            .keys(doc! {"task_manager": 1u32, "name": 1u32})
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.locks().create_index(model).await?;
+        // Index: task_manager + task_name + task_id
+        let model = IndexModel::builder()
+            .keys(doc! {"task_manager": 1u32, "task_name": 1u32, "task_id": 1u32})
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.outcomes().create_index(model).await?;
         Ok(())
     }
 
@@ -70,24 +208,43 @@ impl TaskStore for MongoDBTaskStore {
         &self,
         task: &dyn crate::task::Task,
     ) -> Result<super::TaskState, super::TaskStoreError> {
+        self.try_insert_state(task)
+            .await?
+            .ok_or_else(|| TaskStoreError::duplicate(task))
+    }
+
+    async fn try_insert_state(
+        &self,
+        task: &dyn crate::task::Task,
+    ) -> Result<Option<super::TaskState>, super::TaskStoreError> {
+        self.try_insert_state_with(task, StateUpdate::default()).await
+    }
+
+    async fn try_insert_state_with(
+        &self,
+        task: &dyn crate::task::Task,
+        initial: StateUpdate,
+    ) -> Result<Option<super::TaskState>, super::TaskStoreError> {
         // Create state
-        let state = TaskState {
-            id: None,
-            task_id: task.id(),
-            task_name: task.name(),
-            task_manager: self.manager.to_string(),
-            instance: Some(self.instance.to_string()),
-            status: super::TaskStatus::Pending,
-            creation_time: now_secs(),
-        };
+        let state = TaskState::new(
+            task,
+            &self.manager,
+            Some(self.instance.to_string()),
+            to_millis(self.clock.now()),
+        )
+        .with_initial(initial);
 
-        // Store state
+        // Store state, the unique index rejects an already existing task
         let col = self.collection();
-        let inserted = col.insert_one(state).await?;
+        let inserted = match col.insert_one(state).await {
+            Ok(inserted) => inserted,
+            Err(err) if is_duplicate_key(&err) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
         let filter = doc! {"_id": inserted.inserted_id};
         let result = col.find_one(filter).await?;
 
-        Ok(result.unwrap())
+        Ok(result)
     }
 
     async fn delete_state(
@@ -129,19 +286,43 @@ impl TaskStore for MongoDBTaskStore {
         task: &dyn crate::task::Task,
         status: super::TaskStatus,
     ) -> Result<(), super::TaskStoreError> {
-        // Retrieve task state
-        if let Some(state) = self.get_state(task).await? {
-            // Update if found
-            let col = self.collection();
-            let filter = doc! {"_id": state.id};
-            let update = doc! {"$set": {
-                "status": status}
-            };
-            col.update_one(filter, update).await?;
-        }
+        self.update_state(task, StateUpdate::status(status, to_millis(self.clock.now())))
+            .await
+    }
+
+    async fn update_state(
+        &self,
+        task: &dyn crate::task::Task,
+        update: StateUpdate,
+    ) -> Result<(), super::TaskStoreError> {
+        let set = set_document(update);
+
+        // Update if found
+        let col = self.collection();
+        let filter = doc! {"task_manager": &self.manager, "task_name": task.name(), "task_id": task.id()};
+        col.update_one(filter, doc! {"$set": set}).await?;
         Ok(())
     }
 
+    async fn update_pending_state(
+        &self,
+        task: &dyn crate::task::Task,
+        update: StateUpdate,
+    ) -> Result<bool, super::TaskStoreError> {
+        let set = set_document(update);
+
+        // Update if found and still pending
+        let col = self.collection();
+        let filter = doc! {
+            "task_manager": &self.manager,
+            "task_name": task.name(),
+            "task_id": task.id(),
+            "status": TaskStatus::Pending,
+        };
+        let result = col.update_one(filter, doc! {"$set": set}).await?;
+        Ok(result.matched_count == 1)
+    }
+
     async fn clear(&self) -> Result<(), super::TaskStoreError> {
         let col = self.collection();
         let filter = doc! {"instance": &self.instance};
@@ -155,4 +336,211 @@ impl TaskStore for MongoDBTaskStore {
         let states = col.find(filter).await?.try_collect().await?;
         Ok(states)
     }
+
+    async fn claim_pending(
+        &self,
+        names: &[String],
+        lease: Duration,
+    ) -> Result<Option<super::TaskState>, super::TaskStoreError> {
+        let col = self.collection();
+        let now = to_millis(self.clock.now());
+        let filter = doc! {
+            "task_manager": &self.manager,
+            "payload": {"$type": "string"},
+            "task_name": {"$in": names},
+            "$or": [
+                {"status": TaskStatus::Pending},
+                {"status": TaskStatus::Running, "lease_expiry_time": {"$lt": now as i64}},
+            ],
+        };
+        let update = doc! {"$set": {
+            "status": TaskStatus::Running,
+            "instance": &self.instance,
+            "last_update_time": now as i64,
+            "lease_expiry_time": (now + lease.as_millis() as u64) as i64,
+        }};
+        let state = col
+            .find_one_and_update(filter, update)
+            .sort(doc! {"creation_time": 1})
+            .return_document(ReturnDocument::After)
+            .await?;
+        Ok(state)
+    }
+
+    async fn renew_lease(
+        &self,
+        task: &dyn crate::task::Task,
+        lease: Duration,
+    ) -> Result<bool, super::TaskStoreError> {
+        let now = to_millis(self.clock.now());
+        let filter = doc! {
+            "task_manager": &self.manager,
+            "task_name": task.name(),
+            "task_id": task.id(),
+            "instance": &self.instance,
+            "status": TaskStatus::Running,
+        };
+        let update = doc! {"$set": {
+            "last_update_time": now as i64,
+            "lease_expiry_time": (now + lease.as_millis() as u64) as i64,
+        }};
+        let result = self.collection().update_one(filter, update).await?;
+        Ok(result.matched_count == 1)
+    }
+
+    async fn heartbeat(&self) -> Result<(), super::TaskStoreError> {
+        let filter = doc! {"task_manager": &self.manager, "instance": &self.instance};
+        let update = doc! {"$set": {"heartbeat_time": to_millis(self.clock.now()) as i64}};
+        self.instances().update_one(filter, update).upsert(true).await?;
+        Ok(())
+    }
+
+    async fn dead_instances(&self, timeout: Duration) -> Result<Vec<String>, super::TaskStoreError> {
+        let limit = to_millis(self.clock.now()).saturating_sub(timeout.as_millis() as u64);
+        let filter = doc! {
+            "task_manager": &self.manager,
+            "instance": {"$ne": &self.instance},
+            "heartbeat_time": {"$lt": limit as i64},
+        };
+        let docs: Vec<Document> = self.instances().find(filter).await?.try_collect().await?;
+        docs.iter()
+            .map(|d| {
+                d.get_str("instance")
+                    .map(|instance| instance.to_string())
+                    .map_err(|err| TaskStoreError::serialization(err.to_string()))
+            })
+            .collect()
+    }
+
+    async fn adopt_instance(&self, instance: &str) -> Result<Vec<super::TaskState>, super::TaskStoreError> {
+        self.instances()
+            .delete_one(doc! {"task_manager": &self.manager, "instance": instance})
+            .await?;
+
+        // States are moved one by one, each by a single adopter
+        let col = self.collection();
+        let filter = doc! {"task_manager": &self.manager, "instance": instance};
+        let mut states = vec![];
+        loop {
+            let update = doc! {"$set": {
+                "status": TaskStatus::Pending,
+                "instance": &self.instance,
+                "last_update_time": to_millis(self.clock.now()) as i64,
+            }};
+            match col
+                .find_one_and_update(filter.clone(), update)
+                .return_document(ReturnDocument::After)
+                .await?
+            {
+                Some(state) => states.push(state),
+                None => return Ok(states),
+            }
+        }
+    }
+
+    async fn acquire_leadership(&self, lease: Duration) -> Result<bool, super::TaskStoreError> {
+        // Without a matching document, the upsert conflicts with the document of the current leader
+        let now = to_millis(self.clock.now());
+        let filter = doc! {
+            "task_manager": &self.manager,
+            "$or": [
+                {"instance": &self.instance},
+                {"lease_expiry_time": {"$lt": now as i64}},
+            ],
+        };
+        let update = doc! {"$set": {
+            "instance": &self.instance,
+            "lease_expiry_time": (now + lease.as_millis() as u64) as i64,
+        }};
+        match self.leaders().update_one(filter, update).upsert(true).await {
+            Ok(_) => Ok(true),
+            Err(err) if is_duplicate_key(&err) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn resign_leadership(&self) -> Result<(), super::TaskStoreError> {
+        self.leaders()
+            .delete_one(doc! {"task_manager": &self.manager, "instance": &self.instance})
+            .await?;
+        Ok(())
+    }
+
+    async fn try_lock(
+        &self,
+        name: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<bool, super::TaskStoreError> {
+        // Without a matching document, the upsert conflicts with the document of the current owner
+        let now = to_millis(self.clock.now());
+        let filter = doc! {
+            "task_manager": &self.manager,
+            "name": name,
+            "$or": [
+                {"owner": owner},
+                {"expiry_time": {"$lt": now as i64}},
+            ],
+        };
+        let update = doc! {"$set": {
+            "owner": owner,
+            "expiry_time": (now + ttl.as_millis() as u64) as i64,
+        }};
+        match self.locks().update_one(filter, update).upsert(true).await {
+            Ok(_) => Ok(true),
+            Err(err) if is_duplicate_key(&err) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn unlock(&self, name: &str, owner: &str) -> Result<(), super::TaskStoreError> {
+        self.locks()
+            .delete_one(doc! {"task_manager": &self.manager, "name": name, "owner": owner})
+            .await?;
+        Ok(())
+    }
+
+    async fn record_outcome(
+        &self,
+        task: &dyn crate::task::Task,
+        outcome: &TaskOutcome,
+        ttl: Duration,
+    ) -> Result<(), super::TaskStoreError> {
+        // Expired outcomes of the manager are purged along the way
+        let now = to_millis(self.clock.now());
+        self.outcomes()
+            .delete_many(doc! {"task_manager": &self.manager, "expiry_time": {"$lt": now as i64}})
+            .await?;
+        let filter = doc! {
+            "task_manager": &self.manager,
+            "task_name": task.name(),
+            "task_id": task.id(),
+        };
+        let update = doc! {"$set": {
+            "outcome": outcome.to_string(),
+            "expiry_time": (now + ttl.as_millis() as u64) as i64,
+        }};
+        self.outcomes().update_one(filter, update).upsert(true).await?;
+        Ok(())
+    }
+
+    async fn get_outcome(
+        &self,
+        task: &dyn crate::task::Task,
+    ) -> Result<Option<TaskOutcome>, super::TaskStoreError> {
+        let now = to_millis(self.clock.now());
+        let filter = doc! {
+            "task_manager": &self.manager,
+            "task_name": task.name(),
+            "task_id": task.id(),
+            "expiry_time": {"$gte": now as i64},
+        };
+        let Some(document) = self.outcomes().find_one(filter).await? else {
+            return Ok(None);
+        };
+        let outcome = document
+            .get_str("outcome")
+            .map_err(|err| TaskStoreError::serialization(err.to_string()))?;
+        outcome.parse().map(Some).map_err(TaskStoreError::serialization)
+    }
 }
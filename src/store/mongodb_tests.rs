@@ -0,0 +1,34 @@
+use std::sync::Arc;
+
+use mongodb::Client;
+
+use super::{mongodb::MongoDBTaskStore, TaskStore};
+
+/// Create an initialized and empty store, connected to the local test database.
+/// Connection URI can be overridden with the `QUARTERMASTER_MONGODB_URI` environment variable.
+async fn create_store(manager: &str, instance: &str) -> MongoDBTaskStore {
+    let uri = std::env::var("QUARTERMASTER_MONGODB_URI")
+        .unwrap_or_else(|_| "mongodb://localhost:27017".to_string());
+    let client = Client::with_uri_str(uri).await.unwrap();
+    let db = Arc::new(client.database("quartermaster_tests"));
+    let store = MongoDBTaskStore::new(manager, instance, db);
+    store.init().await.unwrap();
+    store.clear().await.unwrap();
+    store
+}
+
+#[tokio::test]
+async fn conformance() {
+    let factory = |manager: String, instance: String| async move {
+        create_store(&manager, &instance).await
+    };
+    super::conformance::run_all(factory).await;
+    super::conformance::check_claim_pending(&factory).await;
+    super::conformance::check_lease_expiry(&factory).await;
+    super::conformance::check_cross_instance_dedup(&factory).await;
+    super::conformance::check_cross_instance_claim(&factory).await;
+    super::conformance::check_instance_adoption(&factory).await;
+    super::conformance::check_leadership(&factory).await;
+    super::conformance::check_locks(&factory).await;
+    super::conformance::check_outcomes(&factory).await;
+}
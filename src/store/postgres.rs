@@ -0,0 +1,558 @@
+use async_trait::async_trait;
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    task::{Task, TaskOutcome},
+    util::now_millis,
+};
+
+use super::{
+    state::{StateUpdate, TaskState, TaskStatus},
+    TaskStore, TaskStoreError,
+};
+
+use tokio_postgres::{error::SqlState, Client, Row};
+
+impl From<tokio_postgres::Error> for TaskStoreError {
+    fn from(err: tokio_postgres::Error) -> Self {
+        let message = err.to_string();
+        let io_kind = std::error::Error::source(&err)
+            .and_then(|s| s.downcast_ref::<std::io::Error>())
+            .map(|e| e.kind());
+        match err.code() {
+            Some(code) if *code == SqlState::UNIQUE_VIOLATION => Self::Duplicate(message),
+            Some(code) if *code == SqlState::QUERY_CANCELED => Self::Timeout {
+                message,
+                source: Some(Box::new(err)),
+            },
+            Some(code) if code.code().starts_with("08") || *code == SqlState::ADMIN_SHUTDOWN => {
+                Self::Connection {
+                    message,
+                    source: Some(Box::new(err)),
+                }
+            }
+            _ if io_kind == Some(std::io::ErrorKind::TimedOut) => Self::Timeout {
+                message,
+                source: Some(Box::new(err)),
+            },
+            _ if err.is_closed() || io_kind.is_some() => Self::Connection {
+                message,
+                source: Some(Box::new(err)),
+            },
+            _ => Self::Backend {
+                message,
+                source: Some(Box::new(err)),
+            },
+        }
+    }
+}
+
+/// Wrap a row decoding error.
+fn serialization_error(err: tokio_postgres::Error) -> TaskStoreError {
+    TaskStoreError::Serialization {
+        message: err.to_string(),
+        source: Some(Box::new(err)),
+    }
+}
+
+/// PostgreSQL task store implementation.
+#[derive(Clone)]
+pub struct PostgresTaskStore {
+    manager: String,
+    instance: String,
+    client: Arc<Client>,
+}
+
+impl PostgresTaskStore {
+    pub fn new(manager_name: &str, instance_name: &str, client: Arc<Client>) -> Self {
+        Self {
+            manager: manager_name.to_string(),
+            instance: instance_name.to_string(),
+            client,
+        }
+    }
+
+    /// Build a task state from a `task_state` row.
+    fn state_from_row(row: &Row) -> Result<TaskState, TaskStoreError> {
+        let status: String = row.try_get("status").map_err(serialization_error)?;
+        let creation_time: i64 = row.try_get("creation_time").map_err(serialization_error)?;
+        let time = |name: &str| -> Result<Option<u64>, TaskStoreError> {
+            let time: Option<i64> = row.try_get(name).map_err(serialization_error)?;
+            Ok(time.map(|t| t as u64))
+        };
+        let last_update_time: i64 = row.try_get("last_update_time").map_err(serialization_error)?;
+        let worker: Option<i64> = row.try_get("worker").map_err(serialization_error)?;
+        let progress: Option<i16> = row.try_get("progress").map_err(serialization_error)?;
+        let attempt: i32 = row.try_get("attempt").map_err(serialization_error)?;
+        Ok(TaskState {
+            id: None,
+            task_id: row.try_get("task_id").map_err(serialization_error)?,
+            task_name: row.try_get("task_name").map_err(serialization_error)?,
+            task_manager: row.try_get("task_manager").map_err(serialization_error)?,
+            instance: row.try_get("instance").map_err(serialization_error)?,
+            status: status.parse().map_err(TaskStoreError::serialization)?,
+            creation_time: creation_time as u64,
+            started_time: time("started_time")?,
+            finished_time: time("finished_time")?,
+            last_update_time: last_update_time as u64,
+            worker: worker.map(|w| w as usize),
+            host: row.try_get("host").map_err(serialization_error)?,
+            progress: progress.map(|p| p as u8),
+            progress_message: row.try_get("progress_message").map_err(serialization_error)?,
+            parent_name: row.try_get("parent_name").map_err(serialization_error)?,
+            parent_id: row.try_get("parent_id").map_err(serialization_error)?,
+            root_name: row.try_get("root_name").map_err(serialization_error)?,
+            root_id: row.try_get("root_id").map_err(serialization_error)?,
+            batch_id: row.try_get("batch_id").map_err(serialization_error)?,
+            payload: row.try_get("payload").map_err(serialization_error)?,
+            lease_expiry_time: time("lease_expiry_time")?,
+            attempt: attempt as u32,
+        })
+    }
+
+    /// Apply a partial update to a task state, only if it has the given status when set.
+    /// Return the number of updated states.
+    async fn update_state_if(
+        &self,
+        task: &dyn Task,
+        update: StateUpdate,
+        status: Option<TaskStatus>,
+    ) -> Result<u64, TaskStoreError> {
+        // Fields left to NULL are not changed
+        let count = self
+            .client
+            .execute(
+                "UPDATE task_state SET
+                    last_update_time = $4,
+                    status = COALESCE($5, status),
+                    started_time = COALESCE($6, started_time),
+                    finished_time = COALESCE($7, finished_time),
+                    worker = COALESCE($8, worker),
+                    host = COALESCE($9, host),
+                    progress = COALESCE($10, progress),
+                    progress_message = COALESCE($11, progress_message),
+                    parent_name = COALESCE($12, parent_name),
+                    parent_id = COALESCE($13, parent_id),
+                    root_name = COALESCE($14, root_name),
+                    root_id = COALESCE($15, root_id),
+                    batch_id = COALESCE($16, batch_id),
+                    payload = COALESCE($17, payload),
+                    lease_expiry_time = COALESCE($18, lease_expiry_time),
+                    attempt = COALESCE($19, attempt)
+                WHERE task_manager = $1 AND task_name = $2 AND task_id = $3
+                    AND ($20::text IS NULL OR status = $20)",
+                &[
+                    &self.manager,
+                    &task.name(),
+                    &task.id(),
+                    &(update.time as i64),
+                    &update.status.map(|s| s.to_string()),
+                    &update.started_time.map(|t| t as i64),
+                    &update.finished_time.map(|t| t as i64),
+                    &update.worker.map(|w| w as i64),
+                    &update.host,
+                    &update.progress.map(|p| p as i16),
+                    &update.progress_message,
+                    &update.parent_name,
+                    &update.parent_id,
+                    &update.root_name,
+                    &update.root_id,
+                    &update.batch_id,
+                    &update.payload,
+                    &update.lease_expiry_time.map(|t| t as i64),
+                    &update.attempt.map(|a| a as i32),
+                    &status.map(|s| s.to_string()),
+                ],
+            )
+            .await?;
+        Ok(count)
+    }
+}
+
+#[async_trait]
+impl TaskStore for PostgresTaskStore {
+    fn manager_name(&self) -> String {
+        self.manager.to_string()
+    }
+
+    async fn init(&self) -> Result<(), TaskStoreError> {
+        // Primary key: task_manager + task_name + task_id
+        self.client
+            .batch_execute(
+                "CREATE TABLE IF NOT EXISTS task_state (
+                    task_manager TEXT NOT NULL,
+                    task_name TEXT NOT NULL,
+                    task_id TEXT NOT NULL,
+                    instance TEXT,
+                    status TEXT NOT NULL,
+                    creation_time BIGINT NOT NULL,
+                    PRIMARY KEY (task_manager, task_name, task_id)
+                );
+                CREATE INDEX IF NOT EXISTS task_state_instance_idx ON task_state (instance);
+                ALTER TABLE task_state
+                    ADD COLUMN IF NOT EXISTS started_time BIGINT,
+                    ADD COLUMN IF NOT EXISTS finished_time BIGINT,
+                    ADD COLUMN IF NOT EXISTS last_update_time BIGINT NOT NULL DEFAULT 0,
+                    ADD COLUMN IF NOT EXISTS worker BIGINT,
+                    ADD COLUMN IF NOT EXISTS host TEXT,
+                    ADD COLUMN IF NOT EXISTS progress SMALLINT,
+                    ADD COLUMN IF NOT EXISTS progress_message TEXT,
+                    ADD COLUMN IF NOT EXISTS parent_name TEXT,
+                    ADD COLUMN IF NOT EXISTS parent_id TEXT,
+                    ADD COLUMN IF NOT EXISTS root_name TEXT,
+                    ADD COLUMN IF NOT EXISTS root_id TEXT,
+                    ADD COLUMN IF NOT EXISTS batch_id TEXT,
+                    ADD COLUMN IF NOT EXISTS payload TEXT,
+                    ADD COLUMN IF NOT EXISTS lease_expiry_time BIGINT,
+                    ADD COLUMN IF NOT EXISTS attempt INTEGER NOT NULL DEFAULT 0;
+                CREATE TABLE IF NOT EXISTS task_instance (
+                    task_manager TEXT NOT NULL,
+                    instance TEXT NOT NULL,
+                    heartbeat_time BIGINT NOT NULL,
+                    PRIMARY KEY (task_manager, instance)
+                );
+                CREATE TABLE IF NOT EXISTS task_leader (
+                    task_manager TEXT NOT NULL PRIMARY KEY,
+                    instance TEXT NOT NULL,
+                    lease_expiry_time BIGINT NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS task_outcome (
+                    task_manager TEXT NOT NULL,
+                    task_name TEXT NOT NULL,
+                    task_id TEXT NOT NULL,
+                    outcome TEXT NOT NULL,
+                    expiry_time BIGINT NOT NULL,
+                    PRIMARY KEY (task_manager, task_name, task_id)
+                );",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn save_state(&self, task: &dyn Task) -> Result<TaskState, TaskStoreError> {
+        self.try_insert_state(task)
+            .await?
+            .ok_or_else(|| TaskStoreError::duplicate(task))
+    }
+
+    async fn try_insert_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        self.try_insert_state_with(task, StateUpdate::default()).await
+    }
+
+    async fn try_insert_state_with(
+        &self,
+        task: &dyn Task,
+        initial: StateUpdate,
+    ) -> Result<Option<TaskState>, TaskStoreError> {
+        let state = TaskState::new(
+            task,
+            &self.manager,
+            Some(self.instance.to_string()),
+            now_millis(),
+        )
+        .with_initial(initial);
+
+        // Insert state, unless another one already exists for the same task
+        let row = self
+            .client
+            .query_opt(
+                "INSERT INTO task_state (
+                    task_manager, task_name, task_id, instance, status, creation_time, last_update_time,
+                    started_time, finished_time, worker, host, progress, progress_message,
+                    parent_name, parent_id, root_name, root_id, batch_id, payload, lease_expiry_time,
+                    attempt)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21)
+                ON CONFLICT (task_manager, task_name, task_id) DO NOTHING
+                RETURNING *",
+                &[
+                    &state.task_manager,
+                    &state.task_name,
+                    &state.task_id,
+                    &state.instance,
+                    &state.status.to_string(),
+                    &(state.creation_time as i64),
+                    &(state.last_update_time as i64),
+                    &state.started_time.map(|t| t as i64),
+                    &state.finished_time.map(|t| t as i64),
+                    &state.worker.map(|w| w as i64),
+                    &state.host,
+                    &state.progress.map(|p| p as i16),
+                    &state.progress_message,
+                    &state.parent_name,
+                    &state.parent_id,
+                    &state.root_name,
+                    &state.root_id,
+                    &state.batch_id,
+                    &state.payload,
+                    &state.lease_expiry_time.map(|t| t as i64),
+                    &(state.attempt as i32),
+                ],
+            )
+            .await?;
+        row.as_ref().map(Self::state_from_row).transpose()
+    }
+
+    async fn delete_state(&self, task: &dyn Task) -> Result<(), TaskStoreError> {
+        self.client
+            .execute(
+                "DELETE FROM task_state WHERE task_manager = $1 AND task_name = $2 AND task_id = $3",
+                &[&self.manager, &task.name(), &task.id()],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        let row = self
+            .client
+            .query_opt(
+                "SELECT * FROM task_state WHERE task_manager = $1 AND task_name = $2 AND task_id = $3",
+                &[&self.manager, &task.name(), &task.id()],
+            )
+            .await?;
+        row.as_ref().map(Self::state_from_row).transpose()
+    }
+
+    async fn count_tasks(&self) -> Result<usize, TaskStoreError> {
+        // Count for current instance
+        let count: i64 = self
+            .client
+            .query_one(
+                "SELECT COUNT(*) FROM task_state WHERE instance = $1",
+                &[&self.instance],
+            )
+            .await?
+            .try_get(0)?;
+        Ok(count as usize)
+    }
+
+    async fn update_status(
+        &self,
+        task: &dyn Task,
+        status: TaskStatus,
+    ) -> Result<(), TaskStoreError> {
+        self.update_state(task, StateUpdate::status(status, now_millis()))
+            .await
+    }
+
+    async fn update_state(&self, task: &dyn Task, update: StateUpdate) -> Result<(), TaskStoreError> {
+        self.update_state_if(task, update, None).await?;
+        Ok(())
+    }
+
+    async fn update_pending_state(
+        &self,
+        task: &dyn Task,
+        update: StateUpdate,
+    ) -> Result<bool, TaskStoreError> {
+        let count = self
+            .update_state_if(task, update, Some(TaskStatus::Pending))
+            .await?;
+        Ok(count == 1)
+    }
+
+    async fn clear(&self) -> Result<(), TaskStoreError> {
+        self.client
+            .execute(
+                "DELETE FROM task_state WHERE instance = $1",
+                &[&self.instance],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get_all_states(&self) -> Result<Vec<TaskState>, TaskStoreError> {
+        self.client
+            .query(
+                "SELECT * FROM task_state WHERE instance = $1",
+                &[&self.instance],
+            )
+            .await?
+            .iter()
+            .map(Self::state_from_row)
+            .collect()
+    }
+
+    async fn claim_pending(
+        &self,
+        names: &[String],
+        lease: Duration,
+    ) -> Result<Option<TaskState>, TaskStoreError> {
+        // States locked by a concurrent claim are skipped
+        let now = now_millis();
+        let row = self
+            .client
+            .query_opt(
+                "UPDATE task_state SET status = $2, instance = $3, last_update_time = $4, lease_expiry_time = $7
+                WHERE (task_manager, task_name, task_id) = (
+                    SELECT task_manager, task_name, task_id FROM task_state
+                    WHERE task_manager = $1 AND payload IS NOT NULL AND task_name = ANY($6)
+                        AND (status = $5 OR (status = $2 AND lease_expiry_time < $4))
+                    ORDER BY creation_time
+                    LIMIT 1
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING *",
+                &[
+                    &self.manager,
+                    &TaskStatus::Running.to_string(),
+                    &self.instance,
+                    &(now as i64),
+                    &TaskStatus::Pending.to_string(),
+                    &names,
+                    &((now + lease.as_millis() as u64) as i64),
+                ],
+            )
+            .await?;
+        row.as_ref().map(Self::state_from_row).transpose()
+    }
+
+    async fn renew_lease(&self, task: &dyn Task, lease: Duration) -> Result<bool, TaskStoreError> {
+        let now = now_millis();
+        let count = self
+            .client
+            .execute(
+                "UPDATE task_state SET lease_expiry_time = $5, last_update_time = $6
+                WHERE task_manager = $1 AND task_name = $2 AND task_id = $3 AND instance = $4 AND status = $7",
+                &[
+                    &self.manager,
+                    &task.name(),
+                    &task.id(),
+                    &self.instance,
+                    &((now + lease.as_millis() as u64) as i64),
+                    &(now as i64),
+                    &TaskStatus::Running.to_string(),
+                ],
+            )
+            .await?;
+        Ok(count == 1)
+    }
+
+    async fn heartbeat(&self) -> Result<(), TaskStoreError> {
+        self.client
+            .execute(
+                "INSERT INTO task_instance (task_manager, instance, heartbeat_time) VALUES ($1, $2, $3)
+                ON CONFLICT (task_manager, instance) DO UPDATE SET heartbeat_time = EXCLUDED.heartbeat_time",
+                &[&self.manager, &self.instance, &(now_millis() as i64)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn dead_instances(&self, timeout: Duration) -> Result<Vec<String>, TaskStoreError> {
+        let limit = now_millis().saturating_sub(timeout.as_millis() as u64);
+        self.client
+            .query(
+                "SELECT instance FROM task_instance
+                WHERE task_manager = $1 AND instance <> $2 AND heartbeat_time < $3",
+                &[&self.manager, &self.instance, &(limit as i64)],
+            )
+            .await?
+            .iter()
+            .map(|row| row.try_get("instance").map_err(serialization_error))
+            .collect()
+    }
+
+    async fn adopt_instance(&self, instance: &str) -> Result<Vec<TaskState>, TaskStoreError> {
+        // Single statement: the heartbeat is forgotten along with the move
+        self.client
+            .query(
+                "WITH forgotten AS (
+                    DELETE FROM task_instance WHERE task_manager = $1 AND instance = $2
+                )
+                UPDATE task_state SET instance = $3, status = $4, last_update_time = $5
+                WHERE task_manager = $1 AND instance = $2
+                RETURNING *",
+                &[
+                    &self.manager,
+                    &instance,
+                    &self.instance,
+                    &TaskStatus::Pending.to_string(),
+                    &(now_millis() as i64),
+                ],
+            )
+            .await?
+            .iter()
+            .map(Self::state_from_row)
+            .collect()
+    }
+
+    async fn acquire_leadership(&self, lease: Duration) -> Result<bool, TaskStoreError> {
+        // The row of another leader is only replaced once its lease expired
+        let now = now_millis();
+        let row = self
+            .client
+            .query_opt(
+                "INSERT INTO task_leader (task_manager, instance, lease_expiry_time) VALUES ($1, $2, $3)
+                ON CONFLICT (task_manager) DO UPDATE
+                SET instance = EXCLUDED.instance, lease_expiry_time = EXCLUDED.lease_expiry_time
+                WHERE task_leader.instance = EXCLUDED.instance OR task_leader.lease_expiry_time < $4
+                RETURNING instance",
+                &[
+                    &self.manager,
+                    &self.instance,
+                    &((now + lease.as_millis() as u64) as i64),
+                    &(now as i64),
+                ],
+            )
+            .await?;
+        Ok(row.is_some())
+    }
+
+    async fn resign_leadership(&self) -> Result<(), TaskStoreError> {
+        self.client
+            .execute(
+                "DELETE FROM task_leader WHERE task_manager = $1 AND instance = $2",
+                &[&self.manager, &self.instance],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn record_outcome(
+        &self,
+        task: &dyn Task,
+        outcome: &TaskOutcome,
+        ttl: Duration,
+    ) -> Result<(), TaskStoreError> {
+        // Expired outcomes of the manager are purged along the way
+        let now = now_millis();
+        self.client
+            .execute(
+                "WITH expired AS (
+                    DELETE FROM task_outcome
+                    WHERE task_manager = $1 AND expiry_time < $6 AND NOT (task_name = $2 AND task_id = $3)
+                )
+                INSERT INTO task_outcome (task_manager, task_name, task_id, outcome, expiry_time)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (task_manager, task_name, task_id) DO UPDATE
+                SET outcome = EXCLUDED.outcome, expiry_time = EXCLUDED.expiry_time",
+                &[
+                    &self.manager,
+                    &task.name(),
+                    &task.id(),
+                    &outcome.to_string(),
+                    &((now + ttl.as_millis() as u64) as i64),
+                    &(now as i64),
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get_outcome(&self, task: &dyn Task) -> Result<Option<TaskOutcome>, TaskStoreError> {
+        let row = self
+            .client
+            .query_opt(
+                "SELECT outcome FROM task_outcome
+                WHERE task_manager = $1 AND task_name = $2 AND task_id = $3 AND expiry_time >= $4",
+                &[&self.manager, &task.name(), &task.id(), &(now_millis() as i64)],
+            )
+            .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let outcome: String = row.try_get("outcome").map_err(serialization_error)?;
+        outcome.parse().map(Some).map_err(TaskStoreError::serialization)
+    }
+}
@@ -0,0 +1,329 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{sync::RwLock, time::sleep};
+use tokio_postgres::{Client, NoTls};
+
+use crate::{
+    context::TaskContext,
+    manager::{OrphanPolicy, RunResult, TaskManager},
+    registry::TaskRegistry,
+    store::{StateUpdate, TaskStatus},
+    task::Task,
+};
+
+use super::{TaskStoreError, postgres::PostgresTaskStore, TaskStore};
+
+struct TestTask {
+    pub id: String,
+}
+
+#[async_trait]
+impl Task for TestTask {
+    fn name(&self) -> String {
+        "test_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn run(&self, _ctx: &TaskContext) {
+        // Nothing
+    }
+}
+
+/// Connect to the local test database.
+/// Connection string can be overridden with the `QUARTERMASTER_POSTGRES_URL` environment variable.
+async fn connect() -> Arc<Client> {
+    let url = std::env::var("QUARTERMASTER_POSTGRES_URL")
+        .unwrap_or_else(|_| "host=localhost user=postgres".to_string());
+    let (client, connection) = tokio_postgres::connect(&url, NoTls).await.unwrap();
+    tokio::spawn(connection);
+    Arc::new(client)
+}
+
+/// Create an initialized and empty store.
+async fn create_store(instance: &str) -> PostgresTaskStore {
+    create_manager_store(instance, instance).await
+}
+
+async fn create_manager_store(manager: &str, instance: &str) -> PostgresTaskStore {
+    let store = PostgresTaskStore::new(manager, instance, connect().await);
+    store.init().await.unwrap();
+    store.clear().await.unwrap();
+    store
+}
+
+#[tokio::test]
+async fn create_state() {
+    let store = create_store("pg_create_state").await;
+    let state1 = store
+        .save_state(&TestTask {
+            id: "1".to_string(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(state1.task_id, "1");
+    assert_eq!(state1.instance, Some("pg_create_state".to_string()));
+    let state2 = store
+        .save_state(&TestTask {
+            id: "2".to_string(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(state2.task_id, "2");
+    assert_eq!(store.count_tasks().await.unwrap(), 2);
+}
+
+#[tokio::test]
+async fn create_state_duplicate() {
+    let store = create_store("pg_create_state_duplicate").await;
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    store.save_state(&task).await.unwrap();
+    assert!(matches!(
+        store.save_state(&task).await,
+        Err(TaskStoreError::Duplicate(_))
+    ));
+    assert_eq!(store.count_tasks().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn get_state_found() {
+    let store = create_store("pg_get_state_found").await;
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    store.save_state(&task).await.unwrap();
+    assert!(store.get_state(&task).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn get_state_not_found() {
+    let store = create_store("pg_get_state_not_found").await;
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    store.save_state(&task).await.unwrap();
+    assert!(store
+        .get_state(&TestTask {
+            id: "2".to_string(),
+        })
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn delete_state() {
+    let store = create_store("pg_delete_state").await;
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    store.save_state(&task).await.unwrap();
+    assert_eq!(store.count_tasks().await.unwrap(), 1);
+    store.delete_state(&task).await.unwrap();
+    assert_eq!(store.count_tasks().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn update_state() {
+    let store = create_store("pg_update_state").await;
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    store.save_state(&task).await.unwrap();
+    store
+        .update_status(&task, TaskStatus::Running)
+        .await
+        .unwrap();
+    let state = store.get_state(&task).await.unwrap().unwrap();
+    assert_eq!(state.status, TaskStatus::Running);
+}
+
+#[tokio::test]
+async fn get_all_states() {
+    let store = create_store("pg_get_all_states").await;
+    let other = create_store("pg_get_all_states_other").await;
+    store
+        .save_state(&TestTask {
+            id: "1".to_string(),
+        })
+        .await
+        .unwrap();
+    store
+        .save_state(&TestTask {
+            id: "2".to_string(),
+        })
+        .await
+        .unwrap();
+    other
+        .save_state(&TestTask {
+            id: "3".to_string(),
+        })
+        .await
+        .unwrap();
+    let states = store.get_all_states().await.unwrap();
+    assert_eq!(states.len(), 2);
+    assert!(states.iter().any(|s| s.task_id == "1"));
+    assert!(states.iter().any(|s| s.task_id == "2"));
+}
+
+#[tokio::test]
+async fn try_insert_state() {
+    let store = create_store("pg_try_insert_state").await;
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    assert!(store.try_insert_state(&task).await.unwrap().is_some());
+    assert!(store.try_insert_state(&task).await.unwrap().is_none());
+    assert_eq!(store.count_tasks().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn conformance() {
+    let factory = |manager: String, instance: String| async move {
+        create_manager_store(&manager, &format!("pg_{}", instance)).await
+    };
+    super::conformance::run_all(factory).await;
+    super::conformance::check_claim_pending(&factory).await;
+    super::conformance::check_lease_expiry(&factory).await;
+    super::conformance::check_cross_instance_dedup(&factory).await;
+    super::conformance::check_cross_instance_claim(&factory).await;
+    super::conformance::check_instance_adoption(&factory).await;
+    super::conformance::check_leadership(&factory).await;
+    super::conformance::check_outcomes(&factory).await;
+}
+
+/// Task recording its run, with its id as payload.
+struct PayloadTask {
+    pub id: String,
+    pub results: Arc<RwLock<Vec<String>>>,
+}
+
+#[async_trait]
+impl Task for PayloadTask {
+    fn name(&self) -> String {
+        "payload_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn run(&self, _ctx: &TaskContext) {
+        self.results.write().await.push(self.id.clone());
+    }
+
+    fn payload(&self) -> Option<String> {
+        Some(self.id.clone())
+    }
+}
+
+fn registry(results: &Arc<RwLock<Vec<String>>>) -> TaskRegistry {
+    let results = results.clone();
+    TaskRegistry::new().register("payload_task", move |payload| {
+        Ok(Box::new(PayloadTask {
+            id: payload.to_string(),
+            results: results.clone(),
+        }) as Box<dyn Task + Send + Sync>)
+    })
+}
+
+/// Leave a running task with a payload, and one without, on an instance which stops sending heartbeats.
+async fn dead_instance(manager: &str) -> PostgresTaskStore {
+    let dead = create_manager_store(manager, &format!("{}_dead", manager)).await;
+    let initial = StateUpdate {
+        payload: Some("1".to_string()),
+        ..Default::default()
+    };
+    let task = PayloadTask {
+        id: "1".to_string(),
+        results: Arc::new(RwLock::new(vec![])),
+    };
+    dead.try_insert_state_with(&task, initial).await.unwrap();
+    dead.update_status(&task, TaskStatus::Running).await.unwrap();
+    dead.save_state(&TestTask {
+        id: "2".to_string(),
+    })
+    .await
+    .unwrap();
+    dead.heartbeat().await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    dead
+}
+
+#[tokio::test]
+async fn reassign_orphans() {
+    let dead = dead_instance("pg_reassign_orphans").await;
+    let results = Arc::new(RwLock::new(vec![]));
+    let live = create_manager_store("pg_reassign_orphans", "pg_reassign_orphans_live").await;
+    let manager = TaskManager::new(live.clone(), 1)
+        .with_registry(registry(&results))
+        .with_heartbeat(Duration::from_millis(10), Duration::from_millis(30));
+    manager.start().await;
+    for _ in 0..100 {
+        if !results.read().await.is_empty() && live.count_tasks().await.unwrap() == 0 {
+            break;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    manager.stop().await;
+
+    // Task with a payload ran again, the other one was cleared
+    assert_eq!(*results.read().await, vec!["1".to_string()]);
+    assert_eq!(dead.count_tasks().await.unwrap(), 0);
+    assert_eq!(live.count_tasks().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn delete_orphans() {
+    let dead = dead_instance("pg_delete_orphans").await;
+    let results = Arc::new(RwLock::new(vec![]));
+    let live = create_manager_store("pg_delete_orphans", "pg_delete_orphans_live").await;
+    let manager = TaskManager::new(live.clone(), 1)
+        .with_registry(registry(&results))
+        .with_heartbeat(Duration::from_secs(10), Duration::from_millis(30))
+        .with_orphan_policy(OrphanPolicy::Delete);
+    assert_eq!(manager.reap_orphans().await, 2);
+    assert_eq!(dead.count_tasks().await.unwrap(), 0);
+    assert_eq!(live.count_tasks().await.unwrap(), 0);
+    assert_eq!(manager.reap_orphans().await, 0);
+}
+
+/// Task manager instance running the test tasks on the leader only.
+async fn leader_instance(instance: &str) -> TaskManager<PostgresTaskStore> {
+    let store = create_manager_store("pg_leader_failover", instance).await;
+    TaskManager::new(store, 1).with_leader_only(&["test_task"], Duration::from_millis(150))
+}
+
+#[tokio::test]
+async fn leader_failover() {
+    let a = leader_instance("pg_leader_failover_a").await;
+    let b = leader_instance("pg_leader_failover_b").await;
+    a.start().await;
+    b.start().await;
+    assert!(a.is_leader());
+    assert!(!b.is_leader());
+    let task = |id: &str| {
+        Box::new(TestTask {
+            id: id.to_string(),
+        })
+    };
+    assert!(matches!(b.run(task("1")).await, RunResult::NotLeader));
+    assert!(a.run(task("2")).await.is_accepted());
+
+    // Leadership taken over once the leader stopped
+    a.stop().await;
+    for _ in 0..100 {
+        if b.is_leader() {
+            break;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    assert!(b.is_leader());
+    assert!(b.run(task("3")).await.is_accepted());
+    b.stop().await;
+}
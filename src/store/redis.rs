@@ -0,0 +1,611 @@
+use async_trait::async_trait;
+use std::{collections::HashMap, time::Duration};
+
+use crate::{
+    task::{Task, TaskOutcome},
+    util::now_millis,
+};
+
+use super::{
+    state::{StateUpdate, TaskState, TaskStatus},
+    TaskStore, TaskStoreError,
+};
+
+use redis::{aio::ConnectionManager, AsyncCommands, Script};
+
+/// Key prefix of all the keys written by the store.
+/// Scripts access the state keys found in the indexes, not passed in KEYS:
+/// the store requires a standalone Redis server, not a Redis Cluster.
+const KEY_PREFIX: &str = "TaskState";
+
+/// Insert a task state hash, unless it already exists.
+/// Claimable states (with a payload) are added to the pending index, scored by creation time.
+/// KEYS[1]: state key, KEYS[2]: instance index key, KEYS[3]: pending index key,
+/// ARGV[1]: creation time if claimable (empty otherwise), ARGV[2..]: field and value pairs.
+const SAVE_STATE_SCRIPT: &str = r#"
+if redis.call('EXISTS', KEYS[1]) == 1 then
+    return 0
+end
+redis.call('HSET', KEYS[1], unpack(ARGV, 2))
+redis.call('SADD', KEYS[2], KEYS[1])
+if ARGV[1] ~= '' then
+    redis.call('ZADD', KEYS[3], ARGV[1], KEYS[1])
+end
+return 1
+"#;
+
+/// Claim, with one of the given names, a running state of the lease index whose lease expired,
+/// or else the oldest pending state of the pending index,
+/// moving it to the claiming instance index and leasing it.
+/// Index entries of deleted, finished or claimed states are dropped.
+/// KEYS[1]: pending index key, KEYS[2]: lease index key, ARGV[1]: instance index key prefix,
+/// ARGV[2]: claiming instance, ARGV[3]: claim time, ARGV[4]: lease expiry time, ARGV[5..]: task names.
+/// Return the claimed state key, or false.
+const CLAIM_SCRIPT: &str = r#"
+local names = {}
+for i = 5, #ARGV do
+    names[ARGV[i]] = true
+end
+local function claim(key, instance)
+    redis.call('SREM', ARGV[1] .. instance, key)
+    redis.call('SADD', ARGV[1] .. ARGV[2], key)
+    redis.call('HSET', key, 'status', 'Running', 'instance', ARGV[2], 'last_update_time', ARGV[3],
+        'lease_expiry_time', ARGV[4])
+    redis.call('ZADD', KEYS[2], ARGV[4], key)
+    return key
+end
+for _, key in ipairs(redis.call('ZRANGEBYSCORE', KEYS[2], '-inf', '(' .. ARGV[3])) do
+    local state = redis.call('HMGET', key, 'task_name', 'status', 'instance', 'payload', 'lease_expiry_time')
+    if not state[1] or state[2] ~= 'Running' or not state[4] then
+        redis.call('ZREM', KEYS[2], key)
+    elseif names[state[1]] and tonumber(state[5]) < tonumber(ARGV[3]) then
+        return claim(key, state[3])
+    end
+end
+for _, key in ipairs(redis.call('ZRANGE', KEYS[1], 0, -1)) do
+    local state = redis.call('HMGET', key, 'task_name', 'status', 'instance', 'payload')
+    if not state[1] or state[2] ~= 'Pending' or not state[4] then
+        redis.call('ZREM', KEYS[1], key)
+    elseif names[state[1]] then
+        redis.call('ZREM', KEYS[1], key)
+        return claim(key, state[3])
+    end
+end
+return false
+"#;
+
+/// Extend the lease of a running state owned by the given instance.
+/// KEYS[1]: state key, KEYS[2]: lease index key, ARGV[1]: instance, ARGV[2]: renewal time,
+/// ARGV[3]: lease expiry time.
+/// Return 1 if the lease was renewed, 0 otherwise.
+const RENEW_LEASE_SCRIPT: &str = r#"
+local state = redis.call('HMGET', KEYS[1], 'instance', 'status')
+if state[1] ~= ARGV[1] or state[2] ~= 'Running' then
+    return 0
+end
+redis.call('HSET', KEYS[1], 'last_update_time', ARGV[2], 'lease_expiry_time', ARGV[3])
+redis.call('ZADD', KEYS[2], ARGV[3], KEYS[1])
+return 1
+"#;
+
+/// Delete a task state hash and remove it from its instance index.
+/// KEYS[1]: state key, ARGV[1]: instance index key prefix.
+const DELETE_STATE_SCRIPT: &str = r#"
+local instance = redis.call('HGET', KEYS[1], 'instance')
+if instance then
+    redis.call('SREM', ARGV[1] .. instance, KEYS[1])
+end
+return redis.call('DEL', KEYS[1])
+"#;
+
+/// Update task state fields, only if the state exists.
+/// Claimable states set back to pending (e.g. recovered) are added back to the pending index.
+/// KEYS[1]: state key, KEYS[2]: pending index key, ARGV: field and value pairs.
+const UPDATE_STATE_SCRIPT: &str = r#"
+if redis.call('EXISTS', KEYS[1]) == 0 then
+    return 0
+end
+redis.call('HSET', KEYS[1], unpack(ARGV))
+local state = redis.call('HMGET', KEYS[1], 'status', 'payload', 'creation_time')
+if state[1] == 'Pending' and state[2] then
+    redis.call('ZADD', KEYS[2], state[3], KEYS[1])
+end
+return 0
+"#;
+
+/// Update task state fields, only if the state exists and is pending.
+/// Return 1 if updated.
+/// KEYS[1]: state key, ARGV: field and value pairs.
+const UPDATE_PENDING_STATE_SCRIPT: &str = r#"
+if redis.call('HGET', KEYS[1], 'status') ~= 'Pending' then
+    return 0
+end
+redis.call('HSET', KEYS[1], unpack(ARGV))
+return 1
+"#;
+
+/// Delete all the task states of an instance index, and the index itself.
+/// KEYS[1]: instance index key.
+const CLEAR_SCRIPT: &str = r#"
+for _, key in ipairs(redis.call('SMEMBERS', KEYS[1])) do
+    redis.call('DEL', key)
+end
+return redis.call('DEL', KEYS[1])
+"#;
+
+/// Move the states of the manager owned by a dead instance to the adopting instance,
+/// set back to pending, and forget the dead instance heartbeat.
+/// Claimable states (with a payload) are added back to the pending index.
+/// KEYS[1]: dead instance index key, KEYS[2]: adopting instance index key, KEYS[3]: heartbeat hash key,
+/// KEYS[4]: pending index key, ARGV[1]: manager, ARGV[2]: dead instance, ARGV[3]: adopting instance,
+/// ARGV[4]: adoption time.
+/// Return the moved state keys.
+const ADOPT_SCRIPT: &str = r#"
+redis.call('HDEL', KEYS[3], ARGV[2])
+local moved = {}
+for _, key in ipairs(redis.call('SMEMBERS', KEYS[1])) do
+    local state = redis.call('HMGET', key, 'task_manager', 'creation_time', 'payload')
+    if not state[1] then
+        redis.call('SREM', KEYS[1], key)
+    elseif state[1] == ARGV[1] then
+        redis.call('SREM', KEYS[1], key)
+        redis.call('SADD', KEYS[2], key)
+        redis.call('HSET', key, 'status', 'Pending', 'instance', ARGV[3], 'last_update_time', ARGV[4])
+        if state[3] then
+            redis.call('ZADD', KEYS[4], state[2], key)
+        end
+        table.insert(moved, key)
+    end
+end
+return moved
+"#;
+
+/// Acquire or renew the leadership of a manager, unless held by another instance.
+/// KEYS[1]: leader key, ARGV[1]: instance, ARGV[2]: lease (ms).
+/// Return 1 if the instance is the leader, 0 otherwise.
+const ACQUIRE_LEADERSHIP_SCRIPT: &str = r#"
+local leader = redis.call('GET', KEYS[1])
+if leader and leader ~= ARGV[1] then
+    return 0
+end
+redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+return 1
+"#;
+
+/// Release the leadership of a manager, if held by the given instance.
+/// KEYS[1]: leader key, ARGV[1]: instance.
+const RESIGN_LEADERSHIP_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+impl From<redis::RedisError> for TaskStoreError {
+    fn from(err: redis::RedisError) -> Self {
+        let message = err.to_string();
+        if err.is_timeout() {
+            Self::Timeout {
+                message,
+                source: Some(Box::new(err)),
+            }
+        } else if err.is_connection_refusal() || err.is_connection_dropped() || err.is_io_error() {
+            Self::Connection {
+                message,
+                source: Some(Box::new(err)),
+            }
+        } else if err.kind() == redis::ErrorKind::TypeError {
+            Self::Serialization {
+                message,
+                source: Some(Box::new(err)),
+            }
+        } else {
+            Self::Backend {
+                message,
+                source: Some(Box::new(err)),
+            }
+        }
+    }
+}
+
+/// Redis task store implementation, for a standalone Redis server (Redis Cluster is not supported).
+/// Each task state is a hash keyed by manager, task name and task id,
+/// and each instance of a manager keeps a set of the state keys it owns.
+#[derive(Clone)]
+pub struct RedisTaskStore {
+    manager: String,
+    instance: String,
+    connection: ConnectionManager,
+}
+
+impl RedisTaskStore {
+    pub fn new(manager_name: &str, instance_name: &str, connection: ConnectionManager) -> Self {
+        Self {
+            manager: manager_name.to_string(),
+            instance: instance_name.to_string(),
+            connection,
+        }
+    }
+
+    /// Key of a task state hash.
+    fn state_key(&self, task: &dyn Task) -> String {
+        format!(
+            "{}:{}:{}:{}",
+            KEY_PREFIX,
+            self.manager,
+            task.name(),
+            task.id()
+        )
+    }
+
+    /// Key prefix of the instance indexes of the manager.
+    fn instance_key_prefix(&self) -> String {
+        format!("{}:instance:{}:", KEY_PREFIX, self.manager)
+    }
+
+    /// Key of the pending index of the manager.
+    fn pending_key(&self) -> String {
+        format!("{}:pending:{}", KEY_PREFIX, self.manager)
+    }
+
+    /// Key of the leader of the manager, expiring with its lease.
+    fn leader_key(&self) -> String {
+        format!("{}:leader:{}", KEY_PREFIX, self.manager)
+    }
+
+    /// Key of the remembered outcome of a completed task, expiring with its time to live.
+    fn outcome_key(&self, task: &dyn Task) -> String {
+        format!(
+            "{}:outcome:{}:{}:{}",
+            KEY_PREFIX,
+            self.manager,
+            task.name(),
+            task.id()
+        )
+    }
+
+    /// Key of the lease index of the manager.
+    fn lease_key(&self) -> String {
+        format!("{}:leases:{}", KEY_PREFIX, self.manager)
+    }
+
+    /// Key of the current instance index.
+    fn instance_key(&self) -> String {
+        format!("{}{}", self.instance_key_prefix(), self.instance)
+    }
+
+    /// Key of the heartbeat hash of the manager, heartbeat times by instance.
+    fn heartbeat_key(&self) -> String {
+        format!("{}:heartbeat:{}", KEY_PREFIX, self.manager)
+    }
+
+    /// Fetch the task states of the given keys at once.
+    async fn states_of(&self, keys: &[String]) -> Result<Vec<TaskState>, TaskStoreError> {
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut con = self.connection.clone();
+        let mut pipe = redis::pipe();
+        for key in keys {
+            pipe.hgetall(key);
+        }
+        let hashes: Vec<HashMap<String, String>> = pipe.query_async(&mut con).await?;
+
+        let mut states = vec![];
+        for hash in hashes {
+            if let Some(state) = Self::state_from_hash(hash)? {
+                states.push(state);
+            }
+        }
+        Ok(states)
+    }
+
+    /// Build a task state from a task state hash.
+    /// Return None if the hash is empty (state does not exist).
+    fn state_from_hash(
+        mut hash: HashMap<String, String>,
+    ) -> Result<Option<TaskState>, TaskStoreError> {
+        if hash.is_empty() {
+            return Ok(None);
+        }
+        let mut field = |name: &str| {
+            hash.remove(name)
+                .ok_or_else(|| TaskStoreError::serialization(format!("missing task state field {}", name)))
+        };
+        let creation_time = field("creation_time")?
+            .parse()
+            .map_err(|_| TaskStoreError::serialization("invalid task state creation time"))?;
+        let mut state = TaskState {
+            id: None,
+            task_id: field("task_id")?,
+            task_name: field("task_name")?,
+            task_manager: field("task_manager")?,
+            instance: Some(field("instance")?),
+            status: field("status")?.parse().map_err(TaskStoreError::serialization)?,
+            creation_time,
+            started_time: None,
+            finished_time: None,
+            last_update_time: creation_time,
+            worker: None,
+            host: None,
+            progress: None,
+            progress_message: None,
+            parent_name: None,
+            parent_id: None,
+            root_name: None,
+            root_id: None,
+            batch_id: None,
+            payload: None,
+            lease_expiry_time: None,
+            attempt: 0,
+        };
+
+        // Optional fields
+        for (name, value) in hash {
+            state
+                .set_field(&name, &value)
+                .map_err(TaskStoreError::serialization)?;
+        }
+        Ok(Some(state))
+    }
+}
+
+#[async_trait]
+impl TaskStore for RedisTaskStore {
+    fn manager_name(&self) -> String {
+        self.manager.to_string()
+    }
+
+    async fn init(&self) -> Result<(), TaskStoreError> {
+        // Check connection
+        let mut con = self.connection.clone();
+        redis::cmd("PING").query_async::<()>(&mut con).await?;
+        Ok(())
+    }
+
+    async fn save_state(&self, task: &dyn Task) -> Result<TaskState, TaskStoreError> {
+        self.try_insert_state(task)
+            .await?
+            .ok_or_else(|| TaskStoreError::duplicate(task))
+    }
+
+    async fn try_insert_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        self.try_insert_state_with(task, StateUpdate::default()).await
+    }
+
+    async fn try_insert_state_with(
+        &self,
+        task: &dyn Task,
+        initial: StateUpdate,
+    ) -> Result<Option<TaskState>, TaskStoreError> {
+        let state = TaskState::new(
+            task,
+            &self.manager,
+            Some(self.instance.to_string()),
+            now_millis(),
+        )
+        .with_initial(initial);
+
+        // Store state, unless another one already exists for the same task
+        let mut con = self.connection.clone();
+        let script = Script::new(SAVE_STATE_SCRIPT);
+        let mut invocation = script.key(self.state_key(task));
+        let claimable = match state.payload {
+            Some(_) => state.creation_time.to_string(),
+            None => String::new(),
+        };
+        invocation
+            .key(self.instance_key())
+            .key(self.pending_key())
+            .arg(claimable)
+            .arg("task_id")
+            .arg(&state.task_id)
+            .arg("task_name")
+            .arg(&state.task_name)
+            .arg("task_manager")
+            .arg(&state.task_manager)
+            .arg("instance")
+            .arg(&self.instance)
+            .arg("status")
+            .arg(state.status.to_string())
+            .arg("creation_time")
+            .arg(state.creation_time);
+        for (name, value) in state.extra_fields() {
+            invocation.arg(name).arg(value);
+        }
+        let inserted: bool = invocation.invoke_async(&mut con).await?;
+
+        Ok(inserted.then_some(state))
+    }
+
+    async fn delete_state(&self, task: &dyn Task) -> Result<(), TaskStoreError> {
+        let mut con = self.connection.clone();
+        Script::new(DELETE_STATE_SCRIPT)
+            .key(self.state_key(task))
+            .arg(self.instance_key_prefix())
+            .invoke_async::<()>(&mut con)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        let mut con = self.connection.clone();
+        let hash: HashMap<String, String> = con.hgetall(self.state_key(task)).await?;
+        Self::state_from_hash(hash)
+    }
+
+    async fn count_tasks(&self) -> Result<usize, TaskStoreError> {
+        // Count for current instance
+        let mut con = self.connection.clone();
+        let count: usize = con.scard(self.instance_key()).await?;
+        Ok(count)
+    }
+
+    async fn update_status(
+        &self,
+        task: &dyn Task,
+        status: TaskStatus,
+    ) -> Result<(), TaskStoreError> {
+        self.update_state(task, StateUpdate::status(status, now_millis()))
+            .await
+    }
+
+    async fn update_state(&self, task: &dyn Task, update: StateUpdate) -> Result<(), TaskStoreError> {
+        let mut con = self.connection.clone();
+        let script = Script::new(UPDATE_STATE_SCRIPT);
+        let mut invocation = script.key(self.state_key(task));
+        invocation.key(self.pending_key());
+        for (name, value) in update.fields() {
+            invocation.arg(name).arg(value);
+        }
+        invocation.invoke_async::<()>(&mut con).await?;
+        Ok(())
+    }
+
+    async fn update_pending_state(
+        &self,
+        task: &dyn Task,
+        update: StateUpdate,
+    ) -> Result<bool, TaskStoreError> {
+        let mut con = self.connection.clone();
+        let script = Script::new(UPDATE_PENDING_STATE_SCRIPT);
+        let mut invocation = script.key(self.state_key(task));
+        for (name, value) in update.fields() {
+            invocation.arg(name).arg(value);
+        }
+        let updated: i32 = invocation.invoke_async(&mut con).await?;
+        Ok(updated == 1)
+    }
+
+    async fn clear(&self) -> Result<(), TaskStoreError> {
+        let mut con = self.connection.clone();
+        Script::new(CLEAR_SCRIPT)
+            .key(self.instance_key())
+            .invoke_async::<()>(&mut con)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_all_states(&self) -> Result<Vec<TaskState>, TaskStoreError> {
+        let mut con = self.connection.clone();
+        let keys: Vec<String> = con.smembers(self.instance_key()).await?;
+        self.states_of(&keys).await
+    }
+
+    async fn claim_pending(
+        &self,
+        names: &[String],
+        lease: Duration,
+    ) -> Result<Option<TaskState>, TaskStoreError> {
+        let mut con = self.connection.clone();
+        let now = now_millis();
+        let script = Script::new(CLAIM_SCRIPT);
+        let mut invocation = script.key(self.pending_key());
+        invocation
+            .key(self.lease_key())
+            .arg(self.instance_key_prefix())
+            .arg(&self.instance)
+            .arg(now)
+            .arg(now + lease.as_millis() as u64);
+        for name in names {
+            invocation.arg(name);
+        }
+        let key: Option<String> = invocation.invoke_async(&mut con).await?;
+        match key {
+            Some(key) => {
+                let hash: HashMap<String, String> = con.hgetall(key).await?;
+                Self::state_from_hash(hash)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn renew_lease(&self, task: &dyn Task, lease: Duration) -> Result<bool, TaskStoreError> {
+        let mut con = self.connection.clone();
+        let now = now_millis();
+        let renewed: i64 = Script::new(RENEW_LEASE_SCRIPT)
+            .key(self.state_key(task))
+            .key(self.lease_key())
+            .arg(&self.instance)
+            .arg(now)
+            .arg(now + lease.as_millis() as u64)
+            .invoke_async(&mut con)
+            .await?;
+        Ok(renewed == 1)
+    }
+
+    async fn heartbeat(&self) -> Result<(), TaskStoreError> {
+        let mut con = self.connection.clone();
+        con.hset::<_, _, _, ()>(self.heartbeat_key(), &self.instance, now_millis())
+            .await?;
+        Ok(())
+    }
+
+    async fn dead_instances(&self, timeout: Duration) -> Result<Vec<String>, TaskStoreError> {
+        let mut con = self.connection.clone();
+        let heartbeats: HashMap<String, u64> = con.hgetall(self.heartbeat_key()).await?;
+        let limit = now_millis().saturating_sub(timeout.as_millis() as u64);
+        Ok(heartbeats
+            .into_iter()
+            .filter(|(instance, time)| *instance != self.instance && *time < limit)
+            .map(|(instance, _)| instance)
+            .collect())
+    }
+
+    async fn adopt_instance(&self, instance: &str) -> Result<Vec<TaskState>, TaskStoreError> {
+        let mut con = self.connection.clone();
+        let keys: Vec<String> = Script::new(ADOPT_SCRIPT)
+            .key(format!("{}{}", self.instance_key_prefix(), instance))
+            .key(self.instance_key())
+            .key(self.heartbeat_key())
+            .key(self.pending_key())
+            .arg(&self.manager)
+            .arg(instance)
+            .arg(&self.instance)
+            .arg(now_millis())
+            .invoke_async(&mut con)
+            .await?;
+        self.states_of(&keys).await
+    }
+
+    async fn acquire_leadership(&self, lease: Duration) -> Result<bool, TaskStoreError> {
+        let mut con = self.connection.clone();
+        let leader: i64 = Script::new(ACQUIRE_LEADERSHIP_SCRIPT)
+            .key(self.leader_key())
+            .arg(&self.instance)
+            .arg(lease.as_millis() as u64)
+            .invoke_async(&mut con)
+            .await?;
+        Ok(leader == 1)
+    }
+
+    async fn resign_leadership(&self) -> Result<(), TaskStoreError> {
+        let mut con = self.connection.clone();
+        Script::new(RESIGN_LEADERSHIP_SCRIPT)
+            .key(self.leader_key())
+            .arg(&self.instance)
+            .invoke_async::<()>(&mut con)
+            .await?;
+        Ok(())
+    }
+
+    async fn record_outcome(
+        &self,
+        task: &dyn Task,
+        outcome: &TaskOutcome,
+        ttl: Duration,
+    ) -> Result<(), TaskStoreError> {
+        let mut con = self.connection.clone();
+        con.pset_ex::<_, _, ()>(self.outcome_key(task), outcome.to_string(), ttl.as_millis() as u64)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_outcome(&self, task: &dyn Task) -> Result<Option<TaskOutcome>, TaskStoreError> {
+        let mut con = self.connection.clone();
+        let outcome: Option<String> = con.get(self.outcome_key(task)).await?;
+        outcome
+            .map(|outcome| outcome.parse().map_err(TaskStoreError::serialization))
+            .transpose()
+    }
+}
@@ -0,0 +1,283 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{sync::RwLock, time::sleep};
+
+use crate::{
+    context::TaskContext,
+    manager::TaskManager,
+    registry::TaskRegistry,
+    store::{StateUpdate, TaskStatus},
+    task::Task,
+};
+
+use super::{TaskStoreError, redis::RedisTaskStore, TaskStore};
+
+struct TestTask {
+    pub id: String,
+}
+
+#[async_trait]
+impl Task for TestTask {
+    fn name(&self) -> String {
+        "test_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn run(&self, _ctx: &TaskContext) {
+        // Nothing
+    }
+}
+
+/// Create an initialized and empty store, connected to the local test server.
+/// Connection URL can be overridden with the `QUARTERMASTER_REDIS_URL` environment variable.
+async fn create_store(instance: &str) -> RedisTaskStore {
+    create_manager_store(instance, instance).await
+}
+
+async fn create_manager_store(manager: &str, instance: &str) -> RedisTaskStore {
+    let url = std::env::var("QUARTERMASTER_REDIS_URL")
+        .unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
+    let client = redis::Client::open(url).unwrap();
+    let connection = client.get_connection_manager().await.unwrap();
+    let store = RedisTaskStore::new(manager, instance, connection);
+    store.init().await.unwrap();
+    store.clear().await.unwrap();
+    store
+}
+
+#[tokio::test]
+async fn create_state() {
+    let store = create_store("redis_create_state").await;
+    let state1 = store
+        .save_state(&TestTask {
+            id: "1".to_string(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(state1.task_id, "1");
+    assert_eq!(state1.instance, Some("redis_create_state".to_string()));
+    let state2 = store
+        .save_state(&TestTask {
+            id: "2".to_string(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(state2.task_id, "2");
+    assert_eq!(store.count_tasks().await.unwrap(), 2);
+}
+
+#[tokio::test]
+async fn create_state_duplicate() {
+    let store = create_store("redis_create_state_duplicate").await;
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    store.save_state(&task).await.unwrap();
+    assert!(matches!(
+        store.save_state(&task).await,
+        Err(TaskStoreError::Duplicate(_))
+    ));
+    assert_eq!(store.count_tasks().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn instance_scoped_by_manager() {
+    let store1 = create_manager_store("redis_instance_scoped_1", "instance").await;
+    let store2 = create_manager_store("redis_instance_scoped_2", "instance").await;
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    store1.save_state(&task).await.unwrap();
+    store2.save_state(&task).await.unwrap();
+    assert_eq!(store1.count_tasks().await.unwrap(), 1);
+
+    // Clearing an instance of a manager keeps the states of the other manager
+    store1.clear().await.unwrap();
+    assert_eq!(store1.count_tasks().await.unwrap(), 0);
+    assert_eq!(store2.count_tasks().await.unwrap(), 1);
+    assert!(store2.get_state(&task).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn get_state_found() {
+    let store = create_store("redis_get_state_found").await;
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    store.save_state(&task).await.unwrap();
+    assert!(store.get_state(&task).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn get_state_not_found() {
+    let store = create_store("redis_get_state_not_found").await;
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    store.save_state(&task).await.unwrap();
+    assert!(store
+        .get_state(&TestTask {
+            id: "2".to_string(),
+        })
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn delete_state() {
+    let store = create_store("redis_delete_state").await;
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    store.save_state(&task).await.unwrap();
+    assert_eq!(store.count_tasks().await.unwrap(), 1);
+    store.delete_state(&task).await.unwrap();
+    assert_eq!(store.count_tasks().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn update_state() {
+    let store = create_store("redis_update_state").await;
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    store.save_state(&task).await.unwrap();
+    store
+        .update_status(&task, TaskStatus::Running)
+        .await
+        .unwrap();
+    let state = store.get_state(&task).await.unwrap().unwrap();
+    assert_eq!(state.status, TaskStatus::Running);
+}
+
+#[tokio::test]
+async fn get_all_states() {
+    let store = create_store("redis_get_all_states").await;
+    let other = create_store("redis_get_all_states_other").await;
+    store
+        .save_state(&TestTask {
+            id: "1".to_string(),
+        })
+        .await
+        .unwrap();
+    store
+        .save_state(&TestTask {
+            id: "2".to_string(),
+        })
+        .await
+        .unwrap();
+    other
+        .save_state(&TestTask {
+            id: "3".to_string(),
+        })
+        .await
+        .unwrap();
+    let states = store.get_all_states().await.unwrap();
+    assert_eq!(states.len(), 2);
+    assert!(states.iter().any(|s| s.task_id == "1"));
+    assert!(states.iter().any(|s| s.task_id == "2"));
+}
+
+#[tokio::test]
+async fn try_insert_state() {
+    let store = create_store("redis_try_insert_state").await;
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    assert!(store.try_insert_state(&task).await.unwrap().is_some());
+    assert!(store.try_insert_state(&task).await.unwrap().is_none());
+    assert_eq!(store.count_tasks().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn conformance() {
+    let factory = |manager: String, instance: String| async move {
+        create_manager_store(&manager, &format!("redis_{}", instance)).await
+    };
+    super::conformance::run_all(factory).await;
+    super::conformance::check_claim_pending(&factory).await;
+    super::conformance::check_lease_expiry(&factory).await;
+    super::conformance::check_cross_instance_dedup(&factory).await;
+    super::conformance::check_cross_instance_claim(&factory).await;
+    super::conformance::check_instance_adoption(&factory).await;
+    super::conformance::check_leadership(&factory).await;
+    super::conformance::check_outcomes(&factory).await;
+}
+
+/// Task with a payload, recording its id when run.
+struct PayloadTask {
+    pub id: String,
+    pub results: Arc<RwLock<Vec<String>>>,
+}
+
+#[async_trait]
+impl Task for PayloadTask {
+    fn name(&self) -> String {
+        "payload_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn run(&self, _ctx: &TaskContext) {
+        self.results.write().await.push(self.id.clone());
+    }
+
+    fn payload(&self) -> Option<String> {
+        Some(self.id.clone())
+    }
+}
+
+#[tokio::test]
+async fn recover_running_shared_task() {
+    let store = create_manager_store("redis_recover_shared", "redis_recover_shared").await;
+    let results = Arc::new(RwLock::new(vec![]));
+    let task = PayloadTask {
+        id: "1".to_string(),
+        results: results.clone(),
+    };
+    let initial = StateUpdate {
+        payload: task.payload(),
+        ..Default::default()
+    };
+    store.try_insert_state_with(&task, initial).await.unwrap();
+
+    // Claimed and running when the instance stopped
+    let names = vec!["payload_task".to_string()];
+    let claimed = store
+        .claim_pending(&names, Duration::from_secs(30))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(claimed.status, TaskStatus::Running);
+
+    // Restarted: the task is set back to pending, and claimed again
+    let registry_results = results.clone();
+    let registry = TaskRegistry::new().register("payload_task", move |payload| {
+        Ok(Box::new(PayloadTask {
+            id: payload.to_string(),
+            results: registry_results.clone(),
+        }) as Box<dyn Task + Send + Sync>)
+    });
+    let manager = TaskManager::new(store.clone(), 1).with_shared_queue(registry, Duration::from_millis(5));
+    tokio::join!(manager.start_blocking(), async {
+        for _ in 0..100 {
+            if !results.read().await.is_empty() {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        manager.stop().await;
+    });
+    assert_eq!(*results.read().await, vec!["1"]);
+    assert_eq!(store.count_tasks().await.unwrap(), 0);
+
+    // Same task can be submitted again
+    assert!(store.try_insert_state(&task).await.unwrap().is_some());
+}
@@ -0,0 +1,421 @@
+use std::{collections::VecDeque, future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{
+    sync::Mutex,
+    time::{sleep, Instant},
+};
+
+use crate::task::{Task, TaskOutcome, TaskRef};
+
+use super::{StateUpdate, TaskState, TaskStatus, TaskStore, TaskStoreError};
+
+type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, TaskStoreError>> + Send + 'a>>;
+
+/// State update that could not be written to the inner store yet.
+enum PendingUpdate {
+    Status(TaskRef, TaskStatus),
+    State(TaskRef, Box<StateUpdate>),
+    Delete(TaskRef),
+}
+
+impl PendingUpdate {
+    fn task(&self) -> &TaskRef {
+        match self {
+            PendingUpdate::Status(task, _)
+            | PendingUpdate::State(task, _)
+            | PendingUpdate::Delete(task) => task,
+        }
+    }
+}
+
+/// Circuit breaker state.
+struct Circuit {
+    /// Number of consecutive failed calls.
+    failures: usize,
+    /// When set, calls are rejected until this instant.
+    open_until: Option<Instant>,
+}
+
+/// Resilient task store.
+/// Decorates a task store to retry transient errors with an exponential backoff,
+/// and stops calling it for a while (circuit breaker) after repeated failures.
+/// Writes which are not idempotent (state insertions, claims, adoptions) are not retried:
+/// a timed out call may have been applied.
+/// State updates and deletions that cannot be written are buffered,
+/// and replayed in order once the inner store recovers.
+#[derive(Clone)]
+pub struct ResilientTaskStore<S>
+where
+    S: TaskStore,
+{
+    inner: S,
+    max_retries: usize,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    failure_threshold: usize,
+    open_duration: Duration,
+    buffer_capacity: usize,
+    circuit: Arc<Mutex<Circuit>>,
+    buffer: Arc<Mutex<VecDeque<PendingUpdate>>>,
+}
+
+impl<S: TaskStore> ResilientTaskStore<S> {
+    /// Create a new resilient task store, decorating the given store.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+            buffer_capacity: 10_000,
+            circuit: Arc::new(Mutex::new(Circuit {
+                failures: 0,
+                open_until: None,
+            })),
+            buffer: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Set how many times a call failing with a retryable error is retried.
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Set the delay before the first retry, and the maximum delay between retries.
+    /// The delay doubles after each retry.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Set the number of consecutive failed calls opening the circuit,
+    /// and how long the circuit stays open.
+    pub fn with_circuit_breaker(mut self, failure_threshold: usize, open_duration: Duration) -> Self {
+        self.failure_threshold = failure_threshold;
+        self.open_duration = open_duration;
+        self
+    }
+
+    /// Set the maximum number of buffered state updates.
+    pub fn with_buffer_capacity(mut self, capacity: usize) -> Self {
+        self.buffer_capacity = capacity;
+        self
+    }
+
+    /// Return the inner store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Return true if the circuit is open (inner store is not called).
+    pub async fn is_open(&self) -> bool {
+        matches!(self.circuit.lock().await.open_until, Some(until) if until > Instant::now())
+    }
+
+    /// Return the number of state updates waiting to be replayed.
+    pub async fn buffered_updates(&self) -> usize {
+        self.buffer.lock().await.len()
+    }
+
+    /// Call the inner store, retrying on retryable errors.
+    /// Fail fast while the circuit is open.
+    async fn attempt<'a, T, F>(&'a self, op: F) -> Result<T, TaskStoreError>
+    where
+        F: Fn() -> StoreFuture<'a, T> + Send + Sync,
+        T: Send,
+    {
+        self.attempt_with(op, self.max_retries).await
+    }
+
+    /// Call the inner store, retrying on retryable errors up to the given number of times.
+    /// Fail fast while the circuit is open.
+    async fn attempt_with<'a, T, F>(&'a self, op: F, max_retries: usize) -> Result<T, TaskStoreError>
+    where
+        F: Fn() -> StoreFuture<'a, T> + Send + Sync,
+        T: Send,
+    {
+        if self.is_open().await {
+            return Err(TaskStoreError::Connection {
+                message: "circuit breaker is open".to_string(),
+                source: None,
+            });
+        }
+
+        let mut backoff = self.initial_backoff;
+        let mut retries = 0;
+        loop {
+            match op().await {
+                Ok(result) => {
+                    let mut circuit = self.circuit.lock().await;
+                    circuit.failures = 0;
+                    circuit.open_until = None;
+                    return Ok(result);
+                }
+                Err(err) if err.is_retryable() && retries < max_retries => {
+                    log::debug!("retrying store call in {:?}: {}", backoff, err);
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.max_backoff);
+                    retries += 1;
+                }
+                Err(err) => {
+                    if err.is_retryable() {
+                        let mut circuit = self.circuit.lock().await;
+                        circuit.failures += 1;
+                        if circuit.failures >= self.failure_threshold {
+                            log::warn!(
+                                "opening store circuit breaker for {:?} after {} failures: {}",
+                                self.open_duration,
+                                circuit.failures,
+                                err
+                            );
+                            circuit.open_until = Some(Instant::now() + self.open_duration);
+                        }
+                    }
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Replay buffered updates, then call the inner store.
+    async fn call<'a, T, F>(&'a self, op: F) -> Result<T, TaskStoreError>
+    where
+        F: Fn() -> StoreFuture<'a, T> + Send + Sync,
+        T: Send,
+    {
+        {
+            let mut buffer = self.buffer.lock().await;
+            self.flush(&mut buffer).await;
+        }
+        self.attempt(op).await
+    }
+
+    /// Replay buffered updates, then call the inner store once, without retrying.
+    async fn call_once<'a, T, F>(&'a self, op: F) -> Result<T, TaskStoreError>
+    where
+        F: Fn() -> StoreFuture<'a, T> + Send + Sync,
+        T: Send,
+    {
+        {
+            let mut buffer = self.buffer.lock().await;
+            self.flush(&mut buffer).await;
+        }
+        self.attempt_with(op, 0).await
+    }
+
+    /// Write an update to the inner store, once: it is called with the buffer locked,
+    /// and buffered updates are replayed on later calls.
+    async fn apply(&self, update: &PendingUpdate) -> Result<(), TaskStoreError> {
+        match update {
+            PendingUpdate::Status(task, status) => {
+                self.attempt_with(|| self.inner.update_status(task, status.clone()), 0)
+                    .await
+            }
+            PendingUpdate::State(task, update) => {
+                self.attempt_with(|| self.inner.update_state(task, (**update).clone()), 0)
+                    .await
+            }
+            PendingUpdate::Delete(task) => {
+                self.attempt_with(|| self.inner.delete_state(task), 0).await
+            }
+        }
+    }
+
+    /// Replay buffered updates in order, until the inner store fails again.
+    async fn flush(&self, buffer: &mut VecDeque<PendingUpdate>) {
+        while let Some(update) = buffer.pop_front() {
+            match self.apply(&update).await {
+                Ok(()) => {}
+                Err(err) if err.is_retryable() => {
+                    buffer.push_front(update);
+                    return;
+                }
+                Err(err) => {
+                    log::error!(
+                        "dropping buffered update of task `{}` with id `{}`: {}",
+                        update.task().name,
+                        update.task().id,
+                        err
+                    );
+                }
+            }
+        }
+    }
+
+    /// Write an update, buffering it if the inner store is unavailable.
+    /// The update is not retried here, so that other writers are not blocked while backing off.
+    async fn write(&self, update: PendingUpdate) -> Result<(), TaskStoreError> {
+        let mut buffer = self.buffer.lock().await;
+
+        // Keep updates ordered: replay older ones first
+        self.flush(&mut buffer).await;
+        if !buffer.is_empty() {
+            return self.buffer_update(&mut buffer, update, None);
+        }
+
+        match self.apply(&update).await {
+            Err(err) if err.is_retryable() => self.buffer_update(&mut buffer, update, Some(err)),
+            result => result,
+        }
+    }
+
+    fn buffer_update(
+        &self,
+        buffer: &mut VecDeque<PendingUpdate>,
+        update: PendingUpdate,
+        err: Option<TaskStoreError>,
+    ) -> Result<(), TaskStoreError> {
+        if buffer.len() >= self.buffer_capacity {
+            return Err(err.unwrap_or_else(|| TaskStoreError::Connection {
+                message: "store update buffer is full".to_string(),
+                source: None,
+            }));
+        }
+        log::warn!(
+            "buffering update of task `{}` with id `{}` until store recovers",
+            update.task().name,
+            update.task().id
+        );
+        buffer.push_back(update);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<S: TaskStore> TaskStore for ResilientTaskStore<S> {
+    fn manager_name(&self) -> String {
+        self.inner.manager_name()
+    }
+
+    async fn init(&self) -> Result<(), TaskStoreError> {
+        self.call(|| self.inner.init()).await
+    }
+
+    async fn save_state(&self, task: &dyn Task) -> Result<TaskState, TaskStoreError> {
+        self.call_once(|| self.inner.save_state(task)).await
+    }
+
+    async fn try_insert_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        self.call_once(|| self.inner.try_insert_state(task)).await
+    }
+
+    async fn try_insert_state_with(
+        &self,
+        task: &dyn Task,
+        initial: StateUpdate,
+    ) -> Result<Option<TaskState>, TaskStoreError> {
+        self.call_once(|| self.inner.try_insert_state_with(task, initial.clone()))
+            .await
+    }
+
+    async fn delete_state(&self, task: &dyn Task) -> Result<(), TaskStoreError> {
+        self.write(PendingUpdate::Delete(TaskRef::of(task))).await
+    }
+
+    async fn get_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        self.call(|| self.inner.get_state(task)).await
+    }
+
+    async fn count_tasks(&self) -> Result<usize, TaskStoreError> {
+        self.call(|| self.inner.count_tasks()).await
+    }
+
+    async fn update_status(
+        &self,
+        task: &dyn Task,
+        status: TaskStatus,
+    ) -> Result<(), TaskStoreError> {
+        self.write(PendingUpdate::Status(
+            TaskRef::of(task),
+            status,
+        ))
+        .await
+    }
+
+    async fn update_state(&self, task: &dyn Task, update: StateUpdate) -> Result<(), TaskStoreError> {
+        self.write(PendingUpdate::State(
+            TaskRef::of(task),
+            Box::new(update),
+        ))
+        .await
+    }
+
+    /// Not buffered: the update only applies to a task still pending when written.
+    async fn update_pending_state(
+        &self,
+        task: &dyn Task,
+        update: StateUpdate,
+    ) -> Result<bool, TaskStoreError> {
+        self.call(|| self.inner.update_pending_state(task, update.clone()))
+            .await
+    }
+
+    async fn clear(&self) -> Result<(), TaskStoreError> {
+        // Buffered updates are obsolete once the states are cleared
+        self.attempt(|| self.inner.clear()).await?;
+        self.buffer.lock().await.clear();
+        Ok(())
+    }
+
+    async fn get_all_states(&self) -> Result<Vec<TaskState>, TaskStoreError> {
+        self.call(|| self.inner.get_all_states()).await
+    }
+
+    async fn claim_pending(
+        &self,
+        names: &[String],
+        lease: Duration,
+    ) -> Result<Option<TaskState>, TaskStoreError> {
+        self.call_once(|| self.inner.claim_pending(names, lease)).await
+    }
+
+    async fn renew_lease(&self, task: &dyn Task, lease: Duration) -> Result<bool, TaskStoreError> {
+        self.call(|| self.inner.renew_lease(task, lease)).await
+    }
+
+    async fn heartbeat(&self) -> Result<(), TaskStoreError> {
+        self.call(|| self.inner.heartbeat()).await
+    }
+
+    async fn dead_instances(&self, timeout: Duration) -> Result<Vec<String>, TaskStoreError> {
+        self.call(|| self.inner.dead_instances(timeout)).await
+    }
+
+    async fn adopt_instance(&self, instance: &str) -> Result<Vec<TaskState>, TaskStoreError> {
+        self.call_once(|| self.inner.adopt_instance(instance)).await
+    }
+
+    async fn acquire_leadership(&self, lease: Duration) -> Result<bool, TaskStoreError> {
+        self.call(|| self.inner.acquire_leadership(lease)).await
+    }
+
+    async fn resign_leadership(&self) -> Result<(), TaskStoreError> {
+        self.call(|| self.inner.resign_leadership()).await
+    }
+
+    async fn try_lock(&self, name: &str, owner: &str, ttl: Duration) -> Result<bool, TaskStoreError> {
+        self.call(|| self.inner.try_lock(name, owner, ttl)).await
+    }
+
+    async fn unlock(&self, name: &str, owner: &str) -> Result<(), TaskStoreError> {
+        self.call(|| self.inner.unlock(name, owner)).await
+    }
+
+    async fn record_outcome(
+        &self,
+        task: &dyn Task,
+        outcome: &TaskOutcome,
+        ttl: Duration,
+    ) -> Result<(), TaskStoreError> {
+        self.call(|| self.inner.record_outcome(task, outcome, ttl)).await
+    }
+
+    async fn get_outcome(&self, task: &dyn Task) -> Result<Option<TaskOutcome>, TaskStoreError> {
+        self.call(|| self.inner.get_outcome(task)).await
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+
+use crate::{context::TaskContext, store::TaskStatus, task::Task};
+
+use super::{
+    memory::InMemoryTaskStore, resilient::ResilientTaskStore, TaskState, TaskStore,
+    TaskStoreError,
+};
+
+struct TestTask {
+    pub id: String,
+}
+
+#[async_trait]
+impl Task for TestTask {
+    fn name(&self) -> String {
+        "test_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn run(&self, _ctx: &TaskContext) {
+        // Nothing
+    }
+}
+
+/// In memory store that can be taken down.
+#[derive(Clone)]
+struct FlakyStore {
+    inner: InMemoryTaskStore,
+    down: Arc<AtomicBool>,
+    calls: Arc<AtomicUsize>,
+}
+
+impl FlakyStore {
+    fn new() -> Self {
+        Self {
+            inner: InMemoryTaskStore::new("test_manager"),
+            down: Arc::new(AtomicBool::new(false)),
+            calls: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn set_down(&self, down: bool) {
+        self.down.store(down, Ordering::SeqCst);
+    }
+
+    fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    fn check(&self) -> Result<(), TaskStoreError> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        if self.down.load(Ordering::SeqCst) {
+            Err(TaskStoreError::Connection {
+                message: "store is down".to_string(),
+                source: None,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[async_trait]
+impl TaskStore for FlakyStore {
+    fn manager_name(&self) -> String {
+        self.inner.manager_name()
+    }
+
+    async fn init(&self) -> Result<(), TaskStoreError> {
+        self.check()?;
+        self.inner.init().await
+    }
+
+    async fn save_state(&self, task: &dyn Task) -> Result<TaskState, TaskStoreError> {
+        self.check()?;
+        self.inner.save_state(task).await
+    }
+
+    async fn try_insert_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        self.check()?;
+        self.inner.try_insert_state(task).await
+    }
+
+    async fn delete_state(&self, task: &dyn Task) -> Result<(), TaskStoreError> {
+        self.check()?;
+        self.inner.delete_state(task).await
+    }
+
+    async fn get_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        self.check()?;
+        self.inner.get_state(task).await
+    }
+
+    async fn count_tasks(&self) -> Result<usize, TaskStoreError> {
+        self.check()?;
+        self.inner.count_tasks().await
+    }
+
+    async fn update_status(
+        &self,
+        task: &dyn Task,
+        status: TaskStatus,
+    ) -> Result<(), TaskStoreError> {
+        self.check()?;
+        self.inner.update_status(task, status).await
+    }
+
+    async fn clear(&self) -> Result<(), TaskStoreError> {
+        self.check()?;
+        self.inner.clear().await
+    }
+
+    async fn get_all_states(&self) -> Result<Vec<TaskState>, TaskStoreError> {
+        self.check()?;
+        self.inner.get_all_states().await
+    }
+}
+
+fn create_store(flaky: &FlakyStore) -> ResilientTaskStore<FlakyStore> {
+    ResilientTaskStore::new(flaky.clone())
+        .with_max_retries(2)
+        .with_backoff(Duration::from_millis(1), Duration::from_millis(2))
+        .with_circuit_breaker(2, Duration::from_millis(50))
+}
+
+#[tokio::test]
+async fn retry() {
+    let flaky = FlakyStore::new();
+    let store = create_store(&flaky);
+    flaky.set_down(true);
+    assert!(store.count_tasks().await.unwrap_err().is_retryable());
+    // First call + 2 retries
+    assert_eq!(flaky.calls(), 3);
+}
+
+#[tokio::test]
+async fn insert_not_retried() {
+    let flaky = FlakyStore::new();
+    let store = create_store(&flaky);
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    flaky.set_down(true);
+    assert!(store.try_insert_state(&task).await.unwrap_err().is_retryable());
+    assert!(store.save_state(&task).await.unwrap_err().is_retryable());
+    assert_eq!(flaky.calls(), 2);
+}
+
+#[tokio::test]
+async fn not_retryable() {
+    let flaky = FlakyStore::new();
+    let store = create_store(&flaky);
+    let task = TestTask {
+        id: "1".to_string(),
+    };
+    assert!(matches!(
+        store.update_status(&task, TaskStatus::Running).await,
+        Err(TaskStoreError::NotFound(_))
+    ));
+    assert_eq!(flaky.calls(), 1);
+    assert_eq!(store.buffered_updates().await, 0);
+}
+
+#[tokio::test]
+async fn circuit_breaker() {
+    let flaky = FlakyStore::new();
+    let store = create_store(&flaky);
+    flaky.set_down(true);
+    store.count_tasks().await.unwrap_err();
+    store.count_tasks().await.unwrap_err();
+    assert!(store.is_open().await);
+
+    // Open circuit: inner store is not called
+    let calls = flaky.calls();
+    store.count_tasks().await.unwrap_err();
+    assert_eq!(flaky.calls(), calls);
+
+    // Circuit closes once the store answers again
+    flaky.set_down(false);
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert_eq!(store.count_tasks().await.unwrap(), 0);
+    assert!(!store.is_open().await);
+}
+
+#[tokio::test]
+async fn buffer_updates() {
+    let flaky = FlakyStore::new();
+    let store = create_store(&flaky);
+    let task1 = TestTask {
+        id: "1".to_string(),
+    };
+    let task2 = TestTask {
+        id: "2".to_string(),
+    };
+    store.try_insert_state(&task1).await.unwrap();
+    store.try_insert_state(&task2).await.unwrap();
+
+    // Updates are accepted while the store is down
+    flaky.set_down(true);
+    store
+        .update_status(&task1, TaskStatus::Running)
+        .await
+        .unwrap();
+    store
+        .update_status(&task2, TaskStatus::Running)
+        .await
+        .unwrap();
+    store.delete_state(&task2).await.unwrap();
+    assert_eq!(store.buffered_updates().await, 3);
+
+    // And replayed in order once it recovers
+    flaky.set_down(false);
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    let states = store.get_all_states().await.unwrap();
+    assert_eq!(store.buffered_updates().await, 0);
+    assert_eq!(states.len(), 1);
+    let state = store.get_state(&task1).await.unwrap().unwrap();
+    assert_eq!(state.status, TaskStatus::Running);
+}
+
+#[tokio::test]
+async fn conformance() {
+    let factory = |manager: String, _| async move { ResilientTaskStore::new(InMemoryTaskStore::new(&manager)) };
+    super::conformance::run_all(factory).await;
+    super::conformance::check_claim_pending(&factory).await;
+    super::conformance::check_lease_expiry(&factory).await;
+}
@@ -1,8 +1,10 @@
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
+
+use crate::task::Task;
 
 #[cfg(feature = "mongodb")]
 use mongodb::bson::oid::ObjectId;
-#[cfg(feature = "mongodb")]
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 /// Represent a task state status.
@@ -22,6 +24,18 @@ impl Display for TaskStatus {
     }
 }
 
+impl FromStr for TaskStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Pending" => Ok(TaskStatus::Pending),
+            "Running" => Ok(TaskStatus::Running),
+            _ => Err(format!("unknown task status {}", s)),
+        }
+    }
+}
+
 /// Represent a task state.
 #[cfg_attr(
     feature = "serde",
@@ -39,5 +53,312 @@ pub struct TaskState {
     pub task_manager: String,
     pub instance: Option<String>,
     pub status: TaskStatus,
+    /// Creation timestamp, in milliseconds since the unix epoch.
     pub creation_time: u64,
+    /// Time a worker started the task (ms).
+    #[cfg_attr(feature = "mongodb", serde(default))]
+    pub started_time: Option<u64>,
+    /// Time the task run completed (ms).
+    #[cfg_attr(feature = "mongodb", serde(default))]
+    pub finished_time: Option<u64>,
+    /// Time of the last state change (ms).
+    #[cfg_attr(feature = "mongodb", serde(default))]
+    pub last_update_time: u64,
+    /// Index of the worker running the task.
+    #[cfg_attr(feature = "mongodb", serde(default))]
+    pub worker: Option<usize>,
+    /// Host running the task.
+    #[cfg_attr(feature = "mongodb", serde(default))]
+    pub host: Option<String>,
+    /// Progress reported by the task, in percent.
+    #[cfg_attr(feature = "mongodb", serde(default))]
+    pub progress: Option<u8>,
+    /// Message of the last progress report.
+    #[cfg_attr(feature = "mongodb", serde(default))]
+    pub progress_message: Option<String>,
+    /// Name of the task which submitted this task.
+    #[cfg_attr(feature = "mongodb", serde(default))]
+    pub parent_name: Option<String>,
+    /// Id of the task which submitted this task.
+    #[cfg_attr(feature = "mongodb", serde(default))]
+    pub parent_id: Option<String>,
+    /// Name of the first task of the pipeline this task belongs to.
+    #[cfg_attr(feature = "mongodb", serde(default))]
+    pub root_name: Option<String>,
+    /// Id of the first task of the pipeline this task belongs to.
+    #[cfg_attr(feature = "mongodb", serde(default))]
+    pub root_id: Option<String>,
+    /// Id of the batch this task belongs to.
+    #[cfg_attr(feature = "mongodb", serde(default))]
+    pub batch_id: Option<String>,
+    /// Serialized task, to re-create it after a restart.
+    #[cfg_attr(feature = "mongodb", serde(default))]
+    pub payload: Option<String>,
+    /// Time the lease of the instance which claimed the task expires (ms),
+    /// after which the task can be claimed again.
+    #[cfg_attr(feature = "mongodb", serde(default))]
+    pub lease_expiry_time: Option<u64>,
+    /// Number of times a worker started the task, the last run included.
+    #[cfg_attr(feature = "mongodb", serde(default))]
+    pub attempt: u32,
+}
+
+impl TaskState {
+    /// Create a new pending task state.
+    pub fn new(task: &dyn Task, manager: &str, instance: Option<String>, time: u64) -> Self {
+        Self {
+            id: None,
+            task_id: task.id(),
+            task_name: task.name(),
+            task_manager: manager.to_string(),
+            instance,
+            status: TaskStatus::Pending,
+            creation_time: time,
+            started_time: None,
+            finished_time: None,
+            last_update_time: time,
+            worker: None,
+            host: None,
+            progress: None,
+            progress_message: None,
+            parent_name: None,
+            parent_id: None,
+            root_name: None,
+            root_id: None,
+            batch_id: None,
+            payload: None,
+            lease_expiry_time: None,
+            attempt: 0,
+        }
+    }
+
+    /// Set the initial fields of a new state.
+    pub(crate) fn with_initial(mut self, mut initial: StateUpdate) -> Self {
+        initial.time = self.creation_time;
+        initial.apply(&mut self);
+        self
+    }
+
+    /// Return the fields not part of the task identity, status and creation time,
+    /// as name and value pairs. Unset fields are skipped.
+    pub(crate) fn extra_fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![("last_update_time", self.last_update_time.to_string())];
+        if let Some(time) = self.started_time {
+            fields.push(("started_time", time.to_string()));
+        }
+        if let Some(time) = self.finished_time {
+            fields.push(("finished_time", time.to_string()));
+        }
+        if let Some(worker) = self.worker {
+            fields.push(("worker", worker.to_string()));
+        }
+        if let Some(host) = &self.host {
+            fields.push(("host", host.clone()));
+        }
+        if let Some(progress) = self.progress {
+            fields.push(("progress", progress.to_string()));
+        }
+        if let Some(message) = &self.progress_message {
+            fields.push(("progress_message", message.clone()));
+        }
+        if let Some(value) = &self.parent_name {
+            fields.push(("parent_name", value.clone()));
+        }
+        if let Some(value) = &self.parent_id {
+            fields.push(("parent_id", value.clone()));
+        }
+        if let Some(value) = &self.root_name {
+            fields.push(("root_name", value.clone()));
+        }
+        if let Some(value) = &self.root_id {
+            fields.push(("root_id", value.clone()));
+        }
+        if let Some(value) = &self.batch_id {
+            fields.push(("batch_id", value.clone()));
+        }
+        if let Some(value) = &self.payload {
+            fields.push(("payload", value.clone()));
+        }
+        if let Some(time) = self.lease_expiry_time {
+            fields.push(("lease_expiry_time", time.to_string()));
+        }
+        if self.attempt > 0 {
+            fields.push(("attempt", self.attempt.to_string()));
+        }
+        fields
+    }
+
+    /// Set a field from its name and value, as returned by `extra_fields` or `StateUpdate::fields`.
+    pub(crate) fn set_field(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let number = |value: &str| {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("invalid task state {}: {}", name, value))
+        };
+        match name {
+            "status" => self.status = value.parse()?,
+            "last_update_time" => self.last_update_time = number(value)?,
+            "started_time" => self.started_time = Some(number(value)?),
+            "finished_time" => self.finished_time = Some(number(value)?),
+            "worker" => self.worker = Some(number(value)? as usize),
+            "host" => self.host = Some(value.to_string()),
+            "progress" => self.progress = Some(number(value)?.min(100) as u8),
+            "progress_message" => self.progress_message = Some(value.to_string()),
+            "parent_name" => self.parent_name = Some(value.to_string()),
+            "parent_id" => self.parent_id = Some(value.to_string()),
+            "root_name" => self.root_name = Some(value.to_string()),
+            "root_id" => self.root_id = Some(value.to_string()),
+            "batch_id" => self.batch_id = Some(value.to_string()),
+            "payload" => self.payload = Some(value.to_string()),
+            "lease_expiry_time" => self.lease_expiry_time = Some(number(value)?),
+            "attempt" => self.attempt = number(value)? as u32,
+            _ => return Err(format!("unknown task state field {}", name)),
+        }
+        Ok(())
+    }
+
+    /// Return true if the task can be claimed at the given time (ms):
+    /// it has a payload, and is pending or its lease expired.
+    pub(crate) fn is_claimable(&self, now: u64) -> bool {
+        self.payload.is_some()
+            && match self.status {
+                TaskStatus::Pending => true,
+                TaskStatus::Running => self.lease_expiry_time.is_some_and(|time| time < now),
+            }
+    }
+}
+
+/// Partial update of a task state.
+/// Fields left to None are not changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateUpdate {
+    pub status: Option<TaskStatus>,
+    pub started_time: Option<u64>,
+    pub finished_time: Option<u64>,
+    pub worker: Option<usize>,
+    pub host: Option<String>,
+    pub progress: Option<u8>,
+    pub progress_message: Option<String>,
+    pub parent_name: Option<String>,
+    pub parent_id: Option<String>,
+    pub root_name: Option<String>,
+    pub root_id: Option<String>,
+    pub batch_id: Option<String>,
+    pub payload: Option<String>,
+    pub lease_expiry_time: Option<u64>,
+    pub attempt: Option<u32>,
+    /// Time of the update (ms), written as the state last update time.
+    pub time: u64,
+}
+
+impl StateUpdate {
+    /// Create a status update.
+    pub fn status(status: TaskStatus, time: u64) -> Self {
+        Self {
+            status: Some(status),
+            time,
+            ..Default::default()
+        }
+    }
+
+    /// Apply the update to a state.
+    pub fn apply(&self, state: &mut TaskState) {
+        if let Some(status) = &self.status {
+            state.status = status.clone();
+        }
+        if let Some(time) = self.started_time {
+            state.started_time = Some(time);
+        }
+        if let Some(time) = self.finished_time {
+            state.finished_time = Some(time);
+        }
+        if let Some(worker) = self.worker {
+            state.worker = Some(worker);
+        }
+        if let Some(host) = &self.host {
+            state.host = Some(host.clone());
+        }
+        if let Some(progress) = self.progress {
+            state.progress = Some(progress);
+        }
+        if let Some(message) = &self.progress_message {
+            state.progress_message = Some(message.clone());
+        }
+        if let Some(value) = &self.parent_name {
+            state.parent_name = Some(value.clone());
+        }
+        if let Some(value) = &self.parent_id {
+            state.parent_id = Some(value.clone());
+        }
+        if let Some(value) = &self.root_name {
+            state.root_name = Some(value.clone());
+        }
+        if let Some(value) = &self.root_id {
+            state.root_id = Some(value.clone());
+        }
+        if let Some(value) = &self.batch_id {
+            state.batch_id = Some(value.clone());
+        }
+        if let Some(value) = &self.payload {
+            state.payload = Some(value.clone());
+        }
+        if let Some(time) = self.lease_expiry_time {
+            state.lease_expiry_time = Some(time);
+        }
+        if let Some(attempt) = self.attempt {
+            state.attempt = attempt;
+        }
+        state.last_update_time = self.time;
+    }
+
+    /// Return the updated fields, as name and value pairs.
+    pub(crate) fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![("last_update_time", self.time.to_string())];
+        if let Some(status) = &self.status {
+            fields.push(("status", status.to_string()));
+        }
+        if let Some(time) = self.started_time {
+            fields.push(("started_time", time.to_string()));
+        }
+        if let Some(time) = self.finished_time {
+            fields.push(("finished_time", time.to_string()));
+        }
+        if let Some(worker) = self.worker {
+            fields.push(("worker", worker.to_string()));
+        }
+        if let Some(host) = &self.host {
+            fields.push(("host", host.clone()));
+        }
+        if let Some(progress) = self.progress {
+            fields.push(("progress", progress.to_string()));
+        }
+        if let Some(message) = &self.progress_message {
+            fields.push(("progress_message", message.clone()));
+        }
+        if let Some(value) = &self.parent_name {
+            fields.push(("parent_name", value.clone()));
+        }
+        if let Some(value) = &self.parent_id {
+            fields.push(("parent_id", value.clone()));
+        }
+        if let Some(value) = &self.root_name {
+            fields.push(("root_name", value.clone()));
+        }
+        if let Some(value) = &self.root_id {
+            fields.push(("root_id", value.clone()));
+        }
+        if let Some(value) = &self.batch_id {
+            fields.push(("batch_id", value.clone()));
+        }
+        if let Some(value) = &self.payload {
+            fields.push(("payload", value.clone()));
+        }
+        if let Some(time) = self.lease_expiry_time {
+            fields.push(("lease_expiry_time", time.to_string()));
+        }
+        if let Some(attempt) = self.attempt {
+            fields.push(("attempt", attempt.to_string()));
+        }
+        fields
+    }
 }
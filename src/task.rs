@@ -1,5 +1,9 @@
+use std::{fmt::Display, str::FromStr};
+
 use async_trait::async_trait;
 
+use crate::{context::TaskContext, dedup::DedupPolicy};
+
 /// Task.
 /// Defines a task to run.
 #[async_trait]
@@ -10,5 +14,92 @@ pub trait Task: Send + Sync {
     /// Two tasks with the same name and the same id are considered as equal.
     fn id(&self) -> String;
     /// Task execution.
-    async fn run(&self);
-}
\ No newline at end of file
+    /// The context gives access to the task manager running the task.
+    async fn run(&self, ctx: &TaskContext);
+    /// Return the serialized task, stored with its state,
+    /// so it can be re-created by a [`TaskRegistry`](crate::registry::TaskRegistry) after a restart.
+    /// Tasks without payload (default) are not recovered.
+    fn payload(&self) -> Option<String> {
+        None
+    }
+    /// Return what to do when submitted while a task with the same name and id is pending or running.
+    /// Such submissions are rejected by default.
+    fn dedup_policy(&self) -> DedupPolicy {
+        DedupPolicy::Reject
+    }
+}
+
+/// Outcome of a task run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskOutcome {
+    /// Task run completed.
+    Succeeded,
+    /// Task reported a failure through its context, with a message.
+    Failed(String),
+    /// Task was cancelled while running.
+    Cancelled,
+}
+
+impl TaskOutcome {
+    /// Return true if the task run completed.
+    pub fn is_success(&self) -> bool {
+        matches!(self, TaskOutcome::Succeeded)
+    }
+}
+
+/// Stored form of an outcome: `Succeeded`, `Cancelled`, or `Failed:` followed by the message.
+impl Display for TaskOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaskOutcome::Succeeded => write!(f, "Succeeded"),
+            TaskOutcome::Failed(message) => write!(f, "Failed:{}", message),
+            TaskOutcome::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+impl FromStr for TaskOutcome {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Succeeded" => Ok(TaskOutcome::Succeeded),
+            "Cancelled" => Ok(TaskOutcome::Cancelled),
+            _ => match s.strip_prefix("Failed:") {
+                Some(message) => Ok(TaskOutcome::Failed(message.to_string())),
+                None => Err(format!("unknown task outcome {}", s)),
+            },
+        }
+    }
+}
+
+/// Reference to a task by name and id,
+/// used to write the state of a task no longer (or not yet) available.
+#[derive(Debug, Clone)]
+pub(crate) struct TaskRef {
+    pub name: String,
+    pub id: String,
+}
+
+impl TaskRef {
+    /// Create a reference to a task.
+    pub fn of(task: &dyn Task) -> Self {
+        Self {
+            name: task.name(),
+            id: task.id(),
+        }
+    }
+}
+
+#[async_trait]
+impl Task for TaskRef {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn run(&self, _ctx: &TaskContext) {}
+}
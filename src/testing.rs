@@ -0,0 +1,418 @@
+/*!
+Deterministic test harness.
+
+Helpers to unit test tasks and scheduling without relying on real time.
+Enabled with the `test-util` feature.
+
+[`TestTaskManager`] runs tasks step by step on the calling task (no worker is started),
+stamps states with a [`VirtualClock`], and records every state transition written to its store.
+
+```ignore
+let tm = TestTaskManager::new("manager");
+tm.run(Box::new(MyTask::new("1"))).await;
+tm.clock().advance(Duration::from_secs(10));
+tm.run_until_idle().await;
+assert_eq!(
+    tm.transitions_of(&MyTask::new("1")),
+    vec![
+        TransitionKind::Created,
+        TransitionKind::Status(TaskStatus::Running),
+        TransitionKind::Finished,
+        TransitionKind::Deleted,
+    ]
+);
+```
+*/
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+
+use crate::{
+    batch::BatchHandle,
+    clock::Clock,
+    graph::{GraphError, GraphHandle, TaskGraph},
+    manager::{RunResult, TaskManager},
+    store::{
+        memory::InMemoryTaskStore,
+        state::{StateUpdate, TaskState, TaskStatus},
+        TaskStore, TaskStoreError,
+    },
+    task::{Task, TaskOutcome},
+};
+
+/// Clock only moving when told to.
+/// Clones share the same time.
+#[derive(Clone)]
+pub struct VirtualClock {
+    now: Arc<Mutex<SystemTime>>,
+}
+
+impl VirtualClock {
+    /// Create a new virtual clock, set to the unix epoch.
+    pub fn new() -> Self {
+        Self::at(UNIX_EPOCH)
+    }
+
+    /// Create a new virtual clock, set to the given time.
+    pub fn at(time: SystemTime) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(time)),
+        }
+    }
+
+    /// Move the clock forward.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+
+    /// Set the clock time.
+    pub fn set(&self, time: SystemTime) {
+        *self.now.lock().unwrap() = time;
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// Kind of task state transition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransitionKind {
+    /// State was created.
+    Created,
+    /// Status was updated.
+    Status(TaskStatus),
+    /// Task run completed.
+    Finished,
+    /// Other state fields were updated.
+    Updated,
+    /// State was deleted.
+    Deleted,
+}
+
+/// Task state transition written to a store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    pub task_name: String,
+    pub task_id: String,
+    pub kind: TransitionKind,
+    /// Clock time of the transition.
+    pub time: SystemTime,
+}
+
+/// Recording task store.
+/// Decorates a task store to record the state transitions successfully written to it.
+#[derive(Clone)]
+pub struct RecordingTaskStore<S>
+where
+    S: TaskStore,
+{
+    inner: S,
+    clock: Arc<dyn Clock>,
+    transitions: Arc<Mutex<Vec<Transition>>>,
+}
+
+impl<S: TaskStore> RecordingTaskStore<S> {
+    /// Create a new recording task store, decorating the given store.
+    /// Transitions are timed using the given clock.
+    pub fn new(inner: S, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            inner,
+            clock,
+            transitions: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Return the inner store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Return the recorded transitions, in order.
+    pub fn transitions(&self) -> Vec<Transition> {
+        self.transitions.lock().unwrap().clone()
+    }
+
+    fn record(&self, task: &dyn Task, kind: TransitionKind) {
+        self.transitions.lock().unwrap().push(Transition {
+            task_name: task.name(),
+            task_id: task.id(),
+            kind,
+            time: self.clock.now(),
+        });
+    }
+}
+
+#[async_trait]
+impl<S: TaskStore> TaskStore for RecordingTaskStore<S> {
+    fn manager_name(&self) -> String {
+        self.inner.manager_name()
+    }
+
+    async fn init(&self) -> Result<(), TaskStoreError> {
+        self.inner.init().await
+    }
+
+    async fn save_state(&self, task: &dyn Task) -> Result<TaskState, TaskStoreError> {
+        let state = self.inner.save_state(task).await?;
+        self.record(task, TransitionKind::Created);
+        Ok(state)
+    }
+
+    async fn try_insert_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        self.try_insert_state_with(task, StateUpdate::default()).await
+    }
+
+    async fn try_insert_state_with(
+        &self,
+        task: &dyn Task,
+        initial: StateUpdate,
+    ) -> Result<Option<TaskState>, TaskStoreError> {
+        let state = self.inner.try_insert_state_with(task, initial).await?;
+        if state.is_some() {
+            self.record(task, TransitionKind::Created);
+        }
+        Ok(state)
+    }
+
+    async fn delete_state(&self, task: &dyn Task) -> Result<(), TaskStoreError> {
+        self.inner.delete_state(task).await?;
+        self.record(task, TransitionKind::Deleted);
+        Ok(())
+    }
+
+    async fn get_state(&self, task: &dyn Task) -> Result<Option<TaskState>, TaskStoreError> {
+        self.inner.get_state(task).await
+    }
+
+    async fn count_tasks(&self) -> Result<usize, TaskStoreError> {
+        self.inner.count_tasks().await
+    }
+
+    async fn update_status(
+        &self,
+        task: &dyn Task,
+        status: TaskStatus,
+    ) -> Result<(), TaskStoreError> {
+        self.inner.update_status(task, status.clone()).await?;
+        self.record(task, TransitionKind::Status(status));
+        Ok(())
+    }
+
+    async fn update_state(&self, task: &dyn Task, update: StateUpdate) -> Result<(), TaskStoreError> {
+        let kind = match (&update.status, update.finished_time) {
+            (Some(status), _) => TransitionKind::Status(status.clone()),
+            (None, Some(_)) => TransitionKind::Finished,
+            (None, None) => TransitionKind::Updated,
+        };
+        self.inner.update_state(task, update).await?;
+        self.record(task, kind);
+        Ok(())
+    }
+
+    async fn update_pending_state(
+        &self,
+        task: &dyn Task,
+        update: StateUpdate,
+    ) -> Result<bool, TaskStoreError> {
+        let updated = self.inner.update_pending_state(task, update).await?;
+        if updated {
+            self.record(task, TransitionKind::Updated);
+        }
+        Ok(updated)
+    }
+
+    async fn clear(&self) -> Result<(), TaskStoreError> {
+        self.inner.clear().await
+    }
+
+    async fn get_all_states(&self) -> Result<Vec<TaskState>, TaskStoreError> {
+        self.inner.get_all_states().await
+    }
+
+    async fn claim_pending(
+        &self,
+        names: &[String],
+        lease: Duration,
+    ) -> Result<Option<TaskState>, TaskStoreError> {
+        let state = self.inner.claim_pending(names, lease).await?;
+        if let Some(state) = &state {
+            self.transitions.lock().unwrap().push(Transition {
+                task_name: state.task_name.clone(),
+                task_id: state.task_id.clone(),
+                kind: TransitionKind::Status(TaskStatus::Running),
+                time: self.clock.now(),
+            });
+        }
+        Ok(state)
+    }
+
+    async fn renew_lease(&self, task: &dyn Task, lease: Duration) -> Result<bool, TaskStoreError> {
+        self.inner.renew_lease(task, lease).await
+    }
+
+    async fn heartbeat(&self) -> Result<(), TaskStoreError> {
+        self.inner.heartbeat().await
+    }
+
+    async fn dead_instances(&self, timeout: Duration) -> Result<Vec<String>, TaskStoreError> {
+        self.inner.dead_instances(timeout).await
+    }
+
+    async fn adopt_instance(&self, instance: &str) -> Result<Vec<TaskState>, TaskStoreError> {
+        let states = self.inner.adopt_instance(instance).await?;
+        for state in &states {
+            self.transitions.lock().unwrap().push(Transition {
+                task_name: state.task_name.clone(),
+                task_id: state.task_id.clone(),
+                kind: TransitionKind::Status(TaskStatus::Pending),
+                time: self.clock.now(),
+            });
+        }
+        Ok(states)
+    }
+
+    async fn acquire_leadership(&self, lease: Duration) -> Result<bool, TaskStoreError> {
+        self.inner.acquire_leadership(lease).await
+    }
+
+    async fn resign_leadership(&self) -> Result<(), TaskStoreError> {
+        self.inner.resign_leadership().await
+    }
+
+    async fn try_lock(&self, name: &str, owner: &str, ttl: Duration) -> Result<bool, TaskStoreError> {
+        self.inner.try_lock(name, owner, ttl).await
+    }
+
+    async fn unlock(&self, name: &str, owner: &str) -> Result<(), TaskStoreError> {
+        self.inner.unlock(name, owner).await
+    }
+
+    async fn record_outcome(
+        &self,
+        task: &dyn Task,
+        outcome: &TaskOutcome,
+        ttl: Duration,
+    ) -> Result<(), TaskStoreError> {
+        self.inner.record_outcome(task, outcome, ttl).await
+    }
+
+    async fn get_outcome(&self, task: &dyn Task) -> Result<Option<TaskOutcome>, TaskStoreError> {
+        self.inner.get_outcome(task).await
+    }
+}
+
+/// Task manager for tests.
+/// Uses an in memory store stamped by a virtual clock, and runs tasks step by step
+/// on the calling task instead of worker threads.
+pub struct TestTaskManager {
+    manager: TaskManager<RecordingTaskStore<InMemoryTaskStore>>,
+    store: RecordingTaskStore<InMemoryTaskStore>,
+    clock: VirtualClock,
+}
+
+impl TestTaskManager {
+    /// Create a new test task manager.
+    pub fn new(name: &str) -> Self {
+        let clock = VirtualClock::new();
+        let store = RecordingTaskStore::new(
+            InMemoryTaskStore::new(name).with_clock(Arc::new(clock.clone())),
+            Arc::new(clock.clone()),
+        );
+        Self {
+            manager: TaskManager::new(store.clone(), 1).with_clock(Arc::new(clock.clone())),
+            store,
+            clock,
+        }
+    }
+
+    /// Return the virtual clock.
+    pub fn clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+
+    /// Return the task store.
+    pub fn store(&self) -> &RecordingTaskStore<InMemoryTaskStore> {
+        &self.store
+    }
+
+    /// Submit a task, see [`TaskManager::run`].
+    /// The task is not run until stepped.
+    pub async fn run(&self, task: Box<dyn Task + Send + Sync>) -> RunResult {
+        self.manager.run(task).await
+    }
+
+    /// Submit a batch of tasks, see [`TaskManager::run_batch`].
+    /// Tasks are not run until stepped.
+    pub async fn run_batch(&self, tasks: Vec<Box<dyn Task + Send + Sync>>) -> BatchHandle {
+        self.manager.run_batch(tasks).await
+    }
+
+    /// Submit a batch of tasks with the given id, see [`TaskManager::run_batch_with_id`].
+    pub async fn run_batch_with_id(&self, id: &str, tasks: Vec<Box<dyn Task + Send + Sync>>) -> BatchHandle {
+        self.manager.run_batch_with_id(id, tasks).await
+    }
+
+    /// Submit a task graph, see [`TaskManager::run_graph`].
+    /// Tasks are not run until stepped.
+    pub async fn run_graph(&self, graph: TaskGraph) -> Result<GraphHandle, GraphError> {
+        self.manager.run_graph(graph).await
+    }
+
+    /// Run the next queued task to completion.
+    /// Return false if there was no queued task.
+    pub async fn step(&self) -> bool {
+        self.manager.step().await
+    }
+
+    /// Run queued tasks until the queue is empty, including tasks queued meanwhile.
+    /// Return the number of tasks run.
+    pub async fn run_until_idle(&self) -> usize {
+        let mut count = 0;
+        while self.step().await {
+            count += 1;
+        }
+        count
+    }
+
+    /// Stop the task manager, see [`TaskManager::stop`].
+    pub async fn stop(&self) {
+        self.manager.stop().await;
+    }
+
+    /// Get task manager state.
+    pub async fn get_state(&self) -> Vec<TaskState> {
+        self.manager.get_state().await
+    }
+
+    /// Get the states of a pipeline, see [`TaskManager::pipeline_states`].
+    pub async fn pipeline_states(&self, root: &dyn Task) -> Vec<TaskState> {
+        self.manager.pipeline_states(root).await
+    }
+
+    /// Get the states of a batch, see [`TaskManager::batch_states`].
+    pub async fn batch_states(&self, id: &str) -> Vec<TaskState> {
+        self.manager.batch_states(id).await
+    }
+
+    /// Return all the recorded state transitions, in order.
+    pub fn transitions(&self) -> Vec<Transition> {
+        self.store.transitions()
+    }
+
+    /// Return the recorded state transitions of a task, in order.
+    pub fn transitions_of(&self, task: &dyn Task) -> Vec<TransitionKind> {
+        self.store
+            .transitions()
+            .into_iter()
+            .filter(|t| t.task_name == task.name() && t.task_id == task.id())
+            .map(|t| t.kind)
+            .collect()
+    }
+}
@@ -0,0 +1,358 @@
+use std::{
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+use crate::{
+    context::TaskContext,
+    manager::RunResult,
+    store::{
+        memory::InMemoryTaskStore,
+        state::{TaskState, TaskStatus},
+        TaskStore,
+    },
+    task::Task,
+    testing::{RecordingTaskStore, TestTaskManager, TransitionKind, VirtualClock},
+};
+
+struct TestTask {
+    pub id: String,
+    pub results: Arc<RwLock<Vec<String>>>,
+}
+
+impl TestTask {
+    fn new(id: &str, results: &Arc<RwLock<Vec<String>>>) -> Box<Self> {
+        Box::new(Self {
+            id: id.to_string(),
+            results: results.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Task for TestTask {
+    fn name(&self) -> String {
+        "test_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn run(&self, _ctx: &TaskContext) {
+        self.results.write().await.push(self.id.clone());
+    }
+}
+
+#[tokio::test]
+async fn step() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let tm = TestTaskManager::new("manager");
+    assert!(tm.run(TestTask::new("1", &results)).await.is_accepted());
+    assert!(tm.run(TestTask::new("2", &results)).await.is_accepted());
+    assert!(results.read().await.is_empty());
+
+    assert!(tm.step().await);
+    assert_eq!(*results.read().await, vec!["1"]);
+    assert_eq!(tm.get_state().await.len(), 1);
+
+    assert!(tm.step().await);
+    assert!(!tm.step().await);
+    assert_eq!(*results.read().await, vec!["1", "2"]);
+    assert!(tm.get_state().await.is_empty());
+}
+
+#[tokio::test]
+async fn transitions() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let tm = TestTaskManager::new("manager");
+    tm.run(TestTask::new("1", &results)).await;
+    tm.run(TestTask::new("2", &results)).await;
+    assert!(matches!(
+        tm.run(TestTask::new("1", &results)).await,
+        RunResult::Duplicate
+    ));
+    assert_eq!(tm.run_until_idle().await, 2);
+
+    let transitions: Vec<_> = tm
+        .transitions()
+        .into_iter()
+        .map(|t| (t.task_id, t.kind))
+        .collect();
+    assert_eq!(
+        transitions,
+        vec![
+            ("1".to_string(), TransitionKind::Created),
+            ("2".to_string(), TransitionKind::Created),
+            ("1".to_string(), TransitionKind::Status(TaskStatus::Running)),
+            ("1".to_string(), TransitionKind::Finished),
+            ("1".to_string(), TransitionKind::Deleted),
+            ("2".to_string(), TransitionKind::Status(TaskStatus::Running)),
+            ("2".to_string(), TransitionKind::Finished),
+            ("2".to_string(), TransitionKind::Deleted),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn virtual_clock() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let tm = TestTaskManager::new("manager");
+    tm.clock().advance(Duration::from_secs(100));
+    tm.run(TestTask::new("1", &results)).await;
+    tm.clock().advance(Duration::from_secs(20));
+    tm.step().await;
+
+    let states = tm.store().inner().get_all_states().await.unwrap();
+    assert!(states.is_empty());
+    let transitions = tm.transitions();
+    assert_eq!(
+        transitions[0].time.duration_since(UNIX_EPOCH).unwrap(),
+        Duration::from_secs(100)
+    );
+    assert_eq!(
+        transitions[1].time.duration_since(transitions[0].time).unwrap(),
+        Duration::from_secs(20)
+    );
+}
+
+#[tokio::test]
+async fn stamped_by_clock() {
+    let clock = VirtualClock::new();
+    clock.advance(Duration::from_millis(42_500));
+    let store = InMemoryTaskStore::new("manager").with_clock(Arc::new(clock.clone()));
+    let state = store
+        .save_state(TestTask::new("1", &Arc::new(RwLock::new(vec![]))).as_ref())
+        .await
+        .unwrap();
+    assert_eq!(state.creation_time, 42_500);
+}
+
+#[tokio::test]
+async fn stop() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let tm = TestTaskManager::new("manager");
+    tm.run(TestTask::new("1", &results)).await;
+    tm.stop().await;
+    assert!(matches!(
+        tm.run(TestTask::new("2", &results)).await,
+        RunResult::ManagerStopped
+    ));
+    assert_eq!(tm.run_until_idle().await, 1);
+    assert_eq!(
+        tm.transitions_of(TestTask::new("1", &results).as_ref()),
+        vec![
+            TransitionKind::Created,
+            TransitionKind::Status(TaskStatus::Running),
+            TransitionKind::Finished,
+            TransitionKind::Deleted
+        ]
+    );
+}
+
+/// Task capturing its own state while running.
+struct TimedTask {
+    clock: VirtualClock,
+    store: RecordingTaskStore<InMemoryTaskStore>,
+    state: Arc<RwLock<Option<TaskState>>>,
+}
+
+#[async_trait]
+impl Task for TimedTask {
+    fn name(&self) -> String {
+        "timed_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        "1".to_string()
+    }
+
+    async fn run(&self, _ctx: &TaskContext) {
+        *self.state.write().await = self.store.get_state(self).await.unwrap();
+        self.clock.advance(Duration::from_millis(250));
+    }
+}
+
+#[tokio::test]
+async fn timing_fields() {
+    let tm = TestTaskManager::new("manager");
+    let state = Arc::new(RwLock::new(None));
+    tm.clock().advance(Duration::from_millis(1000));
+    tm.run(Box::new(TimedTask {
+        clock: tm.clock().clone(),
+        store: tm.store().clone(),
+        state: state.clone(),
+    }))
+    .await;
+    tm.clock().advance(Duration::from_millis(500));
+    tm.step().await;
+
+    let state = state.read().await.clone().unwrap();
+    assert_eq!(state.status, TaskStatus::Running);
+    assert_eq!(state.creation_time, 1000);
+    assert_eq!(state.started_time, Some(1500));
+    assert_eq!(state.last_update_time, 1500);
+    assert_eq!(state.finished_time, None);
+    assert_eq!(state.worker, Some(0));
+    assert!(state.host.is_some());
+
+    let finished = tm
+        .transitions()
+        .into_iter()
+        .find(|t| t.kind == TransitionKind::Finished)
+        .unwrap();
+    assert_eq!(
+        finished.time.duration_since(UNIX_EPOCH).unwrap(),
+        Duration::from_millis(1750)
+    );
+}
+
+/// Task reporting its progress, and capturing its state after each report.
+struct ProgressTask {
+    clock: VirtualClock,
+    store: RecordingTaskStore<InMemoryTaskStore>,
+    states: Arc<RwLock<Vec<TaskState>>>,
+}
+
+#[async_trait]
+impl Task for ProgressTask {
+    fn name(&self) -> String {
+        "progress_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        "1".to_string()
+    }
+
+    async fn run(&self, ctx: &TaskContext) {
+        for (percent, delay) in [(10, 0), (20, 100), (60, 1000)] {
+            self.clock.advance(Duration::from_millis(delay));
+            ctx.report_progress(percent, &format!("{}%", percent)).await;
+            let state = self.store.get_state(self).await.unwrap().unwrap();
+            self.states.write().await.push(state);
+        }
+    }
+}
+
+#[tokio::test]
+async fn report_progress() {
+    let tm = TestTaskManager::new("manager");
+    let states = Arc::new(RwLock::new(vec![]));
+    tm.run(Box::new(ProgressTask {
+        clock: tm.clock().clone(),
+        store: tm.store().clone(),
+        states: states.clone(),
+    }))
+    .await;
+    tm.step().await;
+
+    // Second report is throttled
+    let progress: Vec<_> = states.read().await.iter().map(|s| s.progress).collect();
+    assert_eq!(progress, vec![Some(10), Some(10), Some(60)]);
+    let state = &states.read().await[2];
+    assert_eq!(state.progress_message.as_deref(), Some("60%"));
+    assert_eq!(state.last_update_time, 1100);
+    assert_eq!(
+        tm.transitions()
+            .iter()
+            .filter(|t| t.kind == TransitionKind::Updated)
+            .count(),
+        2
+    );
+}
+
+/// Task submitting a follow-up task.
+struct ParentTask {
+    pub results: Arc<RwLock<Vec<String>>>,
+}
+
+#[async_trait]
+impl Task for ParentTask {
+    fn name(&self) -> String {
+        "parent_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        "parent".to_string()
+    }
+
+    async fn run(&self, ctx: &TaskContext) {
+        let result = ctx.submit(TestTask::new("child", &self.results)).await;
+        assert!(result.is_accepted());
+        self.results.write().await.push("parent".to_string());
+    }
+}
+
+#[tokio::test]
+async fn submit_follow_up() {
+    let results = Arc::new(RwLock::new(vec![]));
+    let tm = TestTaskManager::new("manager");
+    tm.run(Box::new(ParentTask {
+        results: results.clone(),
+    }))
+    .await;
+    assert_eq!(tm.run_until_idle().await, 2);
+    assert_eq!(*results.read().await, vec!["parent", "child"]);
+}
+
+/// Pipeline stage, submitting the next stage until the last one.
+struct StageTask {
+    pub stage: usize,
+}
+
+#[async_trait]
+impl Task for StageTask {
+    fn name(&self) -> String {
+        "stage_task".to_string()
+    }
+
+    fn id(&self) -> String {
+        self.stage.to_string()
+    }
+
+    async fn run(&self, ctx: &TaskContext) {
+        if self.stage < 3 {
+            ctx.submit(Box::new(StageTask {
+                stage: self.stage + 1,
+            }))
+            .await;
+        }
+    }
+}
+
+#[tokio::test]
+async fn follow_up_lineage() {
+    let tm = TestTaskManager::new("manager");
+    let root = StageTask { stage: 1 };
+    tm.run(Box::new(StageTask { stage: 1 })).await;
+    let states = tm.pipeline_states(&root).await;
+    assert_eq!(states.len(), 1);
+    assert!(states[0].parent_name.is_none());
+    assert!(states[0].root_name.is_none());
+
+    // Second stage: child of the root
+    tm.step().await;
+    let states = tm.pipeline_states(&root).await;
+    assert_eq!(states.len(), 1);
+    assert_eq!(states[0].task_id, "2");
+    assert_eq!(states[0].parent_name.as_deref(), Some("stage_task"));
+    assert_eq!(states[0].parent_id.as_deref(), Some("1"));
+    assert_eq!(states[0].root_name.as_deref(), Some("stage_task"));
+    assert_eq!(states[0].root_id.as_deref(), Some("1"));
+
+    // Third stage: grandchild, still in the root pipeline
+    tm.step().await;
+    let states = tm.pipeline_states(&root).await;
+    assert_eq!(states.len(), 1);
+    assert_eq!(states[0].task_id, "3");
+    assert_eq!(states[0].parent_id.as_deref(), Some("2"));
+    assert_eq!(states[0].root_id.as_deref(), Some("1"));
+    assert!(tm.pipeline_states(&StageTask { stage: 2 }).await.is_empty());
+
+    // Pipeline finished
+    tm.step().await;
+    assert!(tm.pipeline_states(&root).await.is_empty());
+}
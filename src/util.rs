@@ -1,9 +1,11 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 
-/// Get now timestamp in seconds.
-pub fn now_secs() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs()
-}
\ No newline at end of file
+/// Get now timestamp in milliseconds.
+pub fn now_millis() -> u64 {
+    to_millis(SystemTime::now())
+}
+
+/// Get timestamp in milliseconds of the given time.
+pub fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
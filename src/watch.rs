@@ -0,0 +1,70 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use crate::task::{Task, TaskOutcome, TaskRef};
+
+/// Watches the runs of some tasks.
+#[async_trait]
+pub(crate) trait Watcher: Send + Sync {
+    /// Called once the task is marked as running.
+    async fn started(&self, _task: &TaskRef) {}
+
+    /// Called once the task run is over and its state is cleared.
+    async fn finished(&self, task: &TaskRef, outcome: &TaskOutcome);
+}
+
+/// Watchers by task name and id.
+type WatcherMap = HashMap<(String, String), Vec<Arc<dyn Watcher>>>;
+
+/// Watchers of the next run of each task.
+#[derive(Default)]
+pub(crate) struct Watchers {
+    watchers: Mutex<WatcherMap>,
+}
+
+impl Watchers {
+    /// Watch the next run of a task.
+    pub async fn register(&self, task: &dyn Task, watcher: Arc<dyn Watcher>) {
+        self.watchers
+            .lock()
+            .await
+            .entry((task.name(), task.id()))
+            .or_default()
+            .push(watcher);
+    }
+
+    /// Cancel the last registration of a watcher for a task.
+    pub async fn unregister(&self, task: &dyn Task, watcher: &Arc<dyn Watcher>) {
+        let mut watchers = self.watchers.lock().await;
+        let key = (task.name(), task.id());
+        if let Some(list) = watchers.get_mut(&key) {
+            if let Some(index) = list.iter().rposition(|w| Arc::ptr_eq(w, watcher)) {
+                list.remove(index);
+            }
+            if list.is_empty() {
+                watchers.remove(&key);
+            }
+        }
+    }
+
+    /// Notify the watchers of a task run start.
+    pub async fn started(&self, task: &dyn Task) {
+        let list = self.watchers.lock().await.get(&(task.name(), task.id())).cloned();
+        let task = TaskRef::of(task);
+        for watcher in list.unwrap_or_default() {
+            watcher.started(&task).await;
+        }
+    }
+
+    /// Notify (and remove) the watchers of a task run.
+    pub async fn finished(&self, task: &dyn Task, outcome: &TaskOutcome) {
+        // Watchers are called without the lock, they may watch other tasks
+        let list = self.watchers.lock().await.remove(&(task.name(), task.id()));
+        let task = TaskRef::of(task);
+        for watcher in list.unwrap_or_default() {
+            watcher.finished(&task, outcome).await;
+        }
+    }
+}